@@ -0,0 +1,32 @@
+#!/bin/sh
+# Adds the SHA-256 digests of the official release archives of the given protoc versions to
+# src/checksums.txt, the table that ProtocFetcher::strict_checksums verifies against. Run this
+# before releasing the crate, for the releases published since the last one:
+#
+#     scripts/update-checksums.sh 31.1 32.0
+set -eu
+
+archive=$(mktemp)
+trap 'rm -f "$archive"' EXIT
+cd "$(dirname "$0")/.."
+base_url=https://github.com/protocolbuffers/protobuf/releases/download
+platforms="linux-aarch_64 linux-ppcle_64 linux-s390_64 linux-x86_32 linux-x86_64 osx-aarch_64
+osx-universal_binary osx-x86_64 win32 win64"
+
+for version in "$@"; do
+    # Release candidates are tagged e.g. v32.0-rc1, but their assets are named 32.0-rc-1.
+    asset_version=$(echo "$version" | sed 's/-rc\([0-9]\)/-rc-\1/')
+    for platform in $platforms; do
+        if grep -q "^$version $platform " src/checksums.txt; then
+            continue
+        fi
+        url="$base_url/v$version/protoc-$asset_version-$platform.zip"
+        # Not every release has every platform, e.g. osx-universal_binary is fairly recent.
+        if curl -fsSL -o "$archive" "$url"; then
+            digest=$(sha256sum "$archive")
+            echo "$version $platform ${digest%% *}" >> src/checksums.txt
+        else
+            echo "Skipping $url" >&2
+        fi
+    done
+done
@@ -0,0 +1,107 @@
+//! Caching the GitHub API responses used to resolve versions on disk, so that warm builds don't
+//! ask GitHub again (or only ask whether anything changed); see
+//! [`ProtocFetcher::api_cache_ttl`].
+
+use crate::digest;
+use crate::fetcher::ProtocFetcher;
+use crate::http::{self, Response};
+use crate::install::INSTALLS_DIR;
+use crate::logging;
+use anyhow::Context;
+use serde_json::{json, Value};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::SystemTime;
+
+/// A cached response. The time it was last fetched or revalidated is the file's mtime.
+struct Entry {
+    etag: Option<String>,
+    body: String,
+    fetched: SystemTime,
+}
+
+/// Returns the body of the response to a GET request for `url`, from the cache if the cached
+/// response is fresh enough (or the fetcher is offline), and otherwise from the server, which is
+/// asked whether the cached response is still current if it has an ETag.
+pub(crate) fn get(fetcher: &ProtocFetcher, url: &str) -> anyhow::Result<String> {
+    let Some(path) = entry_path(fetcher, url) else {
+        return read_body(http::get(fetcher, url)?, url);
+    };
+    let cached = read_entry(&path);
+    if let Some(entry) = &cached {
+        let age = entry.fetched.elapsed().unwrap_or_default();
+        if fetcher.offline || age < fetcher.api_cache_ttl {
+            logging::debug!("Using the response for {url} cached {age:?} ago.");
+            return Ok(entry.body.clone());
+        }
+        logging::debug!(
+            "The response for {url} cached {age:?} ago is older than the TTL of {:?}.",
+            fetcher.api_cache_ttl
+        );
+    }
+
+    let response = match cached.as_ref().and_then(|entry| entry.etag.as_deref()) {
+        Some(etag) => http::get_if_none_match(fetcher, url, etag)?,
+        None => http::get(fetcher, url)?,
+    };
+    if let Some(entry) = cached.filter(|_| response.not_modified) {
+        logging::debug!("The cached response for {url} is still current.");
+        let result = File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(SystemTime::now()));
+        if let Err(err) = result {
+            logging::debug!("Failed to update the timestamp of {path:?}: {err}");
+        }
+        return Ok(entry.body);
+    }
+    let etag = response.etag.clone();
+    let body = read_body(response, url)?;
+    if let Err(err) = write_entry(&path, url, etag.as_deref(), &body) {
+        // The cache is only an optimization, e.g. the install directory may be read-only.
+        logging::debug!("Failed to cache the response for {url} in {path:?}: {err:#}");
+    }
+    Ok(body)
+}
+
+/// Returns the file the response for `url` is cached in, next to the installs, or `None` if there
+/// is no install directory.
+fn entry_path(fetcher: &ProtocFetcher, url: &str) -> Option<PathBuf> {
+    let install_dir = fetcher.resolve_install_dir().ok()?;
+    let name = format!("api-{}.json", &digest::sha256_bytes(url.as_bytes())[..16]);
+    Some(install_dir.join(INSTALLS_DIR).join(name))
+}
+
+fn read_entry(path: &Path) -> Option<Entry> {
+    let fetched = fs::metadata(path).ok()?.modified().ok()?;
+    let entry: Value = serde_json::from_str(&fs::read_to_string(path).ok()?).ok()?;
+    Some(Entry {
+        etag: entry["etag"].as_str().map(str::to_string),
+        body: entry["body"].as_str()?.to_string(),
+        fetched,
+    })
+}
+
+/// Writes the entry to a file of its own first and then moves it into place, so that concurrent
+/// builds never read a half-written entry.
+fn write_entry(path: &Path, url: &str, etag: Option<&str>, body: &str) -> anyhow::Result<()> {
+    let dir = path.parent().context("Cache entry has no directory")?;
+    fs::create_dir_all(dir)?;
+    let mut part_path = path.as_os_str().to_owned();
+    part_path.push(format!(".{}.part", process::id()));
+    let part_path = PathBuf::from(part_path);
+    let entry = json!({ "url": url, "etag": etag, "body": body });
+    fs::write(&part_path, entry.to_string())?;
+    fs::rename(&part_path, path)?;
+    Ok(())
+}
+
+fn read_body(mut response: Response, url: &str) -> anyhow::Result<String> {
+    let mut body = String::new();
+    response
+        .read_to_string(&mut body)
+        .with_context(|| format!("Failed to read {url}"))?;
+    Ok(body)
+}
@@ -0,0 +1,190 @@
+//! The content-addressed store of release archives set with [`ProtocFetcher::archive_store`],
+//! which keeps downloads around independently of the installs extracted from them.
+//!
+//! The store holds each archive once, as `sha256/<digest>`, and remembers which archive each
+//! download URL yielded as `urls/<digest of the URL>`, which contains the digest of the archive
+//! and the URL. That way an archive is found even without a [`sha256`](ProtocFetcher::sha256)
+//! digest to look it up by. Everything is written to a temporary file first and then renamed into
+//! place, so concurrent builds sharing a store never see partial files.
+//!
+//! The store is a cache: a failure to use it is logged, and the archive is downloaded as if there
+//! were no store.
+
+use crate::digest;
+use crate::fetcher::ProtocFetcher;
+use crate::install;
+use crate::logging;
+use anyhow::Context;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::SystemTime;
+
+const ARCHIVES_DIR: &str = "sha256";
+const URLS_DIR: &str = "urls";
+
+/// Puts the release archive named `release_name` at `archive_path`, like
+/// [`install::download_archive`], but taking it from the store in `store_dir` if it is there, and
+/// adding it to the store if it has to be downloaded. Returns the URL the archive was downloaded
+/// from, now or when it was added to the store.
+pub(crate) fn obtain(
+    fetcher: &ProtocFetcher,
+    store_dir: &Path,
+    archive_path: &Path,
+    release_name: &str,
+) -> anyhow::Result<String> {
+    let expected_sha256 = install::expected_sha256(fetcher)?;
+    let urls = install::protoc_release_archive_urls(fetcher, release_name)?;
+    let taken = take(
+        fetcher,
+        store_dir,
+        &urls[0],
+        expected_sha256.as_deref(),
+        archive_path,
+    );
+    match taken {
+        Ok(Some(url)) => {
+            logging::info!(
+                "Using the archive of {release_name} in the archive store {store_dir:?}."
+            );
+            return Ok(url);
+        }
+        Ok(None) => logging::debug!("{release_name} is not in the archive store {store_dir:?}."),
+        Err(err) => logging::warn!("Ignoring the archive store {store_dir:?}: {err:#}"),
+    }
+
+    let url = install::download_archive(
+        fetcher,
+        archive_path,
+        release_name,
+        expected_sha256.as_deref(),
+    )?;
+    if let Err(err) = insert(fetcher, store_dir, &urls[0], &url, archive_path) {
+        logging::warn!("Failed to add {release_name} to the archive store {store_dir:?}: {err:#}");
+    }
+    Ok(url)
+}
+
+/// Copies the archive that downloading `primary_url` yielded (or, if known, the archive with
+/// digest `expected_sha256`) from the store to `archive_path`. Returns `None` if the store doesn't
+/// have it, and otherwise the URL the archive was downloaded from, as recorded, or `primary_url`.
+fn take(
+    fetcher: &ProtocFetcher,
+    store_dir: &Path,
+    primary_url: &str,
+    expected_sha256: Option<&str>,
+    archive_path: &Path,
+) -> anyhow::Result<Option<String>> {
+    let url_key = digest::sha256_bytes(primary_url.as_bytes());
+    let record = match fs::read_to_string(store_dir.join(URLS_DIR).join(url_key)) {
+        Ok(record) => Some(record),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err).context("Failed to read its index"),
+    };
+    let recorded = record
+        .as_deref()
+        .and_then(|record| record.trim().split_once(' '));
+    let (sha256, url) = match (expected_sha256, recorded) {
+        (Some(expected), Some((sha256, url))) if sha256.eq_ignore_ascii_case(expected) => {
+            (expected.to_ascii_lowercase(), Some(url.to_string()))
+        }
+        (Some(expected), _) => (expected.to_ascii_lowercase(), None),
+        (None, Some((sha256, url))) => (sha256.to_string(), Some(url.to_string())),
+        (None, None) => return Ok(None),
+    };
+    let stored = store_dir.join(ARCHIVES_DIR).join(&sha256);
+    if !stored.is_file() {
+        return Ok(None);
+    }
+
+    let part_path = part_path(archive_path);
+    fs::copy(&stored, &part_path).with_context(|| format!("Failed to copy {stored:?}"))?;
+    let actual = digest::sha256_file(&part_path, fetcher.buffer_size)?;
+    if actual != sha256 {
+        logging::warn!("Removing {stored:?} from the archive store, as its digest is {actual}.");
+        fs::remove_file(&part_path)?;
+        fs::remove_file(&stored)?;
+        return Ok(None);
+    }
+    fs::rename(&part_path, archive_path)?;
+    // Eviction goes by the modification time, so this keeps archives in use in the store.
+    let _ = File::options()
+        .write(true)
+        .open(&stored)
+        .and_then(|file| file.set_modified(SystemTime::now()));
+    Ok(Some(url.unwrap_or_else(|| primary_url.to_string())))
+}
+
+/// Adds the archive at `archive_path`, downloaded from `url` when downloading `primary_url` (which
+/// differs if a mirror served it), to the store, and then evicts archives to keep the store within
+/// the fetcher's [`archive_store_limit`](ProtocFetcher::archive_store_limit).
+fn insert(
+    fetcher: &ProtocFetcher,
+    store_dir: &Path,
+    primary_url: &str,
+    url: &str,
+    archive_path: &Path,
+) -> anyhow::Result<()> {
+    let sha256 = digest::sha256_file(archive_path, fetcher.buffer_size)?;
+    let archives_dir = store_dir.join(ARCHIVES_DIR);
+    let urls_dir = store_dir.join(URLS_DIR);
+    fs::create_dir_all(&archives_dir)?;
+    fs::create_dir_all(&urls_dir)?;
+
+    let stored = archives_dir.join(&sha256);
+    if !stored.is_file() {
+        let part_path = part_path(&stored);
+        fs::copy(archive_path, &part_path)?;
+        fs::rename(&part_path, &stored)?;
+    }
+    let record = urls_dir.join(digest::sha256_bytes(primary_url.as_bytes()));
+    let part_path = part_path(&record);
+    fs::write(&part_path, format!("{sha256} {url}\n"))?;
+    fs::rename(&part_path, &record)?;
+    logging::debug!("Added {archive_path:?} to the archive store as {stored:?}.");
+
+    evict(store_dir, fetcher.archive_store_limit, &stored)
+}
+
+/// Removes the least recently used archives from the store until the rest take up at most `limit`
+/// bytes, keeping `keep` (the archive just added) regardless. Records of URLs whose archive was
+/// evicted are left behind; they are harmless, and overwritten when the URL is downloaded again.
+fn evict(store_dir: &Path, limit: u64, keep: &Path) -> anyhow::Result<()> {
+    let mut archives: Vec<(SystemTime, u64, PathBuf)> = Vec::new();
+    for entry in fs::read_dir(store_dir.join(ARCHIVES_DIR))? {
+        let entry = entry?;
+        let path = entry.path();
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() || path.extension().is_some_and(|ext| ext == "part") {
+            continue;
+        }
+        archives.push((metadata.modified()?, metadata.len(), path));
+    }
+    let mut size: u64 = archives.iter().map(|(_, len, _)| len).sum();
+    archives.sort();
+    for (_, len, path) in archives {
+        if size <= limit {
+            break;
+        }
+        if path == keep {
+            continue;
+        }
+        // Another build may be evicting (or using) it at the same time; it's gone either way, or
+        // will be next time.
+        match fs::remove_file(&path) {
+            Ok(()) => logging::debug!("Evicted {path:?} from the archive store."),
+            Err(err) => logging::debug!("Failed to evict {path:?} from the archive store: {err}"),
+        }
+        size -= len;
+    }
+    Ok(())
+}
+
+/// Returns the temporary file that `path` is written to before being renamed into place, unique
+/// to this process.
+fn part_path(path: &Path) -> PathBuf {
+    let mut part_path = path.as_os_str().to_owned();
+    part_path.push(format!(".{}.part", process::id()));
+    PathBuf::from(part_path)
+}
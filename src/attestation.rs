@@ -0,0 +1,107 @@
+//! Verifying the provenance of release archives with GitHub artifact attestations.
+
+use crate::error::Error;
+use crate::fetcher::ProtocFetcher;
+use crate::forge;
+use crate::logging;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+/// Whether to verify the provenance of release archives before installing them; see
+/// [`ProtocFetcher::verify_attestations`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AttestationPolicy {
+    /// Don't verify.
+    #[default]
+    Off,
+    /// Verify where possible: an archive whose attestation doesn't verify is rejected, but one
+    /// that can't be verified at all (e.g. because the release has no attestations, or the
+    /// GitHub CLI isn't installed) is installed with a warning.
+    Warn,
+    /// Fail closed: only install archives whose attestation verifies.
+    Require,
+}
+
+/// Verifies the provenance of the release archive at `path` as the fetcher's
+/// [`AttestationPolicy`] says, failing with [`Error::Provenance`] if it is rejected.
+///
+/// Verification is done by `gh attestation verify`, which checks the archive's digest against the
+/// attestations GitHub holds for the release repository and validates their sigstore signatures.
+pub(crate) fn verify(fetcher: &ProtocFetcher, path: &Path) -> anyhow::Result<()> {
+    let policy = fetcher.attestation_policy;
+    if policy == AttestationPolicy::Off {
+        return Ok(());
+    }
+    match run_gh(fetcher, path) {
+        Ok(()) => {
+            logging::info!("Verified the attestation of {path:?}.");
+            Ok(())
+        }
+        Err(Unverified::Unavailable(reason)) if policy == AttestationPolicy::Warn => {
+            logging::warn!("Installing {path:?} without verifying its provenance: {reason}.");
+            Ok(())
+        }
+        Err(Unverified::Unavailable(reason) | Unverified::Rejected(reason)) => {
+            Err(Error::Provenance {
+                path: path.to_path_buf(),
+                reason,
+            }
+            .into())
+        }
+    }
+}
+
+/// Why an archive's provenance wasn't verified.
+enum Unverified {
+    /// It couldn't be checked at all.
+    Unavailable(String),
+    /// It was checked, and doesn't verify.
+    Rejected(String),
+}
+
+/// Runs `gh attestation verify` on the archive at `path`.
+fn run_gh(fetcher: &ProtocFetcher, path: &Path) -> Result<(), Unverified> {
+    if fetcher.offline {
+        return Err(Unverified::Unavailable(
+            "attestations can't be fetched in offline mode".to_string(),
+        ));
+    }
+    let mut command = Command::new("gh");
+    command
+        .args(["attestation", "verify"])
+        .arg(path)
+        .args(["--repo", forge::repo(fetcher)]);
+    if !forge::is_github_com(fetcher) {
+        command.env("GH_HOST", forge::web_url(fetcher));
+    }
+    if let Some(token) = &fetcher.github_token {
+        command.env("GH_TOKEN", &token.0);
+    }
+    let output = match command.output() {
+        Ok(output) => output,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return Err(Unverified::Unavailable(
+                "the GitHub CLI (gh) isn't installed".to_string(),
+            ))
+        }
+        Err(err) => {
+            return Err(Unverified::Unavailable(format!(
+                "the GitHub CLI (gh) couldn't be run: {err}"
+            )))
+        }
+    };
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    let lowercase = stderr.to_lowercase();
+    if lowercase.contains("no attestations found")
+        || lowercase.contains("error connecting")
+        || lowercase.contains("gh auth login")
+    {
+        Err(Unverified::Unavailable(stderr))
+    } else {
+        Err(Unverified::Rejected(stderr))
+    }
+}
@@ -0,0 +1,100 @@
+//! Helpers for build scripts: fetching protoc and telling Cargo about it.
+//!
+//! ```no_run
+//! // build.rs
+//! fn main() -> anyhow::Result<()> {
+//!     protoc_fetcher::build::setup("31.1")?;
+//!     tonic_build::compile_protos("protos/my_service.proto")?;
+//!     println!("cargo:rerun-if-changed=protos");
+//!     Ok(())
+//! }
+//! ```
+//!
+//! Like any `rerun-if` directive, the ones printed here turn off Cargo's default of re-running
+//! the build script whenever any file of the package changes, so print `cargo:rerun-if-changed`
+//! for the protos too, as above (or see
+//! [`ProtocOutput::emit_rerun_if_changed`](crate::ProtocOutput::emit_rerun_if_changed)).
+
+use crate::config;
+use crate::error::Error;
+use crate::fetcher::ProtocFetcher;
+use crate::logging::Verbosity;
+use crate::protoc::Protoc;
+use std::env;
+
+/// The env vars that change what is fetched; see [`ProtocFetcher::fetch`].
+const FETCH_ENV_VARS: &[&str] = &[
+    "PROTOC_FETCHER_VERSION",
+    "PROTOC_FETCHER_DIR",
+    "PROTOC_FETCHER_OFFLINE",
+    "PROTOC_FETCHER_MIRROR",
+];
+
+/// Fetches protoc `version` into `OUT_DIR` and sets the build up to use it; see [`setup_with`].
+pub fn setup(version: &str) -> Result<Protoc, Error> {
+    setup_with(ProtocFetcher::new(version))
+}
+
+/// Like [`setup`], but with the version read from the workspace's config; see
+/// [`ProtocFetcher::from_config`]. The build script re-runs when the config file changes.
+pub fn setup_from_config() -> Result<Protoc, Error> {
+    let config = config::find()?;
+    println!("cargo:rerun-if-changed={}", config.path.display());
+    setup_with(config.fetcher())
+}
+
+/// Fetches protoc with `fetcher` and sets the build up to use it:
+///
+/// - Prints `cargo:rerun-if-env-changed` directives for the env vars that change what is fetched
+///   (`PROTOC_FETCHER_VERSION` and the like, and `PROTOC` with
+///   [`system_protoc`](ProtocFetcher::system_protoc)), so that the build script re-runs when they
+///   change.
+/// - Sets the `PROTOC` and `PROTOC_INCLUDE` env vars for the rest of the build script, as
+///   [`Protoc::set_env`] does, for prost-build and tonic-build to pick up.
+/// - Sets them for the compilation of the crate too (`cargo:rustc-env`), so that its code and
+///   tests can get at protoc with `env!("PROTOC")`.
+/// - Unless the build script has set up a logger of its own, turns the fetch's warnings (e.g.
+///   about retried downloads) into `cargo:warning` directives, which Cargo shows to the user;
+///   with [`Verbosity::Verbose`], everything else the fetch logs too.
+pub fn setup_with(fetcher: ProtocFetcher) -> Result<Protoc, Error> {
+    if log::set_logger(&CargoWarningLogger).is_ok() {
+        log::set_max_level(match fetcher.verbosity {
+            Verbosity::Verbose => log::LevelFilter::Info,
+            _ => log::LevelFilter::Warn,
+        });
+    }
+    for name in FETCH_ENV_VARS {
+        println!("cargo:rerun-if-env-changed={name}");
+    }
+    if fetcher.system_protoc {
+        println!("cargo:rerun-if-env-changed=PROTOC");
+    }
+
+    let protoc = fetcher.fetch()?;
+    let include_path = protoc.include_path()?;
+    env::set_var("PROTOC", protoc.path());
+    env::set_var("PROTOC_INCLUDE", &include_path);
+    println!("cargo:rustc-env=PROTOC={}", protoc.path().display());
+    println!("cargo:rustc-env=PROTOC_INCLUDE={}", include_path.display());
+    Ok(protoc)
+}
+
+/// Logs as `cargo:warning` directives: warnings and errors, or everything up to the max level
+/// set for a verbose fetch.
+struct CargoWarningLogger;
+
+impl log::Log for CargoWarningLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            // A directive ends at the end of the line.
+            let message = record.args().to_string().replace('\n', " ");
+            println!("cargo:warning={message}");
+        }
+    }
+
+    fn flush(&self) {}
+}
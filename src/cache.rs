@@ -0,0 +1,83 @@
+//! The shared directories installs can go into instead of `OUT_DIR`: the user-level cache shared
+//! by all builds of a user, and the Cargo target directory shared by the crates of a workspace.
+
+use crate::error::Error;
+use anyhow::anyhow;
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// The file Cargo puts into the root of every target directory.
+const CACHEDIR_TAG: &str = "CACHEDIR.TAG";
+
+/// Returns the user's cache directory, which [`ProtocFetcher::user_cache`] installs into; the
+/// installs themselves live in its `protoc-fetcher` subdirectory. Pass it to
+/// [`validate_cache`](crate::validate_cache) to check the shared installs.
+///
+/// This is `$XDG_CACHE_HOME` (or `~/.cache`) on Linux and other Unixes, `~/Library/Caches` on
+/// macOS, and `%LOCALAPPDATA%` on Windows.
+///
+/// [`ProtocFetcher::user_cache`]: crate::ProtocFetcher::user_cache
+pub fn user_cache_dir() -> Result<PathBuf, Error> {
+    let dir = if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Caches"))
+    } else {
+        env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+    };
+    match dir {
+        Some(dir) if !dir.as_os_str().is_empty() => Ok(dir),
+        _ => Err(anyhow!("Could not determine the user's cache directory").into()),
+    }
+}
+
+/// Returns the Cargo target directory of the build, e.g. `<workspace>/target`, which
+/// [`ProtocFetcher::shared_target_dir`] installs into; the installs themselves live in its
+/// `protoc-fetcher` subdirectory.
+///
+/// This is `CARGO_TARGET_DIR` if that is set to an absolute path, and is otherwise found among the
+/// ancestors of `OUT_DIR` (as `<target>/[<triple>/]<profile>/build/<package>-<hash>/out`), so it
+/// is only known in build scripts.
+///
+/// [`ProtocFetcher::shared_target_dir`]: crate::ProtocFetcher::shared_target_dir
+pub fn cargo_target_dir() -> Result<PathBuf, Error> {
+    if let Some(dir) = env::var_os("CARGO_TARGET_DIR").map(PathBuf::from) {
+        if dir.is_absolute() {
+            return Ok(dir);
+        }
+    }
+    let Some(out_dir) = env::var_os("OUT_DIR").map(PathBuf::from) else {
+        return Err(anyhow!(
+            "Could not determine the Cargo target directory: the OUT_DIR env var is not set"
+        )
+        .into());
+    };
+    if let Some(dir) = out_dir
+        .ancestors()
+        .find(|dir| dir.join(CACHEDIR_TAG).is_file())
+    {
+        return Ok(dir.to_path_buf());
+    }
+    // Without the tag (e.g. in a target directory that was copied), go by the layout: the
+    // directory above the profile (which is the one above `build`), or above that when
+    // cross-compiling, where the profiles are in a directory named after the target triple.
+    let build_dir = out_dir
+        .ancestors()
+        .find(|dir| dir.file_name().is_some_and(|name| name == "build"));
+    let Some(dir) = build_dir.and_then(Path::parent).and_then(Path::parent) else {
+        return Err(anyhow!(
+            "Could not determine the Cargo target directory from OUT_DIR {out_dir:?}"
+        )
+        .into());
+    };
+    let target = env::var_os("TARGET");
+    match dir.parent() {
+        Some(parent) if target.is_some() && dir.file_name() == target.as_deref() => {
+            Ok(parent.to_path_buf())
+        }
+        _ => Ok(dir.to_path_buf()),
+    }
+}
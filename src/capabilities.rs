@@ -0,0 +1,126 @@
+//! Probing what a protoc release supports.
+
+use crate::diagnostics::Severity;
+use crate::error::Error;
+use crate::install;
+use crate::protoc::{with_temp_file, Protoc};
+use anyhow::{anyhow, bail};
+use std::fs;
+use std::path::Path;
+
+/// The editions that protoc may support, oldest first.
+const EDITIONS: &[&str] = &["2023", "2024"];
+
+/// What a protoc release supports, as determined by [`Protoc::capabilities`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    /// The version protoc reports, e.g. "3.21.12" or "27.1".
+    pub version: String,
+    /// Whether `optional` fields in proto3 files are supported without any experimental flag
+    /// (since 3.15).
+    pub proto3_optional: bool,
+    /// The editions supported (e.g. "2023"), oldest first; empty before 27.0.
+    pub editions: Vec<String>,
+}
+
+impl Capabilities {
+    /// Returns the newest supported edition, if any.
+    pub fn max_edition(&self) -> Option<&str> {
+        self.editions.last().map(String::as_str)
+    }
+
+    /// Returns whether `edition` (e.g. "2023") is supported.
+    pub fn supports_edition(&self, edition: &str) -> bool {
+        self.editions.iter().any(|supported| supported == edition)
+    }
+
+    /// Fails with a clear message unless `edition` is supported, e.g. at the start of a build
+    /// script whose protos use it.
+    pub fn require_edition(&self, edition: &str) -> Result<(), Error> {
+        if !self.supports_edition(edition) {
+            return Err(anyhow!(
+                "protoc {} is too old for edition {edition} (supported editions: {})",
+                self.version,
+                if self.editions.is_empty() {
+                    "none".to_string()
+                } else {
+                    self.editions.join(", ")
+                }
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Fails with a clear message unless proto3 `optional` fields are supported.
+    pub fn require_proto3_optional(&self) -> Result<(), Error> {
+        if !self.proto3_optional {
+            return Err(anyhow!(
+                "protoc {} is too old for optional fields in proto3 files (3.15 or newer is \
+                 needed)",
+                self.version
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+impl Protoc {
+    /// Determines what this protoc supports, by asking it for its version and having it compile
+    /// tiny probe protos for each feature. Lets build scripts fail early with a clear message
+    /// (see [`Capabilities::require_edition`]) rather than with cryptic codegen errors.
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use protoc_fetcher::ProtocFetcher;
+    ///
+    /// let protoc = ProtocFetcher::new("27.0").fetch()?;
+    /// protoc.capabilities()?.require_edition("2023")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn capabilities(&self) -> Result<Capabilities, Error> {
+        let version = install::get_protoc_version(self.path())?;
+        let version = version
+            .trim()
+            .trim_start_matches("libprotoc")
+            .trim()
+            .to_string();
+
+        let proto3_optional = self.probe(
+            "syntax = \"proto3\";\n\npackage protoc_fetcher.probe;\n\n\
+             message Probe {\n  optional int32 field = 1;\n}\n",
+        )?;
+        let mut editions = Vec::new();
+        for edition in EDITIONS {
+            let supported = self.probe(&format!(
+                "edition = \"{edition}\";\n\npackage protoc_fetcher.probe;\n\nmessage Probe {{}}\n"
+            ))?;
+            if supported {
+                editions.push(edition.to_string());
+            }
+        }
+
+        Ok(Capabilities {
+            version,
+            proto3_optional,
+            editions,
+        })
+    }
+
+    /// Returns whether protoc compiles the proto `source`.
+    fn probe(&self, source: &str) -> anyhow::Result<bool> {
+        with_temp_file("probe.proto", |proto| {
+            fs::write(proto, source)?;
+            let (Some(dir), Some(name)) = (proto.parent(), proto.file_name()) else {
+                bail!("Invalid probe path {proto:?}");
+            };
+            let includes: &[&Path] = &[dir];
+            let diagnostics = self.check(&[Path::new(name)], includes)?;
+            Ok(diagnostics
+                .iter()
+                .all(|diagnostic| diagnostic.severity != Severity::Error))
+        })
+    }
+}
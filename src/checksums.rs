@@ -0,0 +1,20 @@
+//! The SHA-256 digests of the official release archives, compiled into the crate so that
+//! downloads can be verified without trusting the network; see
+//! [`ProtocFetcher::strict_checksums`](crate::ProtocFetcher::strict_checksums).
+
+/// Lines of `<version> <platform> <sha256>`, regenerated by `scripts/update-checksums.sh` when the
+/// crate is released.
+const KNOWN_DIGESTS: &str = include_str!("checksums.txt");
+
+/// Returns the digest of the official release archive of protoc `version` (as tagged, e.g.
+/// "32.0-rc1") for `platform`, if it is known.
+pub(crate) fn known_sha256(version: &str, platform: &str) -> Option<&'static str> {
+    KNOWN_DIGESTS
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .find_map(|line| {
+            let mut fields = line.split_whitespace();
+            let matches = fields.next()? == version && fields.next()? == platform;
+            matches.then(|| fields.next()).flatten()
+        })
+}
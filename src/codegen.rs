@@ -0,0 +1,232 @@
+//! Generating Rust code with prost-build and tonic-build, using the fetched protoc.
+
+use crate::error::Error;
+use crate::protoc::Protoc;
+use anyhow::Context;
+use std::path::Path;
+
+impl Protoc {
+    /// Runs [prost-build] with this protoc, as configured by `config`, to generate Rust code for
+    /// `protos`, with `includes` as the include paths. Sets the `PROTOC` and `PROTOC_INCLUDE` env
+    /// vars first, as with [`set_env`](Self::set_env), so call this from `build.rs`.
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use protoc_fetcher::ProtocFetcher;
+    ///
+    /// let mut config = prost_build::Config::new();
+    /// config.bytes(["."]);
+    /// ProtocFetcher::new("21.2").fetch()?.compile_with_prost(
+    ///     config,
+    ///     &["protos/my_service.proto"],
+    ///     &["protos"],
+    /// )?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [prost-build]: https://crates.io/crates/prost-build
+    #[cfg(feature = "prost")]
+    pub fn compile_with_prost(
+        &self,
+        mut config: prost_build::Config,
+        protos: &[impl AsRef<Path>],
+        includes: &[impl AsRef<Path>],
+    ) -> Result<(), Error> {
+        self.set_env()?;
+        config
+            .compile_protos(protos, includes)
+            .context("prost-build failed")?;
+        Ok(())
+    }
+
+    /// Runs [tonic-build] with this protoc, as configured by `builder`, to generate Rust code
+    /// (including gRPC clients and servers) for `protos`, with `includes` as the include paths.
+    /// Sets the `PROTOC` and `PROTOC_INCLUDE` env vars first, as with [`set_env`](Self::set_env),
+    /// so call this from `build.rs`.
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use protoc_fetcher::ProtocFetcher;
+    ///
+    /// ProtocFetcher::new("21.2").fetch()?.compile_with_tonic(
+    ///     tonic_build::configure().build_server(false),
+    ///     &["protos/my_service.proto"],
+    ///     &["protos"],
+    /// )?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [tonic-build]: https://crates.io/crates/tonic-build
+    #[cfg(feature = "tonic")]
+    pub fn compile_with_tonic(
+        &self,
+        builder: tonic_build::Builder,
+        protos: &[impl AsRef<Path>],
+        includes: &[impl AsRef<Path>],
+    ) -> Result<(), Error> {
+        self.set_env()?;
+        builder
+            .compile(protos, includes)
+            .context("tonic-build failed")?;
+        Ok(())
+    }
+}
+
+// The fake protoc the tests run is a shell script, which only runs on Unix-like systems.
+#[cfg(all(test, unix, any(feature = "prost", feature = "tonic")))]
+mod tests {
+    use crate::test_support::{FakeRelease, MockHttp, Sandbox};
+    use crate::ProtocFetcher;
+    use prost::Message;
+    use prost_types::field_descriptor_proto::{Label, Type};
+    use prost_types::{
+        DescriptorProto, FieldDescriptorProto, FileDescriptorProto, FileDescriptorSet,
+        MethodDescriptorProto, ServiceDescriptorProto,
+    };
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::{Mutex, MutexGuard, PoisonError};
+
+    const VERSION: &str = "21.2";
+
+    const GREETER_PROTO: &str = r#"
+syntax = "proto3";
+
+package greeter;
+
+message HelloRequest {
+  string name = 1;
+}
+
+message HelloReply {
+  string message = 1;
+}
+
+service Greeter {
+  rpc SayHello(HelloRequest) returns (HelloReply);
+}
+"#;
+
+    /// Writes `greeter.proto` into `sandbox`, returning its include dir and the output dir the
+    /// generated code is to go into.
+    fn write_greeter_proto(sandbox: &Sandbox) -> (PathBuf, PathBuf) {
+        let protos = sandbox.path().join("protos");
+        let out = sandbox.path().join("out");
+        fs::create_dir_all(&protos).unwrap();
+        fs::create_dir_all(&out).unwrap();
+        fs::write(protos.join("greeter.proto"), GREETER_PROTO).unwrap();
+        (protos, out)
+    }
+
+    /// Returns the descriptor set protoc writes for [`GREETER_PROTO`].
+    fn greeter_descriptor_set() -> Vec<u8> {
+        let message = |name: &str, field: &str| DescriptorProto {
+            name: Some(name.to_string()),
+            field: vec![FieldDescriptorProto {
+                name: Some(field.to_string()),
+                number: Some(1),
+                label: Some(Label::Optional as i32),
+                r#type: Some(Type::String as i32),
+                json_name: Some(field.to_string()),
+                ..FieldDescriptorProto::default()
+            }],
+            ..DescriptorProto::default()
+        };
+        let file = FileDescriptorProto {
+            name: Some("greeter.proto".to_string()),
+            package: Some("greeter".to_string()),
+            message_type: vec![
+                message("HelloRequest", "name"),
+                message("HelloReply", "message"),
+            ],
+            service: vec![ServiceDescriptorProto {
+                name: Some("Greeter".to_string()),
+                method: vec![MethodDescriptorProto {
+                    name: Some("SayHello".to_string()),
+                    input_type: Some(".greeter.HelloRequest".to_string()),
+                    output_type: Some(".greeter.HelloReply".to_string()),
+                    ..MethodDescriptorProto::default()
+                }],
+                ..ServiceDescriptorProto::default()
+            }],
+            syntax: Some("proto3".to_string()),
+            ..FileDescriptorProto::default()
+        };
+        FileDescriptorSet { file: vec![file] }.encode_to_vec()
+    }
+
+    /// Returns a fetcher of a fake release served from memory, whose protoc is a shell script that
+    /// stands in for the real one: it reports its version, and writes
+    /// [`greeter_descriptor_set`] wherever `-o` points, which is all prost-build asks of protoc.
+    fn fetcher(sandbox: &Sandbox) -> ProtocFetcher {
+        let descriptor_set: String = greeter_descriptor_set()
+            .iter()
+            .map(|byte| format!("\\{byte:03o}"))
+            .collect();
+        let protoc = format!(
+            r#"#!/bin/sh
+if [ "$1" = --version ]; then
+    echo "libprotoc {VERSION}"
+    exit
+fi
+while [ $# -gt 0 ]; do
+    if [ "$1" = -o ]; then
+        out="$2"
+    fi
+    shift
+done
+printf '{descriptor_set}' > "$out"
+"#
+        );
+        let release = FakeRelease::new(VERSION).file("bin/protoc", protoc);
+        let http = MockHttp::new().fake_release(&release).unwrap();
+        sandbox.fetcher(VERSION).http_client(http)
+    }
+
+    /// Serializes the tests, as compiling sets the process-wide `PROTOC` env var to the protoc of
+    /// the test's sandbox, which is gone once the test is done.
+    fn lock_protoc_env() -> MutexGuard<'static, ()> {
+        static PROTOC_ENV: Mutex<()> = Mutex::new(());
+        PROTOC_ENV.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    #[cfg(feature = "prost")]
+    #[test]
+    fn compile_with_prost_generates_code() {
+        let _env = lock_protoc_env();
+        let sandbox = Sandbox::new().unwrap();
+        let (protos, out) = write_greeter_proto(&sandbox);
+        let protoc = fetcher(&sandbox).fetch().unwrap();
+
+        let mut config = prost_build::Config::new();
+        config.out_dir(&out);
+        protoc
+            .compile_with_prost(config, &[protos.join("greeter.proto")], &[&protos])
+            .unwrap();
+
+        let generated = fs::read_to_string(out.join("greeter.rs")).unwrap();
+        assert!(generated.contains("pub struct HelloRequest"));
+    }
+
+    #[cfg(feature = "tonic")]
+    #[test]
+    fn compile_with_tonic_generates_code() {
+        let _env = lock_protoc_env();
+        let sandbox = Sandbox::new().unwrap();
+        let (protos, out) = write_greeter_proto(&sandbox);
+        let protoc = fetcher(&sandbox).fetch().unwrap();
+
+        protoc
+            .compile_with_tonic(
+                tonic_build::configure().out_dir(&out),
+                &[protos.join("greeter.proto")],
+                &[&protos],
+            )
+            .unwrap();
+
+        let generated = fs::read_to_string(out.join("greeter.rs")).unwrap();
+        assert!(generated.contains("pub mod greeter_client"));
+    }
+}
@@ -0,0 +1,54 @@
+//! Drop-in replacements for the functions of the `protoc-bin-vendored` crate, so that build
+//! scripts migrating from it only need their imports changed:
+//!
+//! ```no_run
+//! // build.rs, which used `protoc_bin_vendored::{include_path, protoc_bin_path}`
+//! use protoc_fetcher::compat::{include_path, protoc_bin_path};
+//!
+//! fn main() -> anyhow::Result<()> {
+//!     std::env::set_var("PROTOC", protoc_bin_path()?);
+//!     std::env::set_var("PROTOC_INCLUDE", include_path()?);
+//!     Ok(())
+//! }
+//! ```
+//!
+//! Or, with `use protoc_fetcher::compat as protoc_bin_vendored;`, even the call sites stay as
+//! they are. The functions fail with an [`anyhow::Error`] rather than `protoc-bin-vendored`'s own
+//! error type, which works the same with `?`, `unwrap` and `expect`.
+//!
+//! Rather than returning a binary vendored into the crate, they fetch a release on first use: the
+//! version configured for the workspace if there is a config (see
+//! [`ProtocFetcher::from_config`]), and [`DEFAULT_VERSION`] otherwise. It is installed into the
+//! [user cache](ProtocFetcher::user_cache) (unless the config sets a `cache-dir`), so that the
+//! build scripts of a workspace share one install, and the env var overrides of
+//! [`ProtocFetcher::fetch`] apply as usual.
+
+use crate::config;
+use crate::error::Error;
+use crate::fetcher::ProtocFetcher;
+use crate::protoc::Protoc;
+use std::path::PathBuf;
+
+/// The version fetched when the workspace configures none. It only changes with a new version of
+/// this crate, so builds stay reproducible.
+pub const DEFAULT_VERSION: &str = "31.1";
+
+/// Returns the path to the protoc binary, fetching it if need be.
+pub fn protoc_bin_path() -> Result<PathBuf, Error> {
+    Ok(fetch()?.path().to_path_buf())
+}
+
+/// Returns the path to the `include` directory of the release with the well-known types (e.g.
+/// `google/protobuf/timestamp.proto`), fetching the release if need be.
+pub fn include_path() -> Result<PathBuf, Error> {
+    fetch()?.include_path()
+}
+
+fn fetch() -> Result<Protoc, Error> {
+    let fetcher = match config::find_if_any()? {
+        Some(config) if config.cache_dir.is_some() => config.fetcher(),
+        Some(config) => config.fetcher().user_cache(true),
+        None => ProtocFetcher::new(DEFAULT_VERSION).user_cache(true),
+    };
+    fetcher.fetch()
+}
@@ -0,0 +1,516 @@
+//! Running protoc over a set of protos.
+
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::error::Error;
+use crate::protoc::{with_temp_file, Protoc};
+use anyhow::Context;
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use std::{fmt, fs};
+
+/// The code generators to run in a [`Protoc::compile`] call, and where each writes its output.
+/// The output directories are created if need be.
+///
+/// ```no_run
+/// use protoc_fetcher::Outputs;
+///
+/// let outputs = Outputs::new()
+///     .cpp_out("gen/cpp")
+///     .python_out("gen/python")
+///     .plugin_out("go", "gen/go", "paths=source_relative");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Outputs {
+    outputs: Vec<Output>,
+    dependency_out: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+struct Output {
+    language: String,
+    dir: PathBuf,
+    options: Option<String>,
+}
+
+macro_rules! builtin_outputs {
+    ($($method:ident => $language:literal),* $(,)?) => {
+        $(
+            #[doc = concat!(
+                "Runs protoc's built-in ", $language, " generator (`--", $language, "_out`)."
+            )]
+            pub fn $method(self, dir: impl Into<PathBuf>) -> Self {
+                self.push($language, dir.into(), None)
+            }
+        )*
+    };
+}
+
+impl Outputs {
+    /// Creates an empty set of outputs.
+    pub fn new() -> Self {
+        Outputs::default()
+    }
+
+    builtin_outputs! {
+        cpp_out => "cpp",
+        csharp_out => "csharp",
+        java_out => "java",
+        kotlin_out => "kotlin",
+        objc_out => "objc",
+        php_out => "php",
+        pyi_out => "pyi",
+        python_out => "python",
+        ruby_out => "ruby",
+    }
+
+    /// Also has protoc write a Make-style dependency file to `path` (`--dependency_out`), listing
+    /// every proto the outputs depend on, including transitive imports. The dependencies are then
+    /// available as [`ProtocOutput::dependencies`].
+    pub fn dependency_out(mut self, path: impl Into<PathBuf>) -> Self {
+        self.dependency_out = Some(path.into());
+        self
+    }
+
+    /// Runs the generator for `--<name>_out`, i.e. the `protoc-gen-<name>` plugin (or the built-in
+    /// generator of that name), passing it `options` unless they are empty.
+    pub fn plugin_out(self, name: &str, dir: impl Into<PathBuf>, options: &str) -> Self {
+        let options = (!options.is_empty()).then(|| options.to_string());
+        self.push(name, dir.into(), options)
+    }
+
+    fn push(mut self, language: &str, dir: PathBuf, options: Option<String>) -> Self {
+        self.outputs.push(Output {
+            language: language.to_string(),
+            dir,
+            options,
+        });
+        self
+    }
+
+    /// Returns the languages of the outputs, in the order they were added.
+    pub(crate) fn languages(&self) -> impl Iterator<Item = &str> {
+        self.outputs.iter().map(|output| output.language.as_str())
+    }
+
+    /// Creates the output directories (and the dependency file's directory) that don't exist yet.
+    /// Generators that write an archive (e.g. `--java_out=gen.jar`) get the archive's parent
+    /// directory created instead.
+    fn create_dirs(&self) -> anyhow::Result<()> {
+        for output in &self.outputs {
+            let is_archive = output
+                .dir
+                .extension()
+                .is_some_and(|extension| extension == "jar" || extension == "zip");
+            let dir = match output.dir.parent() {
+                Some(parent) if is_archive => parent,
+                _ => &output.dir,
+            };
+            create_dir(dir)?;
+        }
+        if let Some(parent) = self.dependency_out.as_deref().and_then(Path::parent) {
+            create_dir(parent)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the protoc arguments for the outputs.
+    pub(crate) fn args(&self) -> Vec<OsString> {
+        let dependency_out = self
+            .dependency_out
+            .as_deref()
+            .map(|path| path_arg("dependency_out", path));
+        self.outputs
+            .iter()
+            .map(|output| {
+                let mut arg = OsString::from(format!("--{}_out=", output.language));
+                if let Some(options) = &output.options {
+                    arg.push(options);
+                    arg.push(":");
+                }
+                arg.push(&output.dir);
+                arg
+            })
+            .chain(dependency_out)
+            .collect()
+    }
+}
+
+/// The output of a successful protoc run.
+#[derive(Debug, Clone)]
+pub struct ProtocOutput {
+    /// What protoc wrote to stdout, which is binary for `--encode`.
+    pub stdout: Vec<u8>,
+    /// What protoc wrote to stderr, e.g. warnings about unused imports.
+    pub stderr: String,
+    /// `stderr`, parsed.
+    pub diagnostics: Vec<Diagnostic>,
+    /// The protos the outputs depend on, if a [`dependency_out`](Outputs::dependency_out) was
+    /// requested.
+    pub dependencies: Vec<PathBuf>,
+}
+
+impl ProtocOutput {
+    /// Prints a `cargo:rerun-if-changed` directive for each of the
+    /// [`dependencies`](Self::dependencies), so that a build script re-runs code generation
+    /// exactly when one of the protos (or anything they import) changes.
+    pub fn emit_rerun_if_changed(&self) {
+        for dependency in &self.dependencies {
+            println!("cargo:rerun-if-changed={}", dependency.display());
+        }
+    }
+}
+
+/// Why protoc failed when it ran but exited unsuccessfully, e.g. because the protos don't compile,
+/// as returned in an [`Error::ProtocFailed`].
+#[derive(Debug, Clone)]
+pub struct ProtocFailure {
+    /// Protoc's exit status.
+    pub status: ExitStatus,
+    /// What protoc wrote to stdout.
+    pub stdout: Vec<u8>,
+    /// What protoc wrote to stderr, which explains the failure.
+    pub stderr: String,
+    /// `stderr`, parsed, e.g. for rendering as annotations on the offending protos.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl fmt::Display for ProtocFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "protoc failed ({}): {}", self.status, self.stderr.trim())
+    }
+}
+
+impl std::error::Error for ProtocFailure {}
+
+/// Options for [`Protoc::descriptor_set`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DescriptorOptions {
+    /// Also include the descriptors of all the (transitive) imports of the protos, so that the set
+    /// is self-contained (`--include_imports`). Reflection services usually need this.
+    pub include_imports: bool,
+    /// Keep comments and source locations in the descriptors (`--include_source_info`), e.g. for
+    /// documentation generators.
+    pub include_source_info: bool,
+}
+
+impl DescriptorOptions {
+    pub(crate) fn args(&self) -> Vec<&'static str> {
+        let mut args = Vec::new();
+        if self.include_imports {
+            args.push("--include_imports");
+        }
+        if self.include_source_info {
+            args.push("--include_source_info");
+        }
+        args
+    }
+}
+
+impl Protoc {
+    /// Runs protoc over `protos` with the given include paths and generators, e.g.:
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use protoc_fetcher::{Outputs, ProtocFetcher};
+    ///
+    /// let protoc = ProtocFetcher::new("21.2").fetch()?;
+    /// protoc.compile(
+    ///     &["protos/my_service.proto"],
+    ///     &["protos"],
+    ///     &Outputs::new().cpp_out("gen"),
+    /// )?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// The release's `include` directory is always an include path, so the well-known types can be
+    /// imported. If protoc runs but fails, the error is an [`Error::ProtocFailed`].
+    pub fn compile(
+        &self,
+        protos: &[impl AsRef<Path>],
+        includes: &[impl AsRef<Path>],
+        outputs: &Outputs,
+    ) -> Result<ProtocOutput, Error> {
+        Ok(self.compile_with(self.command()?, protos, includes, outputs)?)
+    }
+
+    /// Like [`compile`](Self::compile), but starting from the given protoc `command`.
+    pub(crate) fn compile_with(
+        &self,
+        mut command: Command,
+        protos: &[impl AsRef<Path>],
+        includes: &[impl AsRef<Path>],
+        outputs: &Outputs,
+    ) -> anyhow::Result<ProtocOutput> {
+        outputs.create_dirs()?;
+        command
+            .args(include_args(includes))
+            .args(outputs.args())
+            .args(protos.iter().map(|proto| proto.as_ref()));
+        let mut output = self.run(command)?;
+        if let Some(dependency_out) = &outputs.dependency_out {
+            let contents = fs::read_to_string(dependency_out)
+                .with_context(|| format!("Failed to read {dependency_out:?}"))?;
+            output.dependencies = parse_dependency_file(&contents);
+        }
+        Ok(output)
+    }
+
+    /// Runs protoc over `protos` and returns the resulting serialized `FileDescriptorSet`, for
+    /// reflection (e.g. gRPC server reflection), dynamic messages and similar tooling.
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use protoc_fetcher::{DescriptorOptions, ProtocFetcher};
+    ///
+    /// let protoc = ProtocFetcher::new("21.2").fetch()?;
+    /// let descriptors = protoc.descriptor_set(
+    ///     &["protos/my_service.proto"],
+    ///     &["protos"],
+    ///     DescriptorOptions {
+    ///         include_imports: true,
+    ///         ..DescriptorOptions::default()
+    ///     },
+    /// )?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn descriptor_set(
+        &self,
+        protos: &[impl AsRef<Path>],
+        includes: &[impl AsRef<Path>],
+        options: DescriptorOptions,
+    ) -> Result<Vec<u8>, Error> {
+        Ok(with_temp_file("descriptor_set.pb", |out| {
+            self.write_descriptor_set(protos, includes, options, out)?;
+            fs::read(out).with_context(|| format!("Failed to read {out:?}"))
+        })?)
+    }
+
+    /// Like [`descriptor_set`](Self::descriptor_set), but writes the `FileDescriptorSet` to `out`.
+    pub fn write_descriptor_set(
+        &self,
+        protos: &[impl AsRef<Path>],
+        includes: &[impl AsRef<Path>],
+        options: DescriptorOptions,
+        out: impl AsRef<Path>,
+    ) -> Result<ProtocOutput, Error> {
+        let mut command = self.command()?;
+        command
+            .args(include_args(includes))
+            .arg(path_arg("descriptor_set_out", out.as_ref()))
+            .args(options.args())
+            .args(protos.iter().map(|proto| proto.as_ref()));
+        Ok(self.run(command)?)
+    }
+
+    /// Checks that `protos` compile, without generating anything, e.g. for lint jobs and
+    /// pre-commit hooks. Returns protoc's diagnostics: if any of them is a [`Severity::Error`],
+    /// the protos don't compile.
+    ///
+    /// Only failing to run protoc at all is an `Err`.
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use protoc_fetcher::{ProtocFetcher, Severity};
+    ///
+    /// let protoc = ProtocFetcher::new("21.2").fetch()?;
+    /// let diagnostics = protoc.check(&["protos/my_service.proto"], &["protos"])?;
+    /// for diagnostic in &diagnostics {
+    ///     eprintln!("{diagnostic}");
+    /// }
+    /// if diagnostics.iter().any(|d| d.severity == Severity::Error) {
+    ///     std::process::exit(1);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn check(
+        &self,
+        protos: &[impl AsRef<Path>],
+        includes: &[impl AsRef<Path>],
+    ) -> Result<Vec<Diagnostic>, Error> {
+        // protoc requires some output, so produce a descriptor set and throw it away.
+        let result = with_temp_file("check.pb", |out| {
+            self.write_descriptor_set(protos, includes, DescriptorOptions::default(), out)
+        });
+        match result {
+            Ok(output) => Ok(output.diagnostics),
+            Err(Error::ProtocFailed(failure)) => {
+                let mut diagnostics = failure.diagnostics;
+                if !diagnostics.iter().any(|d| d.severity == Severity::Error) {
+                    diagnostics.push(Diagnostic {
+                        file: None,
+                        line: None,
+                        column: None,
+                        severity: Severity::Error,
+                        message: format!("protoc failed ({})", failure.status),
+                    });
+                }
+                Ok(diagnostics)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Decodes the binary protobuf `message` of type `message_type` (a fully-qualified name,
+    /// e.g. `my.pkg.MyMessage`, defined in one of `protos`) into protoc's text format, using
+    /// `protoc --decode`.
+    pub fn decode(
+        &self,
+        message_type: &str,
+        protos: &[impl AsRef<Path>],
+        includes: &[impl AsRef<Path>],
+        message: &[u8],
+    ) -> Result<String, Error> {
+        let mut command = self.command()?;
+        command
+            .args(include_args(includes))
+            .arg(format!("--decode={message_type}"))
+            .args(protos.iter().map(|proto| proto.as_ref()));
+        let output = self.run_with_input(command, Some(message.to_vec()))?;
+        Ok(String::from_utf8(output.stdout).context("protoc --decode printed invalid UTF-8")?)
+    }
+
+    /// Decodes the binary protobuf `message` without knowing its type, showing only field numbers
+    /// and wire-level values, using `protoc --decode_raw`.
+    pub fn decode_raw(&self, message: &[u8]) -> Result<String, Error> {
+        let mut command = Command::new(self.path());
+        command.arg("--decode_raw");
+        let output = self.run_with_input(command, Some(message.to_vec()))?;
+        Ok(
+            String::from_utf8(output.stdout)
+                .context("protoc --decode_raw printed invalid UTF-8")?,
+        )
+    }
+
+    /// Encodes `text`, a message of type `message_type` in protoc's text format, into binary
+    /// protobuf, using `protoc --encode`. The inverse of [`decode`](Self::decode).
+    pub fn encode(
+        &self,
+        message_type: &str,
+        protos: &[impl AsRef<Path>],
+        includes: &[impl AsRef<Path>],
+        text: &str,
+    ) -> Result<Vec<u8>, Error> {
+        let mut command = self.command()?;
+        command
+            .args(include_args(includes))
+            .arg(format!("--encode={message_type}"))
+            .args(protos.iter().map(|proto| proto.as_ref()));
+        Ok(self.run_with_input(command, Some(text.into()))?.stdout)
+    }
+}
+
+fn create_dir(dir: &Path) -> anyhow::Result<()> {
+    if !dir.as_os_str().is_empty() {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {dir:?}"))?;
+    }
+    Ok(())
+}
+
+/// Parses the dependencies out of a Make-style dependency file as written by protoc, i.e.
+/// `<outputs>: <dependency> \<newline> <dependency> ...`, where spaces in paths are escaped.
+fn parse_dependency_file(contents: &str) -> Vec<PathBuf> {
+    // The outputs end at the first colon followed by whitespace; a colon in a Windows drive
+    // letter is followed by a path separator instead.
+    let dependencies = contents
+        .char_indices()
+        .find(|&(i, c)| c == ':' && contents[i + 1..].starts_with(char::is_whitespace))
+        .map_or("", |(i, _)| &contents[i + 1..]);
+
+    let mut paths = Vec::new();
+    let mut current = String::new();
+    let mut chars = dependencies.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            // An escaped space is part of the path; a backslash before a newline continues the
+            // line; any other backslash is a Windows path separator.
+            '\\' if chars.peek() == Some(&' ') => current.push(chars.next().unwrap_or(' ')),
+            '\\' if matches!(chars.peek(), Some('\n' | '\r')) => {}
+            c if c.is_whitespace() => {
+                if !current.is_empty() {
+                    paths.push(PathBuf::from(std::mem::take(&mut current)));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        paths.push(PathBuf::from(current));
+    }
+    paths
+}
+
+/// Returns a `-I` argument for each of `includes`.
+pub(crate) fn include_args(includes: &[impl AsRef<Path>]) -> Vec<OsString> {
+    includes
+        .iter()
+        .map(|include| {
+            let mut arg = OsString::from("-I");
+            arg.push(include.as_ref());
+            arg
+        })
+        .collect()
+}
+
+/// Returns `--<flag>=<path>`.
+pub(crate) fn path_arg(flag: &str, path: &Path) -> OsString {
+    let mut arg = OsString::from(format!("--{flag}="));
+    arg.push(OsStr::new(path));
+    arg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn parses_continued_lines() {
+        assert_eq!(
+            parse_dependency_file(concat!(
+                "out/foo.pb: protos/foo.proto \\\n",
+                "  protos/bar.proto \\\r\n",
+                "  include/google/protobuf/any.proto\n",
+            )),
+            paths(&[
+                "protos/foo.proto",
+                "protos/bar.proto",
+                "include/google/protobuf/any.proto",
+            ])
+        );
+    }
+
+    #[test]
+    fn unescapes_spaces_in_paths() {
+        assert_eq!(
+            parse_dependency_file("out/foo.pb: my\\ protos/foo.proto other.proto"),
+            paths(&["my protos/foo.proto", "other.proto"])
+        );
+    }
+
+    #[test]
+    fn keeps_windows_drive_letters_and_separators() {
+        assert_eq!(
+            parse_dependency_file(
+                "C:\\out\\foo.pb: C:\\protos\\foo.proto \\\r\n C:\\protos\\bar.proto\r\n"
+            ),
+            paths(&["C:\\protos\\foo.proto", "C:\\protos\\bar.proto"])
+        );
+    }
+
+    #[test]
+    fn handles_several_outputs_and_no_dependencies() {
+        assert_eq!(
+            parse_dependency_file("out/a.rs out/b.rs: foo.proto"),
+            paths(&["foo.proto"])
+        );
+        assert_eq!(parse_dependency_file("out/foo.pb:\n"), paths(&[]));
+        assert_eq!(parse_dependency_file(""), paths(&[]));
+    }
+}
@@ -0,0 +1,477 @@
+//! Reading the protoc version to fetch (and related settings) from a config file, so that a
+//! workspace pins it in one place rather than in every build script.
+
+use crate::fetcher::ProtocFetcher;
+use crate::install;
+use crate::logging;
+use crate::platform;
+use anyhow::{bail, Context};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The config file of its own, for workspaces that would rather not put it in `Cargo.toml`.
+const CONFIG_FILE_NAME: &str = "protoc-version.toml";
+
+/// The tables of `Cargo.toml` that the config may be in, most specific first.
+const METADATA_TABLES: [&str; 2] = [
+    "package.metadata.protoc-fetcher",
+    "workspace.metadata.protoc-fetcher",
+];
+
+/// The prefix of the tables holding the settings for a single host, e.g.
+/// `[target.x86_64-pc-windows-msvc]`.
+const TARGET_TABLE_PREFIX: &str = "target.";
+
+/// The settings read from a config file, with those of the matching `[target.<triple>]` table
+/// applied.
+#[derive(Debug)]
+pub(crate) struct Config {
+    /// The file the settings were read from.
+    pub path: PathBuf,
+    pub version: String,
+    pub sha256: Option<String>,
+    /// The SHA of the release tag, as for [`ProtocFetcher::tag_sha`].
+    pub tag_sha: Option<String>,
+    /// A base URL or URL template, as for `PROTOC_FETCHER_MIRROR`.
+    pub mirror: Option<String>,
+    /// The GitHub server and repository of the releases, as for [`ProtocFetcher::github_host`]
+    /// and [`ProtocFetcher::release_repo`].
+    pub github_host: Option<String>,
+    pub repo: Option<String>,
+    /// The directory to install into, relative to the config file's directory unless absolute.
+    pub cache_dir: Option<PathBuf>,
+    /// The platform of the releases to fetch, as for [`ProtocFetcher::platform`].
+    pub platform: Option<String>,
+}
+
+/// The entries of a config table, and of its `[target.<triple>]` sub-tables.
+#[derive(Debug, Default)]
+struct Table {
+    entries: Vec<(String, String)>,
+    targets: Vec<(String, Vec<(String, String)>)>,
+}
+
+/// Finds the nearest config, searching the directory of the crate being built (or the current
+/// directory, outside of build scripts) and then its ancestors. In each directory,
+/// `protoc-version.toml` takes precedence over `Cargo.toml`, where
+/// `[package.metadata.protoc-fetcher]` takes precedence over `[workspace.metadata.protoc-fetcher]`.
+///
+/// Settings may be overridden for the host the build runs on in a `[target.<triple>]` sub-table
+/// of the config (e.g. `[workspace.metadata.protoc-fetcher.target.aarch64-apple-darwin]`), so
+/// that a workspace built on several CI platforms can use a different mirror, install directory
+/// or release platform on each.
+pub(crate) fn find() -> anyhow::Result<Config> {
+    match find_if_any()? {
+        Some(config) => Ok(config),
+        None => bail!(
+            "No protoc version is configured: add a {CONFIG_FILE_NAME} or a \
+             [workspace.metadata.protoc-fetcher] table to Cargo.toml in {:?} or one of its \
+             parent directories",
+            start_dir()?
+        ),
+    }
+}
+
+/// Like [`find`], but returns `None` rather than failing if there is no config.
+pub(crate) fn find_if_any() -> anyhow::Result<Option<Config>> {
+    for dir in start_dir()?.ancestors() {
+        let path = dir.join(CONFIG_FILE_NAME);
+        if path.is_file() {
+            let table = parse_table(&read(&path)?, "")?.unwrap_or_default();
+            return config(path, table).map(Some);
+        }
+        let path = dir.join("Cargo.toml");
+        if path.is_file() {
+            let contents = read(&path)?;
+            for table in METADATA_TABLES {
+                let parsed = parse_table(&contents, table)
+                    .with_context(|| format!("Failed to parse [{table}] in {path:?}"))?;
+                if let Some(parsed) = parsed {
+                    return config(path, parsed).map(Some);
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Returns the directory the search for the config starts in.
+fn start_dir() -> anyhow::Result<PathBuf> {
+    Ok(match env::var_os("CARGO_MANIFEST_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => env::current_dir()?,
+    })
+}
+
+fn read(path: &Path) -> anyhow::Result<String> {
+    fs::read_to_string(path).with_context(|| format!("Failed to read {path:?}"))
+}
+
+fn config(path: PathBuf, table: Table) -> anyhow::Result<Config> {
+    let mut version = None;
+    let mut config = Config {
+        path,
+        version: String::new(),
+        sha256: None,
+        tag_sha: None,
+        mirror: None,
+        github_host: None,
+        repo: None,
+        cache_dir: None,
+        platform: None,
+    };
+    for (key, value) in table.entries {
+        match key.as_str() {
+            "version" => version = Some(value),
+            _ => config.set(&key, value, "")?,
+        }
+    }
+    let Some(version) = version else {
+        bail!(
+            "The protoc-fetcher config in {:?} doesn't set a version",
+            config.path
+        );
+    };
+    config.version = version;
+    if let Some((triple, entries)) = table
+        .targets
+        .into_iter()
+        .find(|(triple, _)| platform::host_matches_triple(triple))
+    {
+        logging::info!(
+            "Applying the [target.{triple}] settings of {:?}",
+            config.path
+        );
+        for (key, value) in entries {
+            config.set(&key, value, &triple)?;
+        }
+    }
+    logging::info!(
+        "Using protoc {} as configured in {:?}",
+        config.version,
+        config.path
+    );
+    Ok(config)
+}
+
+impl Config {
+    /// Sets the setting `key` (other than the version) read from the table of `triple`, or from
+    /// the main table if `triple` is empty.
+    fn set(&mut self, key: &str, value: String, triple: &str) -> anyhow::Result<()> {
+        match key {
+            "sha256" => self.sha256 = Some(value),
+            "tag-sha" => self.tag_sha = Some(value),
+            "mirror" => self.mirror = Some(value),
+            "github-host" => self.github_host = Some(value),
+            "repo" => self.repo = Some(value),
+            "cache-dir" => {
+                let dir = self.path.parent().unwrap_or(Path::new(""));
+                self.cache_dir = Some(dir.join(value));
+            }
+            "platform" => self.platform = Some(value),
+            _ if triple.is_empty() => {
+                bail!(
+                    "Unknown key {key:?} in the protoc-fetcher config in {:?}",
+                    self.path
+                )
+            }
+            _ => bail!(
+                "Unknown key {key:?} in [target.{triple}] of the protoc-fetcher config in {:?}",
+                self.path
+            ),
+        }
+        Ok(())
+    }
+
+    /// Returns a fetcher configured with the settings.
+    pub fn fetcher(&self) -> ProtocFetcher {
+        let mut fetcher = ProtocFetcher::new(&self.version);
+        if let Some(sha256) = &self.sha256 {
+            fetcher = fetcher.sha256(sha256);
+        }
+        if let Some(sha) = &self.tag_sha {
+            fetcher = fetcher.tag_sha(sha);
+        }
+        if let Some(mirror) = &self.mirror {
+            fetcher = fetcher.download_url_template(&install::url_template(mirror));
+        }
+        if let Some(host) = &self.github_host {
+            fetcher = fetcher.github_host(host);
+        }
+        if let Some(repo) = &self.repo {
+            fetcher = fetcher.release_repo(repo);
+        }
+        if let Some(cache_dir) = &self.cache_dir {
+            fetcher = fetcher.install_dir(cache_dir);
+        }
+        if let Some(platform) = &self.platform {
+            fetcher = fetcher.platform(platform);
+        }
+        fetcher
+    }
+}
+
+/// Returns the string-valued keys of `table` (or the top-level keys if `table` is empty) and of
+/// its `target.<triple>` sub-tables in the TOML document `contents`, or `None` if there is no
+/// such table.
+///
+/// This is far from a full TOML parser, just enough for the config: `key = "value"` lines in a
+/// `[table]`, with basic or literal strings. Other tables are skipped without being parsed, apart
+/// from multi-line strings, which could otherwise be mistaken for table headers.
+fn parse_table(contents: &str, table: &str) -> anyhow::Result<Option<Table>> {
+    let target_prefix = match table {
+        "" => TARGET_TABLE_PREFIX.to_string(),
+        table => format!("{table}.{TARGET_TABLE_PREFIX}"),
+    };
+    let mut current = String::new();
+    let mut found = table.is_empty();
+    let mut parsed = Table::default();
+    let mut in_multiline_string = false;
+    for (number, line) in contents.lines().enumerate() {
+        if (line.matches("\"\"\"").count() + line.matches("'''").count()) % 2 == 1 {
+            in_multiline_string = !in_multiline_string;
+            continue;
+        }
+        let line = line.trim();
+        if in_multiline_string || line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[') {
+            let header = header.split('#').next().unwrap_or_default().trim_end();
+            current = table_name(header.strip_suffix(']').unwrap_or(header));
+            if let Some(triple) = current.strip_prefix(&target_prefix) {
+                parsed.targets.push((triple.to_string(), Vec::new()));
+                found = true;
+            } else {
+                found |= current == table;
+            }
+            continue;
+        }
+        let entries = if current == table {
+            &mut parsed.entries
+        } else if current.starts_with(&target_prefix) {
+            match parsed.targets.last_mut() {
+                Some((_, entries)) => entries,
+                None => continue,
+            }
+        } else {
+            continue;
+        };
+        let entry = line
+            .split_once('=')
+            .and_then(|(key, value)| Some((unquote(key.trim())?, string(value.trim())?)));
+        match entry {
+            Some(entry) => entries.push(entry),
+            None => bail!(
+                "Line {}: expected `key = \"value\"`, got `{line}`",
+                number + 1
+            ),
+        }
+    }
+    Ok(found.then_some(parsed))
+}
+
+/// Normalizes a table name, e.g. `package.metadata."protoc-fetcher"` to
+/// `package.metadata.protoc-fetcher`.
+fn table_name(header: &str) -> String {
+    header
+        .split('.')
+        .map(|key| unquote(key.trim()).unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Returns `key` without the quotes of a quoted key.
+fn unquote(key: &str) -> Option<String> {
+    if key.starts_with(['"', '\'']) {
+        string(key)
+    } else {
+        Some(key.to_string()).filter(|key| !key.is_empty())
+    }
+}
+
+/// Parses a basic (`"..."`) or literal (`'...'`) string, followed by nothing but a comment.
+fn string(value: &str) -> Option<String> {
+    let quote = value.chars().next().filter(|c| matches!(c, '"' | '\''))?;
+    let mut string = String::new();
+    let mut chars = value[1..].chars();
+    loop {
+        match chars.next()? {
+            c if c == quote => break,
+            '\\' if quote == '"' => string.push(match chars.next()? {
+                'n' => '\n',
+                't' => '\t',
+                c => c,
+            }),
+            c => string.push(c),
+        }
+    }
+    let rest = chars.as_str().trim_start();
+    (rest.is_empty() || rest.starts_with('#')).then_some(string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses `contents` as a `protoc-version.toml` in `/workspace`.
+    fn parse(contents: &str) -> anyhow::Result<Config> {
+        let table = parse_table(contents, "")?.unwrap_or_default();
+        config(PathBuf::from("/workspace").join(CONFIG_FILE_NAME), table)
+    }
+
+    #[test]
+    fn reads_the_settings() {
+        let config = parse(concat!(
+            "# The protoc of the workspace.\n",
+            "version = \"21.2\"  # pinned\n",
+            "sha256 = 'abc123'\n",
+            "mirror = \"https://mirror.example/{version}/{asset}\"\n",
+            "\"repo\" = \"acme/protobuf\"\n",
+            "cache-dir = \"target/protoc\"\n",
+        ))
+        .unwrap();
+        assert_eq!(config.version, "21.2");
+        assert_eq!(config.sha256.as_deref(), Some("abc123"));
+        assert_eq!(
+            config.mirror.as_deref(),
+            Some("https://mirror.example/{version}/{asset}")
+        );
+        assert_eq!(config.repo.as_deref(), Some("acme/protobuf"));
+        assert_eq!(
+            config.cache_dir,
+            Some(PathBuf::from("/workspace/target/protoc"))
+        );
+    }
+
+    #[test]
+    fn reads_only_the_given_table() {
+        let contents = concat!(
+            "[package]\n",
+            "name = \"app\"\n",
+            "description = \"\"\"\n",
+            "[workspace.metadata.protoc-fetcher]\n",
+            "version = \"1.0\"\n",
+            "\"\"\"\n",
+            "[workspace.metadata.\"protoc-fetcher\"]  # for the build scripts\n",
+            "version = \"21.2\"\n",
+            "[dependencies]\n",
+            "anyhow = { version = \"1\" }\n",
+        );
+        let table = parse_table(contents, "workspace.metadata.protoc-fetcher")
+            .unwrap()
+            .unwrap();
+        assert_eq!(table.entries, [("version".to_string(), "21.2".to_string())]);
+        assert!(parse_table(contents, "package.metadata.protoc-fetcher")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn builder_and_env_take_precedence_over_the_file() {
+        let config =
+            parse("version = \"21.2\"\ncache-dir = \"file\"\nplatform = \"linux-x86_64\"").unwrap();
+        let fetcher = config.fetcher();
+        assert_eq!(fetcher.version, "21.2");
+        assert_eq!(fetcher.install_dir, Some(PathBuf::from("/workspace/file")));
+
+        let fetcher = fetcher.install_dir("builder");
+        assert_eq!(fetcher.install_dir, Some(PathBuf::from("builder")));
+        assert_eq!(fetcher.platform.as_deref(), Some("linux-x86_64"));
+
+        let fetcher = fetcher
+            .with_overrides(|name| match name {
+                "PROTOC_FETCHER_VERSION" => Some("22.0".to_string()),
+                "PROTOC_FETCHER_DIR" => Some("env".to_string()),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(fetcher.version, "22.0");
+        assert_eq!(fetcher.install_dir, Some(PathBuf::from("env")));
+        assert_eq!(fetcher.platform.as_deref(), Some("linux-x86_64"));
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        for contents in [
+            "version = 21.2",
+            "version = \"21.2",
+            "version = \"21.2\" trailing",
+            "version",
+            "= \"21.2\"",
+            "version = \"21.2\"\nsha256 = [\"abc\"]",
+        ] {
+            let err = parse(contents).unwrap_err();
+            assert!(
+                err.to_string().contains("expected `key = \"value\"`"),
+                "{contents:?}: {err}"
+            );
+        }
+    }
+
+    #[test]
+    fn requires_a_version() {
+        let err = parse("sha256 = \"abc123\"").unwrap_err();
+        assert!(err.to_string().contains("doesn't set a version"), "{err}");
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        let err = parse("version = \"21.2\"\nmirrors = \"https://mirror.example\"").unwrap_err();
+        assert!(err.to_string().contains("Unknown key \"mirrors\""), "{err}");
+    }
+
+    /// Returns a target triple that the host matches, as [`platform::host_matches_triple`] sees
+    /// it.
+    fn host_triple() -> String {
+        if let Some(host) = env::var("HOST").ok().filter(|host| !host.is_empty()) {
+            return host;
+        }
+        let arch = env::consts::ARCH;
+        match env::consts::OS {
+            "linux" => format!("{arch}-unknown-linux-gnu"),
+            "macos" => format!("{arch}-apple-darwin"),
+            "windows" => format!("{arch}-pc-windows-msvc"),
+            os => format!("{arch}-unknown-{os}"),
+        }
+    }
+
+    #[test]
+    fn applies_the_table_of_a_matching_target() {
+        let config = parse(&format!(
+            "version = \"21.2\"\nmirror = \"https://mirror.example\"\ncache-dir = \"protoc\"\n\
+             [target.{}]\nmirror = \"https://host.example\"\n",
+            host_triple()
+        ))
+        .unwrap();
+        assert_eq!(config.mirror.as_deref(), Some("https://host.example"));
+        assert_eq!(config.cache_dir, Some(PathBuf::from("/workspace/protoc")));
+    }
+
+    #[test]
+    fn ignores_the_tables_of_other_targets() {
+        let config = parse(concat!(
+            "version = \"21.2\"\n",
+            "mirror = \"https://mirror.example\"\n",
+            "[target.sparc64-unknown-netbsd]\n",
+            "mirror = \"https://netbsd.example\"\n",
+        ))
+        .unwrap();
+        assert_eq!(config.mirror.as_deref(), Some("https://mirror.example"));
+    }
+
+    #[test]
+    fn rejects_unknown_keys_in_target_tables() {
+        let err = parse(&format!(
+            "version = \"21.2\"\n[target.{}]\nmirrors = \"https://host.example\"\n",
+            host_triple()
+        ))
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("Unknown key \"mirrors\" in [target."),
+            "{err}"
+        );
+    }
+}
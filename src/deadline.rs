@@ -0,0 +1,160 @@
+//! The overall time limit of a fetch set with [`ProtocFetcher::deadline`], and the cancellation of
+//! fetches made with [`ProtocFetcher::fetch_async`] whose future is dropped.
+//!
+//! A fetch checks whether it should stop before every request and every buffer's worth of a
+//! download, and the timeout of each request is cut short to the time left, so that a stalled
+//! download fails by the deadline rather than after the full
+//! [`download_timeout`](ProtocFetcher::download_timeout).
+
+use crate::error::Error;
+use crate::fetcher::ProtocFetcher;
+#[cfg(feature = "async")]
+use std::future::Future;
+#[cfg(feature = "async")]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "async")]
+use std::sync::{Arc, Mutex, PoisonError};
+#[cfg(feature = "async")]
+use std::task::{Poll, Waker};
+use std::time::{Duration, Instant};
+
+/// The state of a running fetch, cloned along with its fetcher: when it runs out of time, and
+/// whether it was cancelled.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Cancellation {
+    /// When the fetch started, and how long it may take.
+    started: Option<(Instant, Option<Duration>)>,
+    #[cfg(feature = "async")]
+    flag: Option<Arc<Flag>>,
+}
+
+/// Set when the future of an async fetch is dropped.
+#[cfg(feature = "async")]
+#[derive(Debug, Default)]
+struct Flag {
+    cancelled: AtomicBool,
+    /// The task waiting on a request of the fetch, to wake so that it notices the cancellation.
+    waker: Mutex<Option<Waker>>,
+}
+
+impl Cancellation {
+    /// Fails if the fetch has run out of time or was cancelled.
+    pub(crate) fn check(&self) -> Result<(), Error> {
+        #[cfg(feature = "async")]
+        if let Some(flag) = &self.flag {
+            if flag.cancelled.load(Ordering::SeqCst) {
+                return Err(Error::Cancelled);
+            }
+        }
+        match self.started {
+            Some((started, Some(deadline))) if started.elapsed() >= deadline => {
+                Err(Error::DeadlineExceeded { deadline })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns `duration`, or the time left until the deadline if that is shorter.
+    pub(crate) fn clamp(&self, duration: Duration) -> Duration {
+        match self.started {
+            Some((started, Some(deadline))) => {
+                duration.min(deadline.saturating_sub(started.elapsed()))
+            }
+            _ => duration,
+        }
+    }
+
+    /// Awaits `future`, unless the fetch is cancelled first.
+    #[cfg(feature = "async")]
+    pub(crate) async fn unless_cancelled<F: Future>(&self, future: F) -> Result<F::Output, Error> {
+        let mut future = std::pin::pin!(future);
+        std::future::poll_fn(|cx| {
+            // Registering before checking makes sure a cancellation in between still wakes us.
+            if let Some(flag) = &self.flag {
+                *flag.waker.lock().unwrap_or_else(PoisonError::into_inner) =
+                    Some(cx.waker().clone());
+            }
+            if let Err(err) = self.check() {
+                return Poll::Ready(Err(err));
+            }
+            future.as_mut().poll(cx).map(Ok)
+        })
+        .await
+    }
+}
+
+/// Cancels the fetch it was created for when dropped, unless [`disarm`](Self::disarm)ed first.
+#[cfg(feature = "async")]
+pub(crate) struct CancelOnDrop {
+    flag: Option<Arc<Flag>>,
+}
+
+#[cfg(feature = "async")]
+impl CancelOnDrop {
+    /// Returns the guard along with the fetcher to make the fetch with.
+    pub(crate) fn new(fetcher: &ProtocFetcher) -> (Self, ProtocFetcher) {
+        let flag = Arc::new(Flag::default());
+        let fetcher = ProtocFetcher {
+            cancellation: Cancellation {
+                flag: Some(flag.clone()),
+                ..fetcher.cancellation.clone()
+            },
+            ..fetcher.clone()
+        };
+        (CancelOnDrop { flag: Some(flag) }, fetcher)
+    }
+
+    /// Lets the fetch be, as it has finished.
+    pub(crate) fn disarm(mut self) {
+        self.flag = None;
+    }
+}
+
+#[cfg(feature = "async")]
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        if let Some(flag) = &self.flag {
+            flag.cancelled.store(true, Ordering::SeqCst);
+            if let Some(waker) = flag
+                .waker
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .take()
+            {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// Runs the fetch `fetch` with the fetcher's deadline counting from now, unless it already is
+/// (i.e. this is part of a fetch that is running). If the fetch fails after running out of time
+/// or being cancelled, the error says so, with the failure it caused (e.g. a timed out request) as
+/// the cause.
+pub(crate) fn run<T>(
+    fetcher: &ProtocFetcher,
+    fetch: impl FnOnce(&ProtocFetcher) -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    if fetcher.cancellation.started.is_some() {
+        return fetch(fetcher);
+    }
+    let mut fetcher = fetcher.clone();
+    fetcher.cancellation.started = Some((Instant::now(), fetcher.deadline));
+    fetch(&fetcher).map_err(|err| {
+        let stopped = matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::DeadlineExceeded { .. } | Error::Cancelled)
+        );
+        match fetcher.cancellation.check() {
+            Err(stop) if !stopped => err.context(stop),
+            _ => err,
+        }
+    })
+}
+
+/// Returns the timeout of a request: the fetcher's
+/// [`download_timeout`](ProtocFetcher::download_timeout), or the time left until the deadline if
+/// that is shorter.
+pub(crate) fn request_timeout(fetcher: &ProtocFetcher) -> Duration {
+    fetcher.cancellation.clamp(fetcher.download_timeout)
+}
@@ -0,0 +1,185 @@
+//! Parsing protoc's error output.
+
+use std::fmt;
+
+/// How serious a [`Diagnostic`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// An error, which makes protoc fail. Protoc doesn't label these, so when it fails, this is
+    /// the severity of any message not labelled as a warning.
+    Error,
+    /// A warning, e.g. about an unused import. When protoc succeeds, all its messages are
+    /// warnings.
+    Warning,
+}
+
+/// One message from protoc's error output, e.g. `foo.proto:12:3: Expected ";".`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The proto the message is about, as protoc names it, if any. Messages about the invocation
+    /// itself (e.g. a missing plugin) have none.
+    pub file: Option<String>,
+    /// The 1-based line within `file`, if the message points at one.
+    pub line: Option<u32>,
+    /// The 1-based column within `line`, if the message points at one.
+    pub column: Option<u32>,
+    /// Whether the message is an error or a warning.
+    pub severity: Severity,
+    /// The message itself, without the location and severity prefixes.
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// Parses protoc's stderr (in its default, GCC-like format) into one diagnostic per line.
+    /// Lines that don't look like a located message are kept as diagnostics without a file.
+    pub(crate) fn parse_all(stderr: &str) -> Vec<Diagnostic> {
+        stderr
+            .lines()
+            .map(str::trim_end)
+            .filter(|line| !line.is_empty())
+            .map(Diagnostic::parse)
+            .collect()
+    }
+
+    fn parse(line: &str) -> Diagnostic {
+        let (file, line_number, column, rest) = match split_location(line) {
+            Some((file, line_number, column, rest)) => {
+                (Some(file), Some(line_number), Some(column), rest)
+            }
+            // Messages about a file as a whole, e.g. `foo.proto: File not found.`
+            None => match line.split_once(": ") {
+                Some((file, rest)) if file.ends_with(".proto") => (Some(file), None, None, rest),
+                _ => (None, None, None, line),
+            },
+        };
+        let (severity, message) = match rest.strip_prefix("warning: ") {
+            Some(message) => (Severity::Warning, message),
+            None => (Severity::Error, rest),
+        };
+        Diagnostic {
+            file: file.map(str::to_string),
+            line: line_number,
+            column,
+            severity,
+            message: message.to_string(),
+        }
+    }
+}
+
+/// Splits `<file>:<line>:<column>: <rest>` into its parts. The file name may contain colons
+/// itself (e.g. Windows drive letters), so the first `:<line>:<column>: ` wins.
+fn split_location(line: &str) -> Option<(&str, u32, u32, &str)> {
+    line.match_indices(':').find_map(|(i, _)| {
+        let (line_number, rest) = line[i + 1..].split_once(':')?;
+        let (column, rest) = rest.split_once(": ")?;
+        Some((
+            &line[..i],
+            line_number.parse().ok()?,
+            column.parse().ok()?,
+            rest,
+        ))
+    })
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{file}:")?;
+            if let (Some(line), Some(column)) = (self.line, self.column) {
+                write!(f, "{line}:{column}:")?;
+            }
+            write!(f, " ")?;
+        }
+        if self.severity == Severity::Warning {
+            write!(f, "warning: ")?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_located_messages() {
+        let diagnostics = Diagnostic::parse_all(
+            "foo.proto:12:3: Expected \";\".\n\
+             foo.proto:1:1: warning: Import bar.proto is unused.\n",
+        );
+        assert_eq!(
+            diagnostics,
+            [
+                Diagnostic {
+                    file: Some("foo.proto".to_string()),
+                    line: Some(12),
+                    column: Some(3),
+                    severity: Severity::Error,
+                    message: "Expected \";\".".to_string(),
+                },
+                Diagnostic {
+                    file: Some("foo.proto".to_string()),
+                    line: Some(1),
+                    column: Some(1),
+                    severity: Severity::Warning,
+                    message: "Import bar.proto is unused.".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_messages_about_a_whole_file() {
+        let [diagnostic] = &Diagnostic::parse_all("protos/foo.proto: File not found.")[..] else {
+            panic!("expected one diagnostic");
+        };
+        assert_eq!(diagnostic.file.as_deref(), Some("protos/foo.proto"));
+        assert_eq!((diagnostic.line, diagnostic.column), (None, None));
+        assert_eq!(diagnostic.message, "File not found.");
+    }
+
+    #[test]
+    fn keeps_unlocated_lines_without_a_file() {
+        let [diagnostic] = &Diagnostic::parse_all("--foo_out: protoc-gen-foo: Plugin failed.")[..]
+        else {
+            panic!("expected one diagnostic");
+        };
+        assert_eq!(diagnostic.file, None);
+        assert_eq!(diagnostic.severity, Severity::Error);
+        assert_eq!(
+            diagnostic.message,
+            "--foo_out: protoc-gen-foo: Plugin failed."
+        );
+    }
+
+    #[test]
+    fn keeps_colons_in_file_names() {
+        let [diagnostic] =
+            &Diagnostic::parse_all(r"C:\protos\foo.proto:4:10: Expected type name.")[..]
+        else {
+            panic!("expected one diagnostic");
+        };
+        assert_eq!(diagnostic.file.as_deref(), Some(r"C:\protos\foo.proto"));
+        assert_eq!((diagnostic.line, diagnostic.column), (Some(4), Some(10)));
+        assert_eq!(diagnostic.message, "Expected type name.");
+    }
+
+    #[test]
+    fn skips_blank_lines_and_trailing_whitespace() {
+        let diagnostics = Diagnostic::parse_all("\nfoo.proto:2:1: Oops.  \r\n\n");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "Oops.");
+    }
+
+    #[test]
+    fn displays_as_protoc_prints_it() {
+        for line in [
+            "foo.proto:12:3: Expected \";\".",
+            "foo.proto:1:1: warning: Import bar.proto is unused.",
+            "foo.proto: File not found.",
+            "Missing output directives.",
+        ] {
+            assert_eq!(Diagnostic::parse_all(line)[0].to_string(), line);
+        }
+    }
+}
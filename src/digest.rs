@@ -0,0 +1,40 @@
+//! SHA-256 digests of downloaded files.
+
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, ErrorKind, Read};
+use std::path::Path;
+
+/// Computes the hex-encoded SHA-256 digest of the file at `path`, reading it in chunks of
+/// `buffer_size` bytes.
+pub(crate) fn sha256_file(path: &Path, buffer_size: usize) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; buffer_size];
+    loop {
+        match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => hasher.update(&buf[..n]),
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// Computes the hex-encoded SHA-256 digest of `bytes`.
+pub(crate) fn sha256_bytes(bytes: &[u8]) -> String {
+    to_hex(&Sha256::digest(bytes))
+}
+
+/// Returns the digest in the contents of a checksum file, as written by `sha256sum`: the hex
+/// digest, optionally followed by whitespace and the file name.
+pub(crate) fn parse_checksum_file(contents: &str) -> Option<&str> {
+    let digest = contents.split_whitespace().next()?;
+    let is_sha256 = digest.len() == 64 && digest.bytes().all(|byte| byte.is_ascii_hexdigit());
+    is_sha256.then_some(digest)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
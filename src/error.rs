@@ -0,0 +1,239 @@
+//! The failures of fetching that callers may want to tell apart.
+
+use crate::compile::ProtocFailure;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// A failure to fetch protoc (or a plugin), or to run it, as returned by every fallible function
+/// of the crate. The variants other than [`Other`](Error::Other) are the failures that callers may
+/// want to handle differently, e.g. by retrying later, falling back to a system protoc, or
+/// reporting a broken mirror.
+///
+/// ```no_run
+/// use protoc_fetcher::{Error, ProtocFetcher};
+///
+/// match ProtocFetcher::new("21.2").fetch() {
+///     Ok(protoc) => println!("protoc is at {:?}", protoc.path()),
+///     Err(Error::Network { .. }) => println!("cargo:warning=offline, using the system protoc"),
+///     Err(err) => panic!("{err:#}"),
+/// }
+/// ```
+///
+/// It converts into an [`anyhow::Error`] with `?`, so build scripts returning `anyhow::Result`
+/// work as before. Conversely, `Error::from` an [`anyhow::Error`] (e.g. from a
+/// [post-install hook](crate::ProtocFetcher::post_install)) is the `Error` within it, if there is
+/// one, and [`Other`](Error::Other) otherwise.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// A request failed without a response, or the response was cut off, e.g. because the
+    /// server can't be reached or took too long.
+    Network {
+        url: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    /// A request got a response with a status other than 200 OK, e.g. 404 for a version that
+    /// doesn't exist.
+    HttpStatus {
+        url: String,
+        status: u16,
+        /// The body of the response, which may explain the status.
+        body: String,
+    },
+    /// A downloaded archive couldn't be extracted, e.g. because it is corrupt.
+    Extraction {
+        archive: PathBuf,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    /// A release archive doesn't contain the binary it should, e.g. because a mirror serves
+    /// something else under the release's name.
+    MissingBinary { archive: PathBuf, binary: String },
+    /// An archive has an entry whose path leads outside of the directory it is extracted into,
+    /// i.e. is absolute, has `..` components or goes through a symlink, or a link to something
+    /// outside of it. Such an archive is either corrupt or malicious, which matters for archives
+    /// from mirrors rather than GitHub.
+    UnsafeEntry { archive: PathBuf, entry: String },
+    /// An archive decompresses to more than the
+    /// [limit](crate::ProtocFetcher::max_extracted_size), e.g. because it is a zip bomb.
+    ArchiveTooLarge { archive: PathBuf, limit: u64 },
+    /// A release's binary doesn't work, e.g. because it was built for another platform.
+    BrokenBinary { path: PathBuf, reason: String },
+    /// A release's binary doesn't run because the system uses musl rather than glibc (e.g. Alpine
+    /// Linux), and the official Linux releases are linked against glibc.
+    Musl { path: PathBuf },
+    /// A release's binary doesn't run on macOS and has the `com.apple.quarantine` attribute, so
+    /// Gatekeeper presumably blocked it; see
+    /// [`strip_quarantine`](crate::ProtocFetcher::strip_quarantine).
+    Quarantined { path: PathBuf },
+    /// A protoc is older than needed; see [`ensure_min_version`](crate::ensure_min_version).
+    TooOld {
+        path: PathBuf,
+        version: String,
+        required: String,
+    },
+    /// There are no releases for the platform.
+    UnsupportedPlatform { os: String, arch: String },
+    /// A download doesn't have the expected SHA-256 digest.
+    Verification {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+    /// [Strict checksums](crate::ProtocFetcher::strict_checksums) are on, but no digest is known
+    /// for the release archive, neither built into the crate nor given to
+    /// [`sha256`](crate::ProtocFetcher::sha256).
+    UnknownDigest { version: String, platform: String },
+    /// A release archive's provenance was rejected by its
+    /// [attestation policy](crate::ProtocFetcher::verify_attestations): its attestation doesn't
+    /// verify, or it has none that can be checked and verification is required.
+    Provenance { path: PathBuf, reason: String },
+    /// The release tag doesn't point at the SHA pinned with
+    /// [`tag_sha`](crate::ProtocFetcher::tag_sha), i.e. it has been moved or re-created.
+    TagMoved {
+        tag: String,
+        expected: String,
+        actual: String,
+    },
+    /// Another process is installing the same release, and the [`LockWait`](crate::LockWait)
+    /// policy says not to wait (any longer).
+    Locked { path: PathBuf, waited: Duration },
+    /// A download was needed, but the fetcher is in
+    /// [offline mode](crate::ProtocFetcher::offline).
+    Offline { url: String },
+    /// The fetch didn't finish within its [`deadline`](crate::ProtocFetcher::deadline), e.g.
+    /// because a download stalled.
+    DeadlineExceeded { deadline: Duration },
+    /// The fetch was abandoned because the future of
+    /// [`fetch_async`](crate::ProtocFetcher::fetch_async) was dropped.
+    Cancelled,
+    /// Protoc ran but exited unsuccessfully, e.g. because the protos don't compile.
+    ProtocFailed(ProtocFailure),
+    /// Any other failure, e.g. an I/O error in the install directory, with the context of where
+    /// it happened. Like an [`anyhow::Error`], it shows its causes when formatted with `{:#}`.
+    Other { source: anyhow::Error },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Network { url, .. } => write!(f, "Error downloading {url}"),
+            Error::HttpStatus { url, status, body } => {
+                write!(f, "Error downloading {url}: {status} {}", body.trim())
+            }
+            Error::Extraction { archive, .. } => write!(f, "Failed to extract {archive:?}"),
+            Error::MissingBinary { archive, binary } => {
+                write!(f, "{archive:?} does not contain {binary}")
+            }
+            Error::UnsafeEntry { archive, entry } => write!(
+                f,
+                "Refusing to extract entry {entry:?} of {archive:?}: it leads outside of the \
+                 directory it is extracted into"
+            ),
+            Error::ArchiveTooLarge { archive, limit } => write!(
+                f,
+                "Refusing to extract {archive:?}: it decompresses to more than {limit} bytes (see \
+                 ProtocFetcher::max_extracted_size)"
+            ),
+            Error::BrokenBinary { path, reason } => {
+                write!(f, "Extracted {path:?}, but it can't be run: {reason}")
+            }
+            Error::Musl { path } => write!(
+                f,
+                "Extracted {path:?}, but it is linked against glibc and can't run on this \
+                 musl-based system (e.g. Alpine Linux); install protoc with the system's package \
+                 manager (e.g. `apk add protobuf-dev`) and use ProtocFetcher::system_protoc, \
+                 install glibc compatibility (e.g. `apk add gcompat`), or build protoc from source \
+                 with ProtocFetcher::build_from_source (`build-from-source` feature)"
+            ),
+            Error::Quarantined { path } => write!(
+                f,
+                "{path:?} was blocked by macOS Gatekeeper, as it has the com.apple.quarantine \
+                 attribute; remove it with `xattr -d com.apple.quarantine {}`",
+                path.display()
+            ),
+            Error::TooOld {
+                path,
+                version,
+                required,
+            } => write!(
+                f,
+                "{path:?} is protoc {version}, but {required} or newer is needed"
+            ),
+            Error::UnsupportedPlatform { os, arch } => {
+                write!(f, "No releases are published for {os}, {arch}")
+            }
+            Error::Verification {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "SHA-256 digest of {path:?} is {actual}, but {expected} was expected"
+            ),
+            Error::UnknownDigest { version, platform } => write!(
+                f,
+                "Strict checksums are on, but no SHA-256 digest is known for the protoc \
+                 {version} release archive for {platform}; pass it to ProtocFetcher::sha256"
+            ),
+            Error::Provenance { path, reason } => {
+                write!(f, "Failed to verify the provenance of {path:?}: {reason}")
+            }
+            Error::TagMoved {
+                tag,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "The release tag {tag} is at {actual}, not at the pinned {expected}; it has been \
+                 moved or re-created"
+            ),
+            Error::Locked { path, waited } if waited.is_zero() => {
+                write!(f, "{path:?} is being installed by another process")
+            }
+            Error::Locked { path, waited } => write!(
+                f,
+                "Timed out after {waited:?} waiting for another process to install {path:?}"
+            ),
+            Error::Offline { url } => write!(
+                f,
+                "Offline mode is on, so {url} can't be downloaded; pre-populate the install \
+                 directory or vendor the release archive"
+            ),
+            Error::DeadlineExceeded { deadline } => {
+                write!(
+                    f,
+                    "The fetch did not finish within its deadline of {deadline:?}"
+                )
+            }
+            Error::Cancelled => write!(f, "The fetch was cancelled"),
+            Error::ProtocFailed(failure) => failure.fmt(f),
+            Error::Other { source } if f.alternate() => write!(f, "{source:#}"),
+            Error::Other { source } => write!(f, "{source}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Network { source, .. } | Error::Extraction { source, .. } => Some(&**source),
+            // The anyhow error stands in for this one, so its first cause is this one's.
+            Error::Other { source } => source.source(),
+            _ => None,
+        }
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(err: anyhow::Error) -> Self {
+        let err = match err.downcast::<Error>() {
+            Ok(err) => return err,
+            Err(err) => err,
+        };
+        match err.downcast::<ProtocFailure>() {
+            Ok(failure) => Error::ProtocFailed(failure),
+            Err(source) => Error::Other { source },
+        }
+    }
+}
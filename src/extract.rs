@@ -0,0 +1,1221 @@
+//! Extraction of release archives into an install directory.
+//!
+//! Extraction never holds an entry's contents in memory: files are streamed from the archive to
+//! disk through a fixed-size buffer, and the only per-entry state that is kept around (names and
+//! link targets) is small and bounded. Peak memory therefore doesn't grow with the size of the
+//! archive or of its entries, and there is nothing that would need to be spilled to disk.
+
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+
+use crate::digest;
+use crate::error::Error;
+use crate::logging;
+use crate::long_path;
+use crate::stream::{self, CopyError};
+use anyhow::{bail, Context};
+use flate2::read::GzDecoder;
+use std::fs;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// The modification time given to every file in an install: 1980-01-01T00:00:00Z, the earliest
+/// timestamp a zip archive can record.
+const NORMALIZED_MTIME: Duration = Duration::from_secs(315_532_800);
+
+/// The maximum length of a symlink target read from a zip archive, where the target is stored as
+/// the entry's contents. Longer targets aren't valid paths on any supported platform, and reading
+/// them unbounded would let a malicious archive make us buffer arbitrary amounts of data.
+const MAX_LINK_TARGET_LEN: u64 = 4096;
+
+/// The entry a [`ArchiveFormat::RawBinary`] download is installed as.
+const RAW_BINARY_ENTRY: &str = "bin/protoc";
+
+/// The formats of release archives; see
+/// [`ProtocFetcher::archive_format`](crate::ProtocFetcher::archive_format).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    /// A zip archive, as the official releases are.
+    Zip,
+    /// A gzipped tarball.
+    TarGz,
+    /// A zstd-compressed tarball. Requires the `zstd` feature.
+    TarZst,
+    /// An xz-compressed tarball. Requires the `xz` feature.
+    TarXz,
+    /// The protoc binary itself rather than an archive, installed as `bin/protoc`. Such installs
+    /// have no `include` tree.
+    RawBinary,
+}
+
+impl ArchiveFormat {
+    /// Returns the file extension of assets in this format, e.g. `.tar.gz`, or an empty string
+    /// for raw binaries.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => ".zip",
+            ArchiveFormat::TarGz => ".tar.gz",
+            ArchiveFormat::TarZst => ".tar.zst",
+            ArchiveFormat::TarXz => ".tar.xz",
+            ArchiveFormat::RawBinary => "",
+        }
+    }
+
+    /// Detects the format of the archive at `path` from its first bytes, falling back to its file
+    /// extension if they aren't conclusive. Executables (ELF, Mach-O and PE) are raw binaries.
+    fn detect(path: &Path) -> anyhow::Result<Self> {
+        let mut magic = [0; 6];
+        let mut file =
+            File::open(path).with_context(|| format!("Failed to open archive {path:?}"))?;
+        let mut len = 0;
+        while len < magic.len() {
+            match file.read(&mut magic[len..])? {
+                0 => break,
+                n => len += n,
+            }
+        }
+
+        match &magic[..len] {
+            [b'P', b'K', 3, 4, ..] | [b'P', b'K', 5, 6, ..] => return Ok(ArchiveFormat::Zip),
+            [0x1f, 0x8b, ..] => return Ok(ArchiveFormat::TarGz),
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => return Ok(ArchiveFormat::TarZst),
+            [0xfd, b'7', b'z', b'X', b'Z', 0] => return Ok(ArchiveFormat::TarXz),
+            [0x7f, b'E', b'L', b'F', ..]
+            | [0xcf | 0xce, 0xfa, 0xed, 0xfe, ..]
+            | [0xca, 0xfe, 0xba, 0xbe, ..]
+            | [b'M', b'Z', ..] => return Ok(ArchiveFormat::RawBinary),
+            _ => {}
+        }
+        let name = path.to_string_lossy();
+        if name.ends_with(".zip") {
+            Ok(ArchiveFormat::Zip)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Ok(ArchiveFormat::TarGz)
+        } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Ok(ArchiveFormat::TarZst)
+        } else if name.ends_with(".tar.xz") || name.ends_with(".txz") {
+            Ok(ArchiveFormat::TarXz)
+        } else {
+            bail!("Unrecognized archive format: {path:?}")
+        }
+    }
+}
+
+/// Options controlling how an archive is extracted.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ExtractOptions {
+    /// The size of the buffers used to stream entries to disk.
+    pub buffer_size: usize,
+    /// The number of leading path components to strip from every entry name, or `None` to strip
+    /// a single top-level directory if the archive has one (see [`detect_strip_components`]).
+    pub strip_components: Option<usize>,
+    /// The format of the archive, or `None` to detect it.
+    pub format: Option<ArchiveFormat>,
+    /// The most bytes the extraction may write, over all entries.
+    pub max_size: u64,
+}
+
+/// Extracts the entries of the archive at `archive_path` for which `filter` returns true into
+/// `dest_dir`. Zip archives and gzipped tarballs are supported, as well as zstd- and xz-compressed
+/// tarballs if the `zstd` and `xz` features are enabled; unless set in `options`, the format is
+/// detected automatically. A raw binary is extracted as a single `bin/protoc` entry.
+///
+/// The filter is given the entry name after stripping leading path components as configured in
+/// `options`, e.g. "bin/protoc". Entries that are stripped away entirely are skipped.
+///
+/// Archives that aren't GitHub's may be corrupt or malicious, so extraction fails with
+/// [`Error::UnsafeEntry`] on an entry that would end up outside of `dest_dir` (or be written
+/// through a symlink), or a link to something outside of it, and with
+/// [`Error::ArchiveTooLarge`] once it would write more than `options.max_size` bytes; any other
+/// failure is an [`Error::Extraction`].
+pub(crate) fn extract_archive(
+    archive_path: &Path,
+    dest_dir: &Path,
+    options: &ExtractOptions,
+    filter: impl Fn(&str) -> bool,
+) -> anyhow::Result<()> {
+    extract(archive_path, dest_dir, options, filter).map_err(|err| {
+        if matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::UnsafeEntry { .. } | Error::ArchiveTooLarge { .. })
+        ) {
+            return err;
+        }
+        Error::Extraction {
+            archive: archive_path.to_path_buf(),
+            source: err.into(),
+        }
+        .into()
+    })
+}
+
+fn extract(
+    archive_path: &Path,
+    dest_dir: &Path,
+    options: &ExtractOptions,
+    filter: impl Fn(&str) -> bool,
+) -> anyhow::Result<()> {
+    let dest_dir = &long_path::extended(dest_dir);
+    let format = match options.format {
+        Some(format) => format,
+        None => ArchiveFormat::detect(archive_path)?,
+    };
+    if format == ArchiveFormat::RawBinary {
+        if filter(RAW_BINARY_ENTRY) {
+            let size = fs::metadata(archive_path)
+                .with_context(|| format!("Failed to read {archive_path:?}"))?
+                .len();
+            if size > options.max_size {
+                return Err(too_large(archive_path, options.max_size));
+            }
+            let out_path = long_path::join(dest_dir, RAW_BINARY_ENTRY);
+            prepare_out_path(archive_path, dest_dir, RAW_BINARY_ENTRY, &out_path)?;
+            fs::copy(archive_path, &out_path)
+                .with_context(|| format!("Failed to copy {archive_path:?} to {out_path:?}"))?;
+        }
+        return Ok(());
+    }
+    let strip_components = match options.strip_components {
+        Some(strip_components) => strip_components,
+        None => detect_strip_components(&entry_names(archive_path, format, options)?),
+    };
+    let mut extractor = Extractor {
+        archive_path,
+        dest_dir,
+        buffer_size: options.buffer_size,
+        strip_components,
+        filter: &filter,
+        links: Vec::new(),
+        max_size: options.max_size,
+        extracted: 0,
+    };
+    fs::create_dir_all(dest_dir)
+        .with_context(|| format!("Failed to create directory {dest_dir:?}"))?;
+
+    match format {
+        ArchiveFormat::Zip => extractor.extract_zip()?,
+        _ => extractor.extract_tar(open_tar(archive_path, format, options.buffer_size)?)?,
+    }
+    extractor.create_links()
+}
+
+/// Opens the tarball at `archive_path` for reading, decompressing it according to `format`.
+fn open_tar(
+    archive_path: &Path,
+    format: ArchiveFormat,
+    buffer_size: usize,
+) -> anyhow::Result<Box<dyn Read>> {
+    let archive_file = File::open(archive_path)
+        .with_context(|| format!("Failed to open archive {archive_path:?}"))?;
+    let archive_file = BufReader::with_capacity(buffer_size, archive_file);
+    Ok(match format {
+        ArchiveFormat::Zip | ArchiveFormat::RawBinary => {
+            bail!("{archive_path:?} is not a tarball")
+        }
+        ArchiveFormat::TarGz => Box::new(GzDecoder::new(archive_file)),
+        #[cfg(feature = "zstd")]
+        ArchiveFormat::TarZst => Box::new(zstd::Decoder::with_buffer(archive_file)?),
+        #[cfg(not(feature = "zstd"))]
+        ArchiveFormat::TarZst => {
+            bail!("{archive_path:?} is zstd-compressed; enable the `zstd` feature to extract it")
+        }
+        #[cfg(feature = "xz")]
+        ArchiveFormat::TarXz => Box::new(xz2::read::XzDecoder::new(archive_file)),
+        #[cfg(not(feature = "xz"))]
+        ArchiveFormat::TarXz => {
+            bail!("{archive_path:?} is xz-compressed; enable the `xz` feature to extract it")
+        }
+    })
+}
+
+/// Lists the names of all entries in the archive at `archive_path`, without any leading "./".
+fn entry_names(
+    archive_path: &Path,
+    format: ArchiveFormat,
+    options: &ExtractOptions,
+) -> anyhow::Result<Vec<String>> {
+    let mut names = Vec::new();
+    if format == ArchiveFormat::Zip {
+        let archive_file = File::open(archive_path)
+            .with_context(|| format!("Failed to open archive {archive_path:?}"))?;
+        let archive =
+            zip::ZipArchive::new(BufReader::with_capacity(options.buffer_size, archive_file))
+                .with_context(|| format!("Failed to read zip archive {archive_path:?}"))?;
+        for name in archive.file_names() {
+            let name =
+                name.with_context(|| format!("Failed to read entry name in {archive_path:?}"))?;
+            names.push(name.into_owned());
+        }
+    } else {
+        let mut archive = tar::Archive::new(open_tar(archive_path, format, options.buffer_size)?);
+        for entry in archive.entries().context("Failed to read tarball")? {
+            let entry = entry.context("Failed to read tarball entry")?;
+            let path = entry.path().context("Failed to read tarball entry path")?;
+            names.push(path.to_string_lossy().trim_start_matches("./").to_string());
+        }
+    }
+    Ok(names)
+}
+
+/// Determines how many leading path components to strip from the entries of an archive with the
+/// given entry names: one if every entry is inside the same top-level directory (as happens when
+/// a mirror repackages a release into e.g. `protoc-21.2/bin/protoc`), zero otherwise.
+///
+/// A lone top-level `bin` or `include` directory is part of the expected layout rather than a
+/// wrapper, so it is never stripped.
+fn detect_strip_components(names: &[String]) -> usize {
+    let mut top_level_dir = None;
+    for name in names.iter().filter(|name| !name.is_empty()) {
+        let Some((first, _)) = name.split_once('/') else {
+            // A file at the top level.
+            return 0;
+        };
+        match top_level_dir {
+            None => top_level_dir = Some(first),
+            Some(dir) if dir == first => {}
+            Some(_) => return 0,
+        }
+    }
+    match top_level_dir {
+        Some("bin" | "include") | None => 0,
+        Some(_) => 1,
+    }
+}
+
+/// Strips the first `n` components from the '/'-separated entry `name`, returning `None` if
+/// nothing remains.
+fn strip_components(name: &str, n: usize) -> Option<&str> {
+    let mut rest = name;
+    for _ in 0..n {
+        rest = rest.split_once('/')?.1;
+    }
+    (!rest.is_empty()).then_some(rest)
+}
+
+/// Checks that the '/'-separated entry `name` stays within the directory it is extracted into,
+/// i.e. it is relative and has no `..` components, and converts it into a path made up of its
+/// normal components only, which can be joined onto an extended-length path.
+fn enclosed_path(name: &str) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+    for component in Path::new(name).components() {
+        match component {
+            Component::Normal(component) => path.push(component),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(path)
+}
+
+/// Checks whether the '/'-separated entry `name` can be created on Windows, returning what's wrong
+/// with it if not: characters that NTFS doesn't allow, or components that are reserved device
+/// names like `con` or `aux` (with or without an extension). Such entries are skipped, rather than
+/// failing the whole extraction.
+fn windows_name_problem(name: &str) -> Option<&'static str> {
+    const RESERVED: &[&str] = &[
+        "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+        "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+    ];
+
+    for component in name.split(['/', '\\']).filter(|c| !c.is_empty()) {
+        if component
+            .chars()
+            .any(|c| matches!(c, '<' | '>' | ':' | '"' | '|' | '?' | '*') || c.is_control())
+        {
+            return Some("its name contains characters that are not allowed");
+        }
+        if component.ends_with(['.', ' ']) && component != "." && component != ".." {
+            return Some("its name ends with a dot or space, which is not allowed");
+        }
+        let stem = component.split('.').next().unwrap_or_default().trim_end();
+        if RESERVED
+            .iter()
+            .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+        {
+            return Some("its name is reserved");
+        }
+    }
+    None
+}
+
+/// Resolves the target of a link entry extracted to `link_path` to the path it refers to, returning
+/// `None` if that would be outside of `dest_dir`. Symlink targets are relative to the directory
+/// containing the link, hard link targets are relative to `dest_dir`.
+///
+/// The resolution is lexical, so a `..` after a component that is itself a symlink (e.g. `s/..`
+/// where `s` links to `..`) isn't resolved the way the file system will. It only weeds out targets
+/// that are obviously outside; what they end up referring to is checked with [`contained`].
+fn resolve_link(dest_dir: &Path, link_path: &Path, target: &Path, hard: bool) -> Option<PathBuf> {
+    let mut resolved = PathBuf::new();
+    if !hard {
+        resolved.push(link_path.parent()?.strip_prefix(dest_dir).ok()?);
+    }
+    for component in target.components() {
+        match component {
+            Component::Normal(name) => resolved.push(name),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !resolved.pop() {
+                    return None;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(dest_dir.join(resolved))
+}
+
+/// Returns whether `path` exists and, with all symlinks along it followed, is within the directory
+/// whose canonical path is `canonical_dest_dir`.
+fn contained(canonical_dest_dir: &Path, path: &Path) -> bool {
+    fs::canonicalize(path).is_ok_and(|path| path.starts_with(canonical_dest_dir))
+}
+
+/// A link entry whose creation is deferred until all regular entries have been extracted.
+struct Link {
+    /// The entry name, for messages.
+    name: String,
+    /// Where the link is extracted to.
+    path: PathBuf,
+    /// The link target as recorded in the archive (after stripping components, for hard links).
+    target: PathBuf,
+    hard: bool,
+}
+
+/// The state shared by the extraction of all entries of one archive.
+struct Extractor<'a> {
+    archive_path: &'a Path,
+    dest_dir: &'a Path,
+    buffer_size: usize,
+    strip_components: usize,
+    filter: &'a dyn Fn(&str) -> bool,
+    links: Vec<Link>,
+    max_size: u64,
+    /// The number of bytes written so far.
+    extracted: u64,
+}
+
+impl Extractor<'_> {
+    /// Returns where the entry `name` should be extracted to, or `None` if it should be skipped.
+    fn out_path(&self, name: &str) -> anyhow::Result<Option<PathBuf>> {
+        let Some(stripped) = strip_components(name, self.strip_components) else {
+            return Ok(None);
+        };
+        if !(self.filter)(stripped) {
+            return Ok(None);
+        }
+        let Some(relative_path) = enclosed_path(stripped) else {
+            return Err(unsafe_entry(self.archive_path, name));
+        };
+        if cfg!(windows) {
+            if let Some(problem) = windows_name_problem(stripped) {
+                logging::warn!(
+                    "Skipping entry {name:?} of {:?}: {problem} on Windows",
+                    self.archive_path
+                );
+                return Ok(None);
+            }
+        }
+        Ok(Some(self.dest_dir.join(relative_path)))
+    }
+
+    /// Extracts a zip archive. Entries are streamed to disk through a buffer of `buffer_size`
+    /// bytes, so memory usage doesn't depend on the size of the archive. Entries are extracted in
+    /// name order regardless of their order in the archive. On Unix, file permissions recorded in
+    /// the archive are preserved (but see [`normalize_tree`]).
+    fn extract_zip(&mut self) -> anyhow::Result<()> {
+        let archive_path = self.archive_path;
+        let archive_file = File::open(archive_path)
+            .with_context(|| format!("Failed to open archive {archive_path:?}"))?;
+        let mut archive =
+            zip::ZipArchive::new(BufReader::with_capacity(self.buffer_size, archive_file))
+                .with_context(|| format!("Failed to read zip archive {archive_path:?}"))?;
+
+        let mut names = Vec::new();
+        for name in archive.file_names() {
+            let name =
+                name.with_context(|| format!("Failed to read entry name in {archive_path:?}"))?;
+            names.push(name.into_owned());
+        }
+        names.sort();
+
+        for name in names {
+            let Some(out_path) = self.out_path(&name)? else {
+                continue;
+            };
+            let mut entry = archive
+                .by_name(&name)
+                .with_context(|| format!("Failed to read entry {name:?} of {archive_path:?}"))?;
+
+            if entry.is_symlink() {
+                let mut target = String::new();
+                (&mut entry)
+                    .take(MAX_LINK_TARGET_LEN + 1)
+                    .read_to_string(&mut target)
+                    .with_context(|| format!("Failed to read link target of entry {name:?}"))?;
+                if target.len() as u64 > MAX_LINK_TARGET_LEN {
+                    bail!("Link target of entry {name:?} of {archive_path:?} is too long");
+                }
+                self.links.push(Link {
+                    name,
+                    path: out_path,
+                    target: PathBuf::from(target),
+                    hard: false,
+                });
+                continue;
+            } else if entry.is_dir() {
+                self.prepare_out_path(&name, &out_path)?;
+                fs::create_dir_all(&out_path)
+                    .with_context(|| format!("Failed to create directory {out_path:?}"))?;
+            } else {
+                // The declared size may be a lie, which extract_file catches, but an honest zip
+                // bomb is rejected without writing anything.
+                if entry.size() > self.max_size - self.extracted {
+                    return Err(too_large(archive_path, self.max_size));
+                }
+                self.prepare_out_path(&name, &out_path)?;
+                self.extract_file(&name, &mut entry, &out_path)?;
+            }
+
+            #[cfg(unix)]
+            if let Some(mode) = entry.unix_mode() {
+                fs::set_permissions(&out_path, fs::Permissions::from_mode(mode))
+                    .with_context(|| format!("Failed to set permissions of {out_path:?}"))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Extracts a (decompressed) tarball read from `reader`. Like zip archives, entries are
+    /// streamed to disk. A tarball can only be read front to back, so entries are extracted in
+    /// archive order. On Unix, file permissions recorded in the archive are preserved.
+    ///
+    /// Only regular files, directories, and links are extracted; other kinds of entries (e.g.
+    /// devices) are skipped.
+    fn extract_tar(&mut self, reader: impl Read) -> anyhow::Result<()> {
+        let archive_path = self.archive_path;
+        let mut archive = tar::Archive::new(reader);
+
+        for entry in archive
+            .entries()
+            .with_context(|| format!("Failed to read tarball {archive_path:?}"))?
+        {
+            let mut entry =
+                entry.with_context(|| format!("Failed to read entry of {archive_path:?}"))?;
+            let path = entry
+                .path()
+                .with_context(|| format!("Failed to read entry path in {archive_path:?}"))?;
+            let name = path.to_string_lossy().trim_start_matches("./").to_string();
+            let Some(out_path) = self.out_path(&name)? else {
+                continue;
+            };
+
+            let entry_type = entry.header().entry_type();
+            if entry_type.is_dir() {
+                self.prepare_out_path(&name, &out_path)?;
+                fs::create_dir_all(&out_path)
+                    .with_context(|| format!("Failed to create directory {out_path:?}"))?;
+            } else if entry_type.is_file() {
+                self.prepare_out_path(&name, &out_path)?;
+                self.extract_file(&name, &mut entry, &out_path)?;
+                #[cfg(unix)]
+                if let Ok(mode) = entry.header().mode() {
+                    fs::set_permissions(&out_path, fs::Permissions::from_mode(mode & 0o777))
+                        .with_context(|| format!("Failed to set permissions of {out_path:?}"))?;
+                }
+            } else if entry_type.is_symlink() || entry_type.is_hard_link() {
+                let hard = entry_type.is_hard_link();
+                let Some(target) = entry.link_name().with_context(|| {
+                    format!("Failed to read link target of entry {name:?} of {archive_path:?}")
+                })?
+                else {
+                    continue;
+                };
+                let target = target
+                    .to_string_lossy()
+                    .trim_start_matches("./")
+                    .to_string();
+                let target = if hard {
+                    match strip_components(&target, self.strip_components) {
+                        Some(target) => target.to_string(),
+                        None => continue,
+                    }
+                } else {
+                    target
+                };
+                self.links.push(Link {
+                    name,
+                    path: out_path,
+                    target: PathBuf::from(target),
+                    hard,
+                });
+            } else {
+                logging::warn!(
+                    "Skipping entry {name:?} of {archive_path:?}: unsupported entry type"
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Extractor<'_> {
+    /// Streams the contents of the file entry `name` from `reader` to `out_path`, failing with
+    /// [`Error::ArchiveTooLarge`] as soon as the extraction as a whole exceeds its size limit.
+    ///
+    /// Zip entries are checked against their CRC and gzip streams against their checksum as they
+    /// are read, so a failure to read means the archive is corrupt. In that case the error names
+    /// the corrupt entry and includes the digest of the whole archive, which can be compared
+    /// against the published one to tell a corrupted download from a corrupted copy on disk.
+    fn extract_file(
+        &mut self,
+        name: &str,
+        reader: &mut impl Read,
+        out_path: &Path,
+    ) -> anyhow::Result<()> {
+        let archive_path = self.archive_path;
+        let mut out_file =
+            File::create(out_path).with_context(|| format!("Failed to create {out_path:?}"))?;
+        let remaining = self.max_size - self.extracted;
+        let mut reader = reader.take(remaining.saturating_add(1));
+        match stream::copy(&mut reader, &mut out_file, self.buffer_size) {
+            Ok(written) if written > remaining => {
+                drop(out_file);
+                fs::remove_file(out_path)?;
+                Err(too_large(archive_path, self.max_size))
+            }
+            Ok(written) => {
+                self.extracted += written;
+                Ok(())
+            }
+            Err(CopyError::Read(err)) => {
+                let archive_sha256 = digest::sha256_file(archive_path, self.buffer_size)
+                    .unwrap_or_else(|err| format!("<unavailable: {err}>"));
+                Err(anyhow::anyhow!(err).context(format!(
+                    "Entry {name:?} of {archive_path:?} is corrupt (archive SHA-256: \
+                     {archive_sha256})"
+                )))
+            }
+            Err(CopyError::Write(err)) => Err(anyhow::anyhow!(err).context(format!(
+                "Failed to write entry {name:?} of {archive_path:?} to {out_path:?}"
+            ))),
+        }
+    }
+
+    /// Makes `out_path`, where the entry `name` is extracted to, ready to be written; see
+    /// [`prepare_out_path`].
+    fn prepare_out_path(&self, name: &str, out_path: &Path) -> anyhow::Result<()> {
+        prepare_out_path(self.archive_path, self.dest_dir, name, out_path)
+    }
+
+    /// Creates the link entries collected while extracting the archive.
+    ///
+    /// Links are only ever materialized within `dest_dir`: extraction fails with
+    /// [`Error::UnsafeEntry`] on a link whose target is outside of it, which is checked after all
+    /// symlinks along the way have been followed, so that chains of links such as `s -> ..` and
+    /// `t -> s/..` can't get out either. On Unix, symlinks are created as symlinks. Elsewhere
+    /// (notably on Windows, where creating symlinks needs special privileges), and for hard links,
+    /// the link is materialized as a copy of its target file. Links whose target doesn't exist are
+    /// skipped.
+    fn create_links(&mut self) -> anyhow::Result<()> {
+        let canonical_dest_dir = fs::canonicalize(self.dest_dir)
+            .with_context(|| format!("Failed to resolve {:?}", self.dest_dir))?;
+        #[cfg(unix)]
+        let mut symlinks = Vec::new();
+        for link in std::mem::take(&mut self.links) {
+            let Some(resolved) = resolve_link(self.dest_dir, &link.path, &link.target, link.hard)
+            else {
+                return Err(unsafe_entry(self.archive_path, &link.name));
+            };
+            self.prepare_out_path(&link.name, &link.path)?;
+            if fs::symlink_metadata(&link.path).is_ok() {
+                fs::remove_file(&link.path)
+                    .with_context(|| format!("Failed to replace {:?}", link.path))?;
+            }
+
+            #[cfg(unix)]
+            if !link.hard {
+                std::os::unix::fs::symlink(&link.target, &link.path)
+                    .with_context(|| format!("Failed to create symlink {:?}", link.path))?;
+                symlinks.push(link);
+                continue;
+            }
+
+            if resolved.is_file() {
+                if !contained(&canonical_dest_dir, &resolved) {
+                    return Err(unsafe_entry(self.archive_path, &link.name));
+                }
+                // Copies count towards the size limit, so that many links to one large entry
+                // can't get around it.
+                let size = fs::metadata(&resolved)?.len();
+                if size > self.max_size - self.extracted {
+                    return Err(too_large(self.archive_path, self.max_size));
+                }
+                self.extracted += size;
+                fs::copy(&resolved, &link.path)
+                    .with_context(|| format!("Failed to copy {resolved:?} to {:?}", link.path))?;
+            } else {
+                logging::warn!(
+                    "Skipping link {:?} of {:?}: target {:?} is not an extracted file",
+                    link.name,
+                    self.archive_path,
+                    link.target
+                );
+            }
+        }
+
+        // A symlink may lead through others created after it, so they are only checked once all of
+        // them exist.
+        #[cfg(unix)]
+        for link in symlinks {
+            let exists = fs::metadata(&link.path).is_ok();
+            if exists && contained(&canonical_dest_dir, &link.path) {
+                continue;
+            }
+            fs::remove_file(&link.path)
+                .with_context(|| format!("Failed to remove symlink {:?}", link.path))?;
+            if exists {
+                return Err(unsafe_entry(self.archive_path, &link.name));
+            }
+            logging::warn!(
+                "Skipping link {:?} of {:?}: target {:?} doesn't exist",
+                link.name,
+                self.archive_path,
+                link.target
+            );
+        }
+        Ok(())
+    }
+}
+
+fn too_large(archive_path: &Path, limit: u64) -> anyhow::Error {
+    Error::ArchiveTooLarge {
+        archive: archive_path.to_path_buf(),
+        limit,
+    }
+    .into()
+}
+
+fn unsafe_entry(archive_path: &Path, name: &str) -> anyhow::Error {
+    Error::UnsafeEntry {
+        archive: archive_path.to_path_buf(),
+        entry: name.to_string(),
+    }
+    .into()
+}
+
+/// Makes `path`, where the entry `name` of the archive at `archive_path` is extracted to within
+/// `dest_dir`, ready to be written: creates its missing parent directories, and removes a symlink
+/// at `path` itself, which the entry then replaces rather than being written through.
+///
+/// Fails with [`Error::UnsafeEntry`] if one of the parent directories is a symlink, e.g. one that
+/// an earlier extraction into `dest_dir` created, through which the entry would be written
+/// somewhere else entirely.
+fn prepare_out_path(
+    archive_path: &Path,
+    dest_dir: &Path,
+    name: &str,
+    path: &Path,
+) -> anyhow::Result<()> {
+    if let Some(parent) = path.strip_prefix(dest_dir)?.parent() {
+        let mut dir = dest_dir.to_path_buf();
+        for component in parent.components() {
+            dir.push(component);
+            match fs::symlink_metadata(&dir) {
+                Ok(metadata) if metadata.file_type().is_symlink() => {
+                    return Err(unsafe_entry(archive_path, name));
+                }
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+    }
+    create_parent_dir(path)?;
+    if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+        fs::remove_file(path).with_context(|| format!("Failed to replace symlink {path:?}"))?;
+    }
+    Ok(())
+}
+
+fn create_parent_dir(path: &Path) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {parent:?}"))?;
+    }
+    Ok(())
+}
+
+/// Sets the mode of the file at `path` to 0755 on Unix, regardless of what the archive it was
+/// extracted from recorded. Does nothing on other platforms.
+pub(crate) fn make_executable(path: &Path) -> anyhow::Result<()> {
+    #[cfg(unix)]
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// Normalizes the metadata of everything under `dir`, so that installs of the same release are
+/// byte-for-byte identical no matter when, where, or by which tool they were extracted. This
+/// matters for content-hashed CI caches and Nix-style stores.
+///
+/// Every file and directory gets the same fixed modification time. On Unix, directories and
+/// executable files get mode 0755 and all other files 0644.
+///
+/// Two bookkeeping files of an install are excluded from that guarantee: `install.json` records
+/// when the install was made, and the completion marker's modification time is moved to the last
+/// use by [`install::record_use`](crate::install::record_use). Caches that hash installs should
+/// leave both out.
+pub(crate) fn normalize_tree(dir: &Path) -> anyhow::Result<()> {
+    let mtime = SystemTime::UNIX_EPOCH + NORMALIZED_MTIME;
+
+    let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            normalize_tree(&path)?;
+        } else if file_type.is_file() {
+            #[cfg(unix)]
+            {
+                let executable = entry.metadata()?.permissions().mode() & 0o111 != 0;
+                let mode = if executable { 0o755 } else { 0o644 };
+                fs::set_permissions(&path, fs::Permissions::from_mode(mode))?;
+            }
+            File::options()
+                .write(true)
+                .open(&path)?
+                .set_modified(mtime)?;
+        }
+    }
+
+    // Directories are done last, since creating files in them updates their mtime. Windows can't
+    // open directories as files, so their mtimes are left alone there.
+    #[cfg(unix)]
+    {
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o755))?;
+        File::open(dir)?.set_modified(mtime)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::Sandbox;
+    use std::io::{Cursor, Write};
+    use zip::write::SimpleFileOptions;
+
+    enum Entry<'a> {
+        File(&'a str),
+        #[cfg(unix)]
+        Symlink(&'a str),
+        HardLink(&'a str),
+    }
+
+    /// Writes a gzipped tarball of `entries` to `path`.
+    fn write_tar(path: &Path, entries: &[(&str, Entry)]) {
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        gz.write_all(&tar(entries)).unwrap();
+        fs::write(path, gz.finish().unwrap()).unwrap();
+    }
+
+    /// Returns a tarball of `entries`. The names and link targets are written into the headers as
+    /// they are, since the `tar` crate refuses to write unsafe ones.
+    fn tar(entries: &[(&str, Entry)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, entry) in entries {
+            let mut header = tar::Header::new_old();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_mode(0o644);
+            let (entry_type, contents, target) = match entry {
+                Entry::File(contents) => (tar::EntryType::Regular, *contents, ""),
+                #[cfg(unix)]
+                Entry::Symlink(target) => (tar::EntryType::Symlink, "", *target),
+                Entry::HardLink(target) => (tar::EntryType::Link, "", *target),
+            };
+            header.set_entry_type(entry_type);
+            header.as_old_mut().linkname[..target.len()].copy_from_slice(target.as_bytes());
+            header.set_size(contents.len() as u64);
+            header.set_cksum();
+            builder.append(&header, contents.as_bytes()).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn write_zip(path: &Path, entries: &[(&str, &str)]) {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, contents) in entries {
+            zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        fs::write(path, zip.finish().unwrap().into_inner()).unwrap();
+    }
+
+    fn extract_all(
+        archive: &Path,
+        dest_dir: &Path,
+        filter: impl Fn(&str) -> bool,
+    ) -> anyhow::Result<()> {
+        let options = ExtractOptions {
+            buffer_size: 8192,
+            strip_components: Some(0),
+            format: None,
+            max_size: 1 << 20,
+        };
+        extract_archive(archive, dest_dir, &options, filter)
+    }
+
+    fn assert_unsafe(result: anyhow::Result<()>, entry: &str) {
+        let err = result.unwrap_err();
+        match err.downcast_ref::<Error>() {
+            Some(Error::UnsafeEntry {
+                entry: unsafe_entry,
+                ..
+            }) => assert_eq!(unsafe_entry, entry),
+            _ => panic!("expected UnsafeEntry for {entry:?}, got {err:?}"),
+        }
+    }
+
+    /// Returns a sandbox with a file outside of the install directory `dest` in it.
+    fn sandbox() -> (Sandbox, PathBuf) {
+        let sandbox = Sandbox::new().unwrap();
+        fs::write(sandbox.path().join("outside.txt"), "outside").unwrap();
+        let dest_dir = sandbox.path().join("dest");
+        (sandbox, dest_dir)
+    }
+
+    #[test]
+    fn rejects_parent_dir_names() {
+        let (sandbox, dest_dir) = sandbox();
+        let tar = sandbox.path().join("release.tar.gz");
+        write_tar(&tar, &[("bin/../../evil.txt", Entry::File("evil"))]);
+        assert_unsafe(extract_all(&tar, &dest_dir, |_| true), "bin/../../evil.txt");
+
+        let zip = sandbox.path().join("release.zip");
+        write_zip(&zip, &[("../evil.txt", "evil")]);
+        assert_unsafe(extract_all(&zip, &dest_dir, |_| true), "../evil.txt");
+        assert!(!sandbox.path().join("evil.txt").exists());
+    }
+
+    #[test]
+    fn rejects_absolute_names() {
+        let (sandbox, dest_dir) = sandbox();
+        let evil = sandbox.path().join("evil.txt");
+        let name = evil.to_str().unwrap().replace('\\', "/");
+        let zip = sandbox.path().join("release.zip");
+        write_zip(&zip, &[(&name, "evil")]);
+        assert_unsafe(extract_all(&zip, &dest_dir, |_| true), &name);
+        assert!(!evil.exists());
+    }
+
+    #[test]
+    fn rejects_hard_links_to_files_outside() {
+        let (sandbox, dest_dir) = sandbox();
+        let tar = sandbox.path().join("release.tar.gz");
+        write_tar(&tar, &[("bin/protoc", Entry::HardLink("../outside.txt"))]);
+        assert_unsafe(extract_all(&tar, &dest_dir, |_| true), "bin/protoc");
+        assert!(!dest_dir.join("bin/protoc").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_chained_symlinks_out() {
+        let (sandbox, dest_dir) = sandbox();
+        let tar = sandbox.path().join("release.tar.gz");
+        // Each target stays within the install when taken lexically, but `s/..` is its parent.
+        write_tar(
+            &tar,
+            &[
+                ("bin/s", Entry::Symlink("..")),
+                ("bin/t", Entry::Symlink("s/..")),
+            ],
+        );
+        assert_unsafe(extract_all(&tar, &dest_dir, |_| true), "bin/t");
+        assert!(fs::symlink_metadata(dest_dir.join("bin/t")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_hard_links_through_symlinks_out() {
+        let (sandbox, dest_dir) = sandbox();
+        let tar = sandbox.path().join("release.tar.gz");
+        write_tar(
+            &tar,
+            &[
+                ("bin/s", Entry::Symlink("..")),
+                ("bin/t", Entry::Symlink("s/..")),
+                ("bin/protoc", Entry::HardLink("bin/t/outside.txt")),
+            ],
+        );
+        assert!(extract_all(&tar, &dest_dir, |_| true).is_err());
+        assert!(!dest_dir.join("bin/protoc").exists());
+    }
+
+    #[test]
+    fn rejects_absolute_link_targets() {
+        let (sandbox, dest_dir) = sandbox();
+        let tar = sandbox.path().join("release.tar.gz");
+        let outside = sandbox.path().join("outside.txt");
+        let outside = outside.to_str().unwrap();
+        write_tar(&tar, &[("bin/protoc", Entry::HardLink(outside))]);
+        assert_unsafe(extract_all(&tar, &dest_dir, |_| true), "bin/protoc");
+        #[cfg(unix)]
+        {
+            write_tar(&tar, &[("bin/protoc", Entry::Symlink(outside))]);
+            assert_unsafe(extract_all(&tar, &dest_dir, |_| true), "bin/protoc");
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_entries_written_through_symlinks() {
+        let (sandbox, dest_dir) = sandbox();
+        let tar = sandbox.path().join("release.tar.gz");
+        write_tar(
+            &tar,
+            &[
+                ("include/evil.txt", Entry::File("evil")),
+                ("inc", Entry::Symlink(".")),
+                ("inc/include/evil2.txt", Entry::File("evil")),
+            ],
+        );
+        // A first, lazy pass creates the link alone (which is fine, as it points within the
+        // install), and a later one must not write through it.
+        extract_all(&tar, &dest_dir, |name| name == "inc").unwrap();
+        assert_unsafe(
+            extract_all(&tar, &dest_dir, |_| true),
+            "inc/include/evil2.txt",
+        );
+
+        // Neither through a symlink that an earlier install left pointing outside.
+        fs::create_dir(sandbox.path().join("elsewhere")).unwrap();
+        fs::remove_file(dest_dir.join("inc")).unwrap();
+        std::os::unix::fs::symlink("../elsewhere", dest_dir.join("inc")).unwrap();
+        let tar = sandbox.path().join("other.tar.gz");
+        write_tar(&tar, &[("inc/evil.txt", Entry::File("evil"))]);
+        assert_unsafe(extract_all(&tar, &dest_dir, |_| true), "inc/evil.txt");
+        assert!(!sandbox.path().join("elsewhere/evil.txt").exists());
+    }
+
+    #[test]
+    fn stops_at_the_size_limit() {
+        let (sandbox, dest_dir) = sandbox();
+        let zip = sandbox.path().join("release.zip");
+        write_zip(&zip, &[("bin/protoc", &"x".repeat(2 << 20))]);
+        let err = extract_all(&zip, &dest_dir, |_| true).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::ArchiveTooLarge { limit, .. }) if *limit == 1 << 20
+        ));
+    }
+
+    #[test]
+    fn extracts_links_within_the_install() {
+        let (sandbox, dest_dir) = sandbox();
+        let tar = sandbox.path().join("release.tar.gz");
+        write_tar(
+            &tar,
+            &[
+                ("bin/protoc-21.2", Entry::File("protoc")),
+                ("bin/protoc", Entry::HardLink("bin/protoc-21.2")),
+            ],
+        );
+        extract_all(&tar, &dest_dir, |_| true).unwrap();
+        assert_eq!(
+            fs::read_to_string(dest_dir.join("bin/protoc")).unwrap(),
+            "protoc"
+        );
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn encloses_relative_names() {
+        assert_eq!(
+            enclosed_path("include/google/protobuf/any.proto"),
+            Some(Path::new("include").join("google/protobuf/any.proto"))
+        );
+        assert_eq!(
+            enclosed_path("./bin//protoc"),
+            Some(Path::new("bin").join("protoc"))
+        );
+        assert_eq!(enclosed_path(""), Some(PathBuf::new()));
+    }
+
+    #[test]
+    fn doesnt_enclose_escaping_names() {
+        for name in ["../evil", "bin/../../evil", "/etc/passwd", "bin/.."] {
+            assert_eq!(enclosed_path(name), None, "{name:?}");
+        }
+    }
+
+    #[test]
+    fn detects_a_lone_top_level_dir() {
+        assert_eq!(
+            detect_strip_components(&names(&[
+                "protoc-21.2/",
+                "protoc-21.2/bin/protoc",
+                "protoc-21.2/include/google/protobuf/any.proto",
+            ])),
+            1
+        );
+    }
+
+    #[test]
+    fn doesnt_strip_release_layouts_or_mixed_dirs() {
+        for entries in [
+            &[
+                "bin/protoc",
+                "include/google/protobuf/any.proto",
+                "readme.txt",
+            ][..],
+            &["bin/", "bin/protoc"],
+            &["include/google/protobuf/any.proto"],
+            &["a/bin/protoc", "b/include/any.proto"],
+            &["protoc"],
+            &[],
+        ] {
+            assert_eq!(detect_strip_components(&names(entries)), 0, "{entries:?}");
+        }
+    }
+
+    #[test]
+    fn strips_components() {
+        assert_eq!(
+            strip_components("protoc-21.2/bin/protoc", 1),
+            Some("bin/protoc")
+        );
+        assert_eq!(strip_components("bin/protoc", 0), Some("bin/protoc"));
+        assert_eq!(strip_components("protoc-21.2/", 1), None);
+        assert_eq!(strip_components("protoc", 1), None);
+    }
+
+    #[test]
+    fn finds_names_windows_cant_create() {
+        for (name, problem) in [
+            ("include/foo:bar.proto", "characters"),
+            ("include/what?.proto", "characters"),
+            ("include/tab\tname", "characters"),
+            ("include/trailing.", "dot or space"),
+            ("include/trailing ", "dot or space"),
+            ("include/con", "reserved"),
+            ("include/AUX.h", "reserved"),
+            ("lpt1/readme.txt", "reserved"),
+            ("include\\nul .txt", "reserved"),
+        ] {
+            let found = windows_name_problem(name);
+            assert!(
+                found.is_some_and(|found| found.contains(problem)),
+                "{name:?}: {found:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn accepts_names_windows_can_create() {
+        for name in [
+            "bin/protoc.exe",
+            "include/google/protobuf/any.proto",
+            "./include/../readme.txt",
+            "include/console.h",
+            "include/com10.txt",
+            "include/.hidden",
+        ] {
+            assert_eq!(windows_name_problem(name), None, "{name:?}");
+        }
+    }
+
+    #[test]
+    fn detects_zips_by_their_contents() {
+        let (sandbox, _) = sandbox();
+        let zip = sandbox.path().join("release");
+        write_zip(&zip, &[("bin/protoc", "protoc")]);
+        assert_eq!(ArchiveFormat::detect(&zip).unwrap(), ArchiveFormat::Zip);
+
+        let empty = sandbox.path().join("empty");
+        write_zip(&empty, &[]);
+        assert_eq!(ArchiveFormat::detect(&empty).unwrap(), ArchiveFormat::Zip);
+    }
+
+    #[test]
+    fn detects_formats_by_their_magic() {
+        let (sandbox, _) = sandbox();
+        for (magic, format) in [
+            (&[0x1f, 0x8b, 8, 0][..], ArchiveFormat::TarGz),
+            (&[0x28, 0xb5, 0x2f, 0xfd, 0], ArchiveFormat::TarZst),
+            (&[0xfd, b'7', b'z', b'X', b'Z', 0, 0], ArchiveFormat::TarXz),
+            (&[0x7f, b'E', b'L', b'F', 2], ArchiveFormat::RawBinary),
+            (&[0xcf, 0xfa, 0xed, 0xfe, 7], ArchiveFormat::RawBinary),
+            (&[0xce, 0xfa, 0xed, 0xfe, 7], ArchiveFormat::RawBinary),
+            (&[0xca, 0xfe, 0xba, 0xbe, 0], ArchiveFormat::RawBinary),
+            (&[b'M', b'Z', 0x90, 0], ArchiveFormat::RawBinary),
+        ] {
+            let path = sandbox.path().join("download");
+            fs::write(&path, magic).unwrap();
+            assert_eq!(ArchiveFormat::detect(&path).unwrap(), format, "{magic:x?}");
+        }
+    }
+
+    #[test]
+    fn falls_back_to_the_extension() {
+        let (sandbox, _) = sandbox();
+        for (name, format) in [
+            ("release.zip", Some(ArchiveFormat::Zip)),
+            ("release.tgz", Some(ArchiveFormat::TarGz)),
+            ("release.tar.zst", Some(ArchiveFormat::TarZst)),
+            ("release.txz", Some(ArchiveFormat::TarXz)),
+            ("release", None),
+        ] {
+            let path = sandbox.path().join(name);
+            fs::write(&path, "not an archive").unwrap();
+            assert_eq!(ArchiveFormat::detect(&path).ok(), format, "{name}");
+        }
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn extracts_zstd_tarballs() {
+        let (sandbox, dest_dir) = sandbox();
+        let archive = sandbox.path().join("release");
+        let tar = tar(&[("bin/protoc", Entry::File("protoc"))]);
+        fs::write(&archive, zstd::encode_all(&tar[..], 0).unwrap()).unwrap();
+        assert_eq!(
+            ArchiveFormat::detect(&archive).unwrap(),
+            ArchiveFormat::TarZst
+        );
+        extract_all(&archive, &dest_dir, |_| true).unwrap();
+        assert_eq!(
+            fs::read_to_string(dest_dir.join("bin/protoc")).unwrap(),
+            "protoc"
+        );
+    }
+
+    #[cfg(feature = "xz")]
+    #[test]
+    fn extracts_xz_tarballs() {
+        let (sandbox, dest_dir) = sandbox();
+        let archive = sandbox.path().join("release");
+        let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+        xz.write_all(&tar(&[("bin/protoc", Entry::File("protoc"))]))
+            .unwrap();
+        fs::write(&archive, xz.finish().unwrap()).unwrap();
+        assert_eq!(
+            ArchiveFormat::detect(&archive).unwrap(),
+            ArchiveFormat::TarXz
+        );
+        extract_all(&archive, &dest_dir, |_| true).unwrap();
+        assert_eq!(
+            fs::read_to_string(dest_dir.join("bin/protoc")).unwrap(),
+            "protoc"
+        );
+    }
+}
@@ -0,0 +1,78 @@
+//! The GitHub server and repository that releases are fetched from: by default the official
+//! releases on github.com, or those of a fork or of a GitHub Enterprise instance as set with
+//! [`ProtocFetcher::github_host`] and [`ProtocFetcher::release_repo`].
+
+use crate::fetcher::ProtocFetcher;
+use crate::http;
+use anyhow::bail;
+
+/// The repository of the official releases.
+pub(crate) const DEFAULT_REPO: &str = "protocolbuffers/protobuf";
+
+/// Returns the repository releases are fetched from, as `owner/name`.
+pub(crate) fn repo(fetcher: &ProtocFetcher) -> &str {
+    fetcher.release_repo.as_deref().unwrap_or(DEFAULT_REPO)
+}
+
+/// Fails unless `repo` names a repository as `owner/name`.
+pub(crate) fn validate_repo(repo: &str) -> anyhow::Result<()> {
+    if repo.split('/').count() != 2 || repo.split('/').any(str::is_empty) {
+        bail!("Invalid GitHub repository {repo:?}: expected <owner>/<name>");
+    }
+    Ok(())
+}
+
+/// Returns the URL of the GitHub server, e.g. `https://github.com` or
+/// `https://github.example.com`.
+pub(crate) fn web_url(fetcher: &ProtocFetcher) -> String {
+    match &fetcher.github_host {
+        Some(host) if host.contains("://") => host.trim_end_matches('/').to_string(),
+        Some(host) => format!("https://{}", host.trim_end_matches('/')),
+        None => "https://github.com".to_string(),
+    }
+}
+
+/// Returns whether releases come from github.com rather than a GitHub Enterprise instance.
+pub(crate) fn is_github_com(fetcher: &ProtocFetcher) -> bool {
+    http::host(&web_url(fetcher)) == Some("github.com")
+}
+
+/// Returns the base URL of the repository API that releases are resolved with: the fetcher's
+/// [`api_base_url`](ProtocFetcher::api_base_url) if set, and otherwise that of the repository
+/// on github.com (`https://api.github.com/repos/<repo>`) or on GitHub Enterprise
+/// (`https://<host>/api/v3/repos/<repo>`).
+pub(crate) fn api_base_url(fetcher: &ProtocFetcher) -> anyhow::Result<String> {
+    if let Some(url) = &fetcher.api_base_url {
+        return Ok(url.trim_end_matches('/').to_string());
+    }
+    let repo = repo(fetcher);
+    validate_repo(repo)?;
+    Ok(if is_github_com(fetcher) {
+        format!("https://api.github.com/repos/{repo}")
+    } else {
+        format!("{}/api/v3/repos/{repo}", web_url(fetcher))
+    })
+}
+
+/// Returns the template of the URLs that the assets of the releases are downloaded from, as for
+/// [`ProtocFetcher::download_url_template`].
+pub(crate) fn download_url_template(fetcher: &ProtocFetcher) -> anyhow::Result<String> {
+    let repo = repo(fetcher);
+    validate_repo(repo)?;
+    Ok(format!(
+        "{}/{repo}/releases/download/{{tag}}/{{asset}}",
+        web_url(fetcher)
+    ))
+}
+
+/// Returns whether `host` is GitHub as far as the fetcher is concerned, i.e. may see its GitHub
+/// token: github.com and its API, or the configured GitHub Enterprise instance and the host of
+/// the API base URL.
+pub(crate) fn is_github_host(fetcher: &ProtocFetcher, host: &str) -> bool {
+    matches!(host, "github.com" | "api.github.com")
+        || http::host(&web_url(fetcher)) == Some(host)
+        || fetcher
+            .api_base_url
+            .as_deref()
+            .is_some_and(|url| http::host(url) == Some(host))
+}
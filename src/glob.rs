@@ -0,0 +1,56 @@
+//! Minimal glob patterns for selecting archive entries.
+
+/// A glob pattern matched against '/'-separated archive entry names.
+///
+/// `*` matches any run of characters within one path component, `?` matches a single character
+/// other than '/', and a `**` component matches any number of components (including none). For
+/// example, `bin/*` matches `bin/protoc` but not `bin/sub/protoc`, and `include/**` matches
+/// everything under `include/`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Glob {
+    pattern: String,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Self {
+        Glob {
+            pattern: pattern.trim_matches('/').to_string(),
+        }
+    }
+
+    /// Returns the pattern as given, minus leading and trailing slashes.
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Checks whether the entry `name` matches. A trailing '/', as used by zip archives for
+    /// directory entries, is ignored.
+    pub fn matches(&self, name: &str) -> bool {
+        let pattern: Vec<&str> = self.pattern.split('/').collect();
+        let name: Vec<&str> = name.trim_end_matches('/').split('/').collect();
+        match_components(&pattern, &name)
+    }
+}
+
+fn match_components(pattern: &[&str], name: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((&"**", rest)) => (0..=name.len()).any(|skip| match_components(rest, &name[skip..])),
+        Some((first, rest)) => match name.split_first() {
+            Some((name_first, name_rest)) => {
+                match_component(first.as_bytes(), name_first.as_bytes())
+                    && match_components(rest, name_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+fn match_component(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skip| match_component(rest, &name[skip..])),
+        Some((b'?', rest)) => !name.is_empty() && match_component(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && match_component(rest, &name[1..]),
+    }
+}
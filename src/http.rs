@@ -0,0 +1,721 @@
+//! The HTTP client shared by all downloads.
+
+use crate::deadline;
+use crate::error::Error;
+use crate::fetcher::ProtocFetcher;
+use crate::forge;
+use crate::logging;
+use crate::proxy::{self, Proxy};
+use crate::transport::{HttpClient, HttpRequest};
+use std::env;
+use std::fmt;
+use std::io::{self, Read};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Sent with every request; the GitHub API rejects requests without a user agent.
+const USER_AGENT: &str = concat!("protoc-fetcher/", env!("CARGO_PKG_VERSION"));
+
+/// Returns the process-wide HTTP client for the given connect timeout and proxy.
+///
+/// Every request made during a session goes through one client, so that its connection pool (and
+/// the keep-alive connections and TLS sessions in it) is reused across downloads instead of a new
+/// connection being set up for every request. The connect timeout and proxy can only be set per
+/// client, so there is one client per combination in use, which is rarely more than one.
+pub(crate) fn client(
+    connect_timeout: Duration,
+    proxy: Option<&Proxy>,
+) -> anyhow::Result<&'static dyn HttpClient> {
+    type Clients = Vec<(Duration, Option<Proxy>, &'static dyn HttpClient)>;
+    static CLIENTS: Mutex<Clients> = Mutex::new(Vec::new());
+    let mut clients = CLIENTS.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some((_, _, client)) = clients
+        .iter()
+        .find(|(timeout, p, _)| *timeout == connect_timeout && p.as_ref() == proxy)
+    {
+        return Ok(*client);
+    }
+    let client: &'static dyn HttpClient = Box::leak(build_client(connect_timeout, proxy)?);
+    clients.push((connect_timeout, proxy.cloned(), client));
+    Ok(client)
+}
+
+/// Builds the client of the HTTP backend selected with the crate's features: reqwest if either of
+/// its TLS features is enabled, and ureq otherwise. The client sends every request through
+/// `proxy`, or directly if there is none; it never picks a proxy of its own from the environment.
+#[cfg(feature = "reqwest")]
+fn build_client(
+    connect_timeout: Duration,
+    proxy: Option<&Proxy>,
+) -> anyhow::Result<Box<dyn HttpClient>> {
+    let mut builder = reqwest::blocking::Client::builder()
+        .user_agent(USER_AGENT)
+        .connect_timeout(connect_timeout)
+        .no_proxy();
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(reqwest_proxy(proxy)?);
+    }
+    Ok(Box::new(builder.build()?))
+}
+
+#[cfg(feature = "reqwest")]
+fn reqwest_proxy(proxy: &Proxy) -> anyhow::Result<reqwest::Proxy> {
+    let mut reqwest_proxy = reqwest::Proxy::all(&proxy.url)?;
+    if let Some((username, password)) = &proxy.credentials {
+        reqwest_proxy = reqwest_proxy.basic_auth(username, password);
+    }
+    Ok(reqwest_proxy)
+}
+
+#[cfg(all(feature = "ureq", not(feature = "reqwest")))]
+fn build_client(
+    connect_timeout: Duration,
+    proxy: Option<&Proxy>,
+) -> anyhow::Result<Box<dyn HttpClient>> {
+    let mut builder = ureq::AgentBuilder::new()
+        .user_agent(USER_AGENT)
+        .timeout_connect(connect_timeout);
+    if let Some(proxy) = proxy {
+        // ureq takes the credentials as is, not percent-encoded.
+        let url = match &proxy.credentials {
+            Some((username, password)) => match proxy.url.split_once("://") {
+                Some((scheme, host_port)) => {
+                    format!("{scheme}://{username}:{password}@{host_port}")
+                }
+                None => format!("{username}:{password}@{}", proxy.url),
+            },
+            None => proxy.url.clone(),
+        };
+        builder = builder.proxy(ureq::Proxy::new(url)?);
+    }
+    let proxy_authorization =
+        proxy
+            .and_then(|proxy| proxy.credentials.as_ref())
+            .map(|(username, password)| {
+                use base64::Engine;
+                let credentials = format!("{username}:{password}");
+                let encoded = base64::engine::general_purpose::STANDARD.encode(credentials);
+                format!("Basic {encoded}")
+            });
+    Ok(Box::new(UreqClient {
+        agent: builder.build(),
+        proxy_authorization,
+    }))
+}
+
+/// The built-in ureq client. ureq only authenticates to a proxy when tunnelling through it, i.e.
+/// for `https` URLs, so the `Proxy-Authorization` header for `http` URLs is added here.
+#[cfg(all(feature = "ureq", not(feature = "reqwest")))]
+struct UreqClient {
+    agent: ureq::Agent,
+    proxy_authorization: Option<String>,
+}
+
+#[cfg(all(feature = "ureq", not(feature = "reqwest")))]
+impl HttpClient for UreqClient {
+    fn get(
+        &self,
+        request: &HttpRequest,
+    ) -> Result<crate::transport::HttpResponse, Box<dyn std::error::Error + Send + Sync>> {
+        match &self.proxy_authorization {
+            Some(authorization) if request.url.starts_with("http://") => {
+                let mut request = request.clone();
+                request
+                    .headers
+                    .push(("Proxy-Authorization".to_string(), authorization.clone()));
+                HttpClient::get(&self.agent, &request)
+            }
+            _ => HttpClient::get(&self.agent, request),
+        }
+    }
+}
+
+#[cfg(not(any(feature = "reqwest", feature = "ureq")))]
+fn build_client(
+    _connect_timeout: Duration,
+    _proxy: Option<&Proxy>,
+) -> anyhow::Result<Box<dyn HttpClient>> {
+    anyhow::bail!(
+        "protoc-fetcher was built without an HTTP backend; enable its `native-tls`, `rustls-tls` \
+         or `ureq` feature, or pass a client to ProtocFetcher::http_client"
+    )
+}
+
+/// The env var holding the GitHub token used unless one is given to
+/// [`ProtocFetcher::github_token`].
+const GITHUB_TOKEN_ENV_VAR: &str = "GITHUB_TOKEN";
+
+/// A secret, such as a GitHub access token or a proxy password. Kept out of `Debug` output, so
+/// that it doesn't end up in logs.
+#[derive(Clone)]
+pub(crate) struct Token(pub String);
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Token(..)")
+    }
+}
+
+/// Returns the GitHub token to send with a request for `url`, if any.
+///
+/// The token is only ever sent to GitHub: `github.com` and `api.github.com`, and the fetcher's
+/// [`github_host`](ProtocFetcher::github_host) and the host of its
+/// [`api_base_url`](ProtocFetcher::api_base_url) for GitHub Enterprise. Mirrors never
+/// see it, and neither do the hosts GitHub redirects downloads to, as the client drops the
+/// `Authorization` header on redirects to other hosts.
+fn github_token(fetcher: &ProtocFetcher, url: &str) -> Option<String> {
+    if !forge::is_github_host(fetcher, host(url)?) {
+        return None;
+    }
+    match &fetcher.github_token {
+        Some(token) => Some(token.0.clone()),
+        None => env::var(GITHUB_TOKEN_ENV_VAR)
+            .ok()
+            .filter(|token| !token.is_empty()),
+    }
+}
+
+/// Returns the host of `url`, e.g. `api.github.com` for `https://api.github.com/repos`.
+pub(crate) fn host(url: &str) -> Option<&str> {
+    let (_, rest) = url.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host_port = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    let host = match host_port.strip_prefix('[') {
+        // An IPv6 address.
+        Some(rest) => rest.split_once(']')?.0,
+        None => host_port.split(':').next()?,
+    };
+    Some(host).filter(|host| !host.is_empty())
+}
+
+/// The body of a response, to be streamed.
+pub(crate) struct Response {
+    body: Box<dyn Read>,
+    /// The length of the body, if the server said.
+    pub content_length: Option<u64>,
+    /// The offset within the resource that the body starts at: non-zero if a range request (see
+    /// [`get_from`]) was honored.
+    pub offset: u64,
+    /// The `ETag` of the resource, if the server said.
+    pub etag: Option<String>,
+    /// Whether the resource still has the ETag given to [`get_if_none_match`], in which case the
+    /// body is empty.
+    pub not_modified: bool,
+}
+
+impl Response {
+    fn new(body: impl Read + 'static, content_length: Option<u64>) -> Self {
+        Response {
+            body: Box::new(body),
+            content_length,
+            offset: 0,
+            etag: None,
+            not_modified: false,
+        }
+    }
+}
+
+impl Read for Response {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.body.read(buf)
+    }
+}
+
+/// Sends a GET request for `url` and returns the response body to be streamed, failing unless
+/// the response is a 200.
+pub(crate) fn get(fetcher: &ProtocFetcher, url: &str) -> anyhow::Result<Response> {
+    get_from(fetcher, url, 0)
+}
+
+/// Like [`get`], but asks for the body from byte `offset` on, to resume an interrupted download.
+/// Servers may ignore the request and send the whole body; the response's
+/// [`offset`](Response::offset) says which it is.
+pub(crate) fn get_from(
+    fetcher: &ProtocFetcher,
+    url: &str,
+    offset: u64,
+) -> anyhow::Result<Response> {
+    #[cfg(feature = "record-replay")]
+    if let Some(dir) = &fetcher.recordings_dir {
+        // Recordings are whole bodies.
+        return record_replay::get(fetcher, dir, url);
+    }
+
+    send(fetcher, url, offset, None)
+}
+
+/// Like [`get`], but only sends the body if the resource no longer has the ETag `etag`;
+/// otherwise, the response is marked [`not_modified`](Response::not_modified).
+pub(crate) fn get_if_none_match(
+    fetcher: &ProtocFetcher,
+    url: &str,
+    etag: &str,
+) -> anyhow::Result<Response> {
+    #[cfg(feature = "record-replay")]
+    if let Some(dir) = &fetcher.recordings_dir {
+        return record_replay::get(fetcher, dir, url);
+    }
+
+    send(fetcher, url, 0, Some(etag))
+}
+
+const OK: u16 = 200;
+const PARTIAL_CONTENT: u16 = 206;
+const NOT_MODIFIED: u16 = 304;
+const TOO_MANY_REQUESTS: u16 = 429;
+const RANGE_NOT_SATISFIABLE: u16 = 416;
+
+/// Returns the offset that a response with `status` to a request for `url` from byte `offset`
+/// on starts at, or `None` if the range can't be satisfied and the whole body should be requested
+/// instead.
+fn response_offset(url: &str, status: u16, offset: u64) -> Result<Option<u64>, u16> {
+    match status {
+        OK => Ok(Some(0)),
+        PARTIAL_CONTENT if offset > 0 => Ok(Some(offset)),
+        RANGE_NOT_SATISFIABLE if offset > 0 => {
+            logging::debug!("Can't resume {url} at byte {offset}, downloading it again.");
+            Ok(None)
+        }
+        status => Err(status),
+    }
+}
+
+/// Returns the headers to send with a request for `url` from byte `offset` on, unless it still
+/// has the ETag `if_none_match`.
+fn request_headers(
+    fetcher: &ProtocFetcher,
+    url: &str,
+    offset: u64,
+    if_none_match: Option<&str>,
+) -> Vec<(String, String)> {
+    let mut headers = vec![("User-Agent".to_string(), USER_AGENT.to_string())];
+    if let Some(token) = github_token(fetcher, url) {
+        headers.push(("Authorization".to_string(), format!("Bearer {token}")));
+    }
+    if offset > 0 {
+        headers.push(("Range".to_string(), format!("bytes={offset}-")));
+    }
+    if let Some(etag) = if_none_match {
+        headers.push(("If-None-Match".to_string(), etag.to_string()));
+    }
+    headers
+}
+
+fn send(
+    fetcher: &ProtocFetcher,
+    url: &str,
+    offset: u64,
+    if_none_match: Option<&str>,
+) -> anyhow::Result<Response> {
+    fetcher.cancellation.check()?;
+    if fetcher.offline {
+        return Err(Error::Offline {
+            url: url.to_string(),
+        }
+        .into());
+    }
+    if let Some(client) = &fetcher.http_client {
+        return send_with(fetcher, &*client.0, url, offset, if_none_match);
+    }
+    let proxy = proxy::for_url(fetcher, url)?;
+    if let Some(proxy) = &proxy {
+        logging::debug!("Sending the request for {url} through the proxy {proxy}");
+    }
+    #[cfg(feature = "async")]
+    if let Some(runtime) = &fetcher.runtime {
+        return nonblocking::send(fetcher, runtime, proxy.as_ref(), url, offset, if_none_match);
+    }
+
+    let client = client(fetcher.connect_timeout, proxy.as_ref())?;
+    send_with(fetcher, client, url, offset, if_none_match)
+}
+
+/// Sends the request through `client`: the built-in one, or the one set with
+/// [`ProtocFetcher::http_client`].
+fn send_with(
+    fetcher: &ProtocFetcher,
+    client: &dyn HttpClient,
+    url: &str,
+    offset: u64,
+    if_none_match: Option<&str>,
+) -> anyhow::Result<Response> {
+    let request = HttpRequest {
+        url: url.to_string(),
+        headers: request_headers(fetcher, url, offset, if_none_match),
+        timeout: deadline::request_timeout(fetcher),
+    };
+    let started = Instant::now();
+    let mut response = client.get(&request).map_err(|err| Error::Network {
+        url: url.to_string(),
+        source: err,
+    })?;
+    logging::debug!(
+        "GET {url}: {} after {:.0?}",
+        response.status,
+        started.elapsed()
+    );
+    if response.status == NOT_MODIFIED && if_none_match.is_some() {
+        return Ok(Response {
+            etag: response.etag,
+            not_modified: true,
+            ..Response::new(io::empty(), Some(0))
+        });
+    }
+    let offset = match response_offset(url, response.status, offset) {
+        Ok(Some(offset)) => offset,
+        Ok(None) => return send_with(fetcher, client, url, 0, if_none_match),
+        Err(status) => {
+            let mut body = String::new();
+            let _ = response.body.read_to_string(&mut body);
+            return Err(status_error(url, status, body).into());
+        }
+    };
+    Ok(Response {
+        offset,
+        etag: response.etag,
+        ..Response::new(response.body, response.content_length)
+    })
+}
+
+pub(crate) fn network_error(
+    url: &str,
+    err: impl std::error::Error + Send + Sync + 'static,
+) -> Error {
+    Error::Network {
+        url: url.to_string(),
+        source: Box::new(err),
+    }
+}
+
+fn status_error(url: &str, status: u16, body: String) -> Error {
+    Error::HttpStatus {
+        url: url.to_string(),
+        status,
+        body,
+    }
+}
+
+/// Returns whether a failed download may well succeed if retried: the connection failed or timed
+/// out, the server had an error or is rate limiting, or the transfer was cut off.
+pub(crate) fn is_transient(err: &anyhow::Error) -> bool {
+    match err.downcast_ref::<Error>() {
+        Some(Error::Network { .. }) => true,
+        Some(Error::HttpStatus { status, .. }) => {
+            (500..600).contains(status) || *status == TOO_MANY_REQUESTS
+        }
+        _ => false,
+    }
+}
+
+/// Downloads through reqwest's async client, for fetches made from within a tokio runtime, where
+/// the blocking client must not be used.
+#[cfg(feature = "async")]
+mod nonblocking {
+    use super::{
+        network_error, request_headers, reqwest_proxy, response_offset, status_error, Response,
+        NOT_MODIFIED,
+    };
+    use crate::deadline::{self, Cancellation};
+    use crate::fetcher::ProtocFetcher;
+    use crate::logging;
+    use crate::proxy::Proxy;
+    use std::io::{self, Read};
+    use std::sync::{Mutex, PoisonError};
+    use std::time::{Duration, Instant};
+    use tokio::runtime::Handle;
+
+    /// Like [`super::client`], for the async client.
+    fn client(
+        connect_timeout: Duration,
+        proxy: Option<&Proxy>,
+    ) -> anyhow::Result<&'static reqwest::Client> {
+        type Clients = Vec<(Duration, Option<Proxy>, &'static reqwest::Client)>;
+        static CLIENTS: Mutex<Clients> = Mutex::new(Vec::new());
+        let mut clients = CLIENTS.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some((_, _, client)) = clients
+            .iter()
+            .find(|(timeout, p, _)| *timeout == connect_timeout && p.as_ref() == proxy)
+        {
+            return Ok(client);
+        }
+        let mut builder = reqwest::Client::builder()
+            .connect_timeout(connect_timeout)
+            .no_proxy();
+        if let Some(proxy) = proxy {
+            builder = builder.proxy(reqwest_proxy(proxy)?);
+        }
+        let client: &'static reqwest::Client = Box::leak(Box::new(builder.build()?));
+        clients.push((connect_timeout, proxy.cloned(), client));
+        Ok(client)
+    }
+
+    /// Sends the request on `runtime`, which the calling thread must not be a worker of (i.e. it
+    /// must be one of the runtime's blocking threads), and returns the body as a blocking reader.
+    pub(super) fn send(
+        fetcher: &ProtocFetcher,
+        runtime: &Handle,
+        proxy: Option<&Proxy>,
+        url: &str,
+        offset: u64,
+        if_none_match: Option<&str>,
+    ) -> anyhow::Result<Response> {
+        let mut request = client(fetcher.connect_timeout, proxy)?
+            .get(url)
+            .timeout(deadline::request_timeout(fetcher));
+        for (name, value) in request_headers(fetcher, url, offset, if_none_match) {
+            request = request.header(name, value);
+        }
+        let started = Instant::now();
+        let response = runtime
+            .block_on(fetcher.cancellation.unless_cancelled(request.send()))?
+            .map_err(|err| network_error(url, err))?;
+        logging::debug!(
+            "GET {url}: {} after {:.0?}",
+            response.status().as_u16(),
+            started.elapsed()
+        );
+        let etag = response
+            .headers()
+            .get(reqwest::header::ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(str::to_string);
+        if response.status().as_u16() == NOT_MODIFIED && if_none_match.is_some() {
+            return Ok(Response {
+                etag,
+                not_modified: true,
+                ..Response::new(io::empty(), Some(0))
+            });
+        }
+        let offset = match response_offset(url, response.status().as_u16(), offset) {
+            Ok(Some(offset)) => offset,
+            Ok(None) => return send(fetcher, runtime, proxy, url, 0, if_none_match),
+            Err(status) => {
+                let body = runtime.block_on(response.text()).unwrap_or_default();
+                return Err(status_error(url, status, body).into());
+            }
+        };
+        let content_length = response.content_length();
+        let body = Body {
+            runtime: runtime.clone(),
+            cancellation: fetcher.cancellation.clone(),
+            response,
+            chunk: Vec::new(),
+            pos: 0,
+        };
+        Ok(Response {
+            offset,
+            etag,
+            ..Response::new(body, content_length)
+        })
+    }
+
+    /// The body of a response, read chunk by chunk as it arrives.
+    struct Body {
+        runtime: Handle,
+        /// Abandons the download once the fetch is cancelled, rather than once the next chunk
+        /// arrives.
+        cancellation: Cancellation,
+        response: reqwest::Response,
+        chunk: Vec<u8>,
+        pos: usize,
+    }
+
+    impl Read for Body {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            while self.pos == self.chunk.len() {
+                let chunk = self
+                    .runtime
+                    .block_on(self.cancellation.unless_cancelled(self.response.chunk()))
+                    .map_err(io::Error::other)?;
+                match chunk {
+                    Ok(Some(chunk)) => {
+                        self.chunk = chunk.to_vec();
+                        self.pos = 0;
+                    }
+                    Ok(None) => return Ok(0),
+                    Err(err) => return Err(io::Error::other(err)),
+                }
+            }
+            let n = buf.len().min(self.chunk.len() - self.pos);
+            buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+            self.pos += n;
+            Ok(n)
+        }
+    }
+}
+
+#[cfg(feature = "record-replay")]
+mod record_replay {
+    use super::Response;
+    use crate::fetcher::ProtocFetcher;
+    use crate::logging;
+    use std::fs::{self, File};
+    use std::io::{self, Read, Write};
+    use std::path::{Path, PathBuf};
+
+    /// Replays the recorded response for `url` from `dir` if there is one; otherwise sends the
+    /// request and records the response body as it is read.
+    pub(super) fn get(fetcher: &ProtocFetcher, dir: &Path, url: &str) -> anyhow::Result<Response> {
+        let path = dir.join(recording_name(url));
+        if path.exists() {
+            logging::debug!("Replaying {url} from {path:?}");
+            let file = File::open(path)?;
+            let len = file.metadata()?.len();
+            return Ok(Response::new(file, Some(len)));
+        }
+
+        let response = super::send(fetcher, url, 0, None)?;
+        fs::create_dir_all(dir)?;
+        let mut part_path = path.as_os_str().to_owned();
+        part_path.push(".part");
+        let part_path = PathBuf::from(part_path);
+        logging::debug!("Recording {url} to {path:?}");
+        let content_length = response.content_length;
+        let recorder = Recorder {
+            response,
+            file: Some(File::create(&part_path)?),
+            part_path,
+            path,
+        };
+        Ok(Response::new(recorder, content_length))
+    }
+
+    /// Names the recording of `url` after the URL itself, so that recordings can be told apart by
+    /// name.
+    fn recording_name(url: &str) -> String {
+        let url = url.split_once("://").map_or(url, |(_, rest)| rest);
+        url.chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+                _ => '_',
+            })
+            .collect()
+    }
+
+    /// Copies everything read from the response into a recording, which is only moved into place
+    /// once the whole body has been read.
+    struct Recorder {
+        response: Response,
+        file: Option<File>,
+        part_path: PathBuf,
+        path: PathBuf,
+    }
+
+    impl Read for Recorder {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.response.read(buf)?;
+            if let Some(file) = &mut self.file {
+                file.write_all(&buf[..n])?;
+                if n == 0 {
+                    file.sync_all()?;
+                    self.file = None;
+                    fs::rename(&self.part_path, &self.path)?;
+                }
+            }
+            Ok(n)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{Fault, MockHttp};
+
+    const URL: &str = "https://mirror.example/protoc-21.2-linux-x86_64.zip";
+
+    fn body(mut response: Response) -> String {
+        let mut body = String::new();
+        response.read_to_string(&mut body).unwrap();
+        body
+    }
+
+    fn status(result: anyhow::Result<Response>) -> Option<u16> {
+        match result.err()?.downcast_ref::<Error>() {
+            Some(Error::HttpStatus { status, .. }) => Some(*status),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn reads_a_whole_body() {
+        let http = MockHttp::new().respond(URL, "protoc");
+        let response = send_with(&ProtocFetcher::new("21.2"), &http, URL, 0, None).unwrap();
+        assert_eq!((response.offset, response.content_length), (0, Some(6)));
+        assert!(!response.not_modified);
+        assert_eq!(body(response), "protoc");
+    }
+
+    #[test]
+    fn resumes_from_a_partial_response() {
+        let http = MockHttp::new().respond(URL, "protoc");
+        let response = send_with(&ProtocFetcher::new("21.2"), &http, URL, 2, None).unwrap();
+        assert_eq!(response.offset, 2);
+        assert_eq!(body(response), "otoc");
+    }
+
+    #[test]
+    fn starts_over_when_the_range_is_ignored() {
+        // The mock can't satisfy the range, so it sends the whole body with a 200.
+        let http = MockHttp::new().respond(URL, "protoc");
+        let response = send_with(&ProtocFetcher::new("21.2"), &http, URL, 10, None).unwrap();
+        assert_eq!(response.offset, 0);
+        assert_eq!(body(response), "protoc");
+    }
+
+    #[test]
+    fn starts_over_when_the_range_is_not_satisfiable() {
+        let http = MockHttp::new().respond(URL, "protoc");
+        http.inject(Fault::Status(RANGE_NOT_SATISFIABLE));
+        let response = send_with(&ProtocFetcher::new("21.2"), &http, URL, 2, None).unwrap();
+        assert_eq!(response.offset, 0);
+        assert_eq!(body(response), "protoc");
+        assert_eq!(http.requests(), [URL, URL]);
+    }
+
+    #[test]
+    fn rejects_unrequested_partial_and_range_responses() {
+        let fetcher = ProtocFetcher::new("21.2");
+        let http = MockHttp::new().respond(URL, "protoc");
+        http.inject(Fault::Status(PARTIAL_CONTENT));
+        assert_eq!(
+            status(send_with(&fetcher, &http, URL, 0, None)),
+            Some(PARTIAL_CONTENT)
+        );
+        http.inject(Fault::Status(RANGE_NOT_SATISFIABLE));
+        assert_eq!(
+            status(send_with(&fetcher, &http, URL, 0, None)),
+            Some(RANGE_NOT_SATISFIABLE)
+        );
+    }
+
+    #[test]
+    fn reports_not_modified_only_when_revalidating() {
+        let fetcher = ProtocFetcher::new("21.2");
+        let http = MockHttp::new().status(URL, NOT_MODIFIED);
+        let response = send_with(&fetcher, &http, URL, 0, Some("\"etag\"")).unwrap();
+        assert!(response.not_modified);
+        assert_eq!(body(response), "");
+        assert_eq!(
+            status(send_with(&fetcher, &http, URL, 0, None)),
+            Some(NOT_MODIFIED)
+        );
+    }
+
+    #[test]
+    fn fails_with_the_status_and_body_of_errors() {
+        let http = MockHttp::new();
+        let Err(err) = send_with(&ProtocFetcher::new("21.2"), &http, URL, 0, None) else {
+            panic!("the request succeeded");
+        };
+        match err.downcast_ref::<Error>() {
+            Some(Error::HttpStatus { url, status, body }) => {
+                assert_eq!((url.as_str(), *status), (URL, 404));
+                assert!(body.contains("no response"), "{body}");
+            }
+            _ => panic!("unexpected error: {err:?}"),
+        }
+    }
+}
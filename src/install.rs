@@ -0,0 +1,1386 @@
+//! Downloading and installing protoc releases.
+
+use crate::archive_store;
+use crate::checksums;
+use crate::deadline::Cancellation;
+use crate::digest;
+use crate::error::Error;
+use crate::extract::{self, ArchiveFormat};
+use crate::fetcher::{LockWait, ProtocFetcher, VendoredArchive};
+use crate::forge;
+use crate::glob::Glob;
+use crate::http;
+use crate::install_record::{self, InstallRecord};
+use crate::lock::{Acquired, InstallLock};
+use crate::logging;
+use crate::long_path;
+use crate::manifest::Manifest;
+use crate::platform::{self, Platform};
+use crate::post_install;
+use crate::progress::{Heartbeat, Progress, ProgressReader};
+use crate::quarantine;
+use crate::release_tag;
+use crate::resolve;
+#[cfg(feature = "build-from-source")]
+use crate::source_build;
+use crate::stream::{self, CopyError};
+use crate::system;
+use crate::wkt;
+use anyhow::{bail, Context};
+use std::fs::File;
+use std::io::Read;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use std::{env, fs};
+
+/// The name of the marker file written into an install directory once the install has been
+/// extracted and verified. It contains the digest of the release archive.
+pub(crate) const COMPLETE_MARKER: &str = ".complete";
+
+/// The env var naming a mirror to download releases from, as either a base URL or a URL template.
+const MIRROR_ENV_VAR: &str = "PROTOC_FETCHER_MIRROR";
+
+/// Appended to the directory of an install to name the directory it is staged in.
+pub(crate) const STAGING_SUFFIX: &str = ".partial";
+
+/// The subdirectory of an install directory that installs go into, one per release.
+pub(crate) const INSTALLS_DIR: &str = "protoc-fetcher";
+
+/// The link next to the installs that points at the most recently fetched one; see
+/// [`ProtocFetcher::link_current`].
+pub(crate) const CURRENT_LINK: &str = "current";
+
+/// Returns the directory that the release fetched by `fetcher` is (or will be) installed into.
+pub(crate) fn protoc_install_dir(
+    fetcher: &ProtocFetcher,
+    install_dir: &Path,
+) -> anyhow::Result<PathBuf> {
+    Ok(install_dir
+        .join(INSTALLS_DIR)
+        .join(get_protoc_release_name(fetcher)?))
+}
+
+/// Checks for an existing protoc of the given version in `protoc_dir`; if not found, then the
+/// official protoc release is downloaded and "installed", i.e., the binary is copied from the
+/// release archive into `protoc_dir`.
+///
+/// An existing install only counts if it has a completion marker, which is written once the install
+/// has been fully extracted and verified; anything else in `protoc_dir` is left over from an
+/// interrupted install and is deleted. If the release archive is still around (and matches the
+/// digest recorded when it was downloaded), protoc is re-extracted from it instead of being
+/// downloaded again.
+///
+/// Returns whether an existing install was reused.
+///
+/// The caller must hold the install lock for `protoc_dir`.
+pub(crate) fn ensure_protoc_installed(
+    fetcher: &ProtocFetcher,
+    protoc_dir: &Path,
+) -> anyhow::Result<bool> {
+    let version = fetcher.version.as_str();
+    let release_name = get_protoc_release_name(fetcher)?;
+
+    let protoc_path = protoc_path(protoc_dir, &release_platform(fetcher)?);
+    if !fetcher.reuse_cache && protoc_dir.exists() {
+        logging::info!("Not reusing the existing install {protoc_dir:?}, removing it.");
+        fs::remove_dir_all(protoc_dir)?;
+    }
+    let complete = protoc_dir.join(COMPLETE_MARKER).exists();
+    if complete && !installed_archive_is_expected(fetcher, protoc_dir)? {
+        logging::warn!(
+            "Existing install was not extracted from the expected archive, removing it."
+        );
+        fs::remove_dir_all(protoc_dir)?;
+        return ensure_protoc_installed(fetcher, protoc_dir);
+    }
+    if complete {
+        if let Some(reason) = install_record::mismatch(fetcher, protoc_dir)? {
+            logging::warn!(
+                "Existing install {protoc_dir:?} can't be reused, as {reason}; removing it."
+            );
+            fs::remove_dir_all(protoc_dir)?;
+            return ensure_protoc_installed(fetcher, protoc_dir);
+        }
+    }
+    if !complete {
+        if protoc_dir.exists() {
+            logging::debug!("{protoc_dir:?} has no completion marker, so it is incomplete.");
+        } else {
+            logging::debug!("protoc v{version} is not installed in {protoc_dir:?}.");
+        }
+    }
+    if complete && repair_permissions(protoc_dir) {
+        logging::warn!("Restored the permissions of the install in {protoc_dir:?}.");
+    }
+    let cache_hit = complete && installed_protoc_is_valid(fetcher, protoc_dir)?;
+    if cache_hit {
+        logging::info!("protoc with correct version is already installed.");
+        let mut checks = vec![if runs_on_host(fetcher) {
+            format!("its protoc reports v{version}")
+        } else {
+            "its protoc is present".to_string()
+        }];
+        if fetcher.verify_installed_binary {
+            checks.push("matches its recorded digest".to_string());
+        }
+        logging::debug!(
+            "Reusing {protoc_dir:?}: it is complete, {}.",
+            checks.join(" and ")
+        );
+    } else {
+        // Install into a staging directory next to the install, and only move it into place once
+        // complete, so that an interrupted install never leaves a half-extracted protoc behind.
+        let archive_name = release_asset_name(fetcher, &release_name);
+        let staging_dir = staging_dir(protoc_dir);
+        let staged_archive = staging_dir.join(&archive_name);
+        reset_dir(&staging_dir)?;
+
+        let retained_archive = protoc_dir.join(&archive_name);
+        let pinned_tag = release_tag::pinned(fetcher);
+        let mut installed = false;
+        if retained_archive_is_valid(fetcher, protoc_dir, &retained_archive)? {
+            logging::warn!(
+                "protoc v{version} incomplete or broken, re-extracting from retained archive..."
+            );
+            // The tag was checked before the archive was downloaded, as its install record says,
+            // unless it was pinned since.
+            if let Some(expected) = pinned_tag {
+                let record = InstallRecord::read(protoc_dir).and_then(|record| record.tag_sha);
+                if !record.is_some_and(|checked| checked.eq_ignore_ascii_case(expected)) {
+                    release_tag::verify(fetcher, expected)?;
+                }
+            }
+            fs::rename(&retained_archive, &staged_archive)?;
+            let archive_url = Manifest::read(protoc_dir)?.archive_url;
+            match install_from_archive(fetcher, &staging_dir, &staged_archive, archive_url) {
+                Ok(()) => installed = true,
+                Err(err) if is_broken_release(&err) => {
+                    logging::warn!("{err:#}; downloading the release again.");
+                    reset_dir(&staging_dir)?;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        if let Some(expected) = pinned_tag.filter(|_| !installed) {
+            release_tag::verify(fetcher, expected)?;
+        }
+        #[cfg(feature = "build-from-source")]
+        if !installed && source_build::applies(fetcher)? {
+            source_build::install(fetcher, &staging_dir)?;
+            installed = true;
+        }
+        // A release that turns out to be broken is downloaded once more, in case the download was
+        // damaged in a way its digest can't catch (e.g. served corrupt from the start). A vendored
+        // archive would just be broken again.
+        let max_attempts = if fetcher.archive.is_some() { 1 } else { 2 };
+        let mut attempts = 0;
+        while !installed {
+            attempts += 1;
+            logging::info!("protoc v{version} not found, installing...");
+            let archive_url = obtain_release_archive(fetcher, &staged_archive, &release_name)?;
+            match install_from_archive(fetcher, &staging_dir, &staged_archive, archive_url) {
+                Ok(()) => installed = true,
+                Err(err) if attempts < max_attempts && is_broken_release(&err) => {
+                    logging::warn!("{err:#}; downloading the release again.");
+                    reset_dir(&staging_dir)?;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        if protoc_dir.exists() {
+            logging::warn!("Removing incomplete install {protoc_dir:?}.");
+            fs::remove_dir_all(protoc_dir)?;
+        }
+        fs::rename(&staging_dir, protoc_dir)
+            .with_context(|| format!("Failed to move {staging_dir:?} to {protoc_dir:?}"))?;
+        logging::info!("protoc installed successfully: {protoc_path:?}");
+        fetcher.report(Progress::Installed { path: &protoc_path });
+    }
+    if runs_on_host(fetcher) {
+        logging::debug!("`protoc --version`: {}", get_protoc_version(&protoc_path)?);
+    }
+
+    Ok(cache_hit)
+}
+
+/// Records that the install in `protoc_dir` was just used, for
+/// [`prune_older_than`](crate::prune_older_than), by setting the modification time of its
+/// completion marker (which is otherwise normalized along with the rest of the install) to now.
+/// Failing to is not an error, e.g. for read-only caches.
+pub(crate) fn record_use(protoc_dir: &Path) {
+    let marker = protoc_dir.join(COMPLETE_MARKER);
+    let result = File::options()
+        .write(true)
+        .open(&marker)
+        .and_then(|file| file.set_modified(SystemTime::now()));
+    if let Err(err) = result {
+        logging::debug!("Failed to update the timestamp of {marker:?}: {err}");
+    }
+}
+
+/// How long to wait for another process to finish replacing the `current` link, which only takes
+/// as long as copying an install's `bin` and `include` directories.
+const LINK_LOCK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Points the `current` link next to the installs at the install in `protoc_dir`, replacing the
+/// install it pointed at before, and returns the path to the link; see
+/// [`ProtocFetcher::link_current`].
+///
+/// On Unix, the link is a relative symlink, so that it survives moving the install directory.
+/// Elsewhere (notably on Windows, where creating symlinks needs special privileges), it is a copy
+/// of the install's `bin` and `include` directories.
+pub(crate) fn link_current(
+    protoc_dir: &Path,
+    cancellation: &Cancellation,
+) -> anyhow::Result<PathBuf> {
+    let (Some(installs_dir), Some(name)) = (protoc_dir.parent(), protoc_dir.file_name()) else {
+        bail!("{protoc_dir:?} is not in an installs directory");
+    };
+    let current = installs_dir.join(CURRENT_LINK);
+    // Fetches of different versions hold the locks of different installs, so they take the lock
+    // of the link too: replacing a copy isn't atomic, as the old one has to be removed first.
+    let _lock =
+        match InstallLock::acquire(&current, &LockWait::Wait(LINK_LOCK_TIMEOUT), cancellation)? {
+            Acquired::Locked(lock) => lock,
+            Acquired::Contended => bail!(Error::Locked {
+                path: current,
+                waited: LINK_LOCK_TIMEOUT,
+            }),
+        };
+    // Create the new link under a name of its own and only then move it into place, so that an
+    // interrupted fetch can't leave a half-made link behind.
+    let staged = installs_dir.join(format!(
+        "{CURRENT_LINK}-{}{STAGING_SUFFIX}",
+        std::process::id()
+    ));
+    remove_link(&staged)?;
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(name, &staged)
+        .with_context(|| format!("Failed to create symlink {staged:?}"))?;
+    #[cfg(not(unix))]
+    {
+        let _ = name;
+        for dir in ["bin", "include"] {
+            if protoc_dir.join(dir).is_dir() {
+                copy_tree(&protoc_dir.join(dir), &staged.join(dir))?;
+            }
+        }
+        // Renaming can't replace a directory here.
+        remove_link(&current)?;
+    }
+    fs::rename(&staged, &current)
+        .with_context(|| format!("Failed to point {current:?} at {protoc_dir:?}"))?;
+    logging::debug!("Pointed {current:?} at {protoc_dir:?}.");
+    Ok(current)
+}
+
+/// Removes the `current` link (or copy) at `path`, if there is one.
+fn remove_link(path: &Path) -> anyhow::Result<()> {
+    let result = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(_) => return Ok(()),
+    };
+    result.with_context(|| format!("Failed to remove {path:?}"))
+}
+
+/// Copies the directory tree at `from` to `to`.
+#[cfg(not(unix))]
+fn copy_tree(from: &Path, to: &Path) -> anyhow::Result<()> {
+    fs::create_dir_all(to).with_context(|| format!("Failed to create directory {to:?}"))?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let to = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_tree(&entry.path(), &to)?;
+        } else {
+            fs::copy(entry.path(), &to)
+                .with_context(|| format!("Failed to copy {:?} to {to:?}", entry.path()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Checks whether the protoc of the install in `protoc_dir` can be reused: it must run and report
+/// the version of the release, which catches a corrupted binary as well as one that doesn't
+/// belong to the release (e.g. an `OUT_DIR` reused across toolchains). With
+/// [`ProtocFetcher::verify_installed_binary`], it must also still have the digest recorded when it
+/// was extracted.
+fn installed_protoc_is_valid(fetcher: &ProtocFetcher, protoc_dir: &Path) -> anyhow::Result<bool> {
+    let protoc_path = protoc_path(protoc_dir, &release_platform(fetcher)?);
+    if runs_on_host(fetcher) {
+        let reported = match get_protoc_version(&protoc_path) {
+            Ok(output) => system::reported_version(&output),
+            Err(err) => {
+                logging::warn!("Installed protoc {protoc_path:?} doesn't run: {err:#}");
+                return Ok(false);
+            }
+        };
+        // Versions such as release candidates aren't reported the way they are named.
+        if resolve::parse_components(&fetcher.version).is_some()
+            && !system::version_matches(&fetcher.version, &reported)
+        {
+            logging::warn!(
+                "Installed protoc {protoc_path:?} is v{reported}, not v{}.",
+                fetcher.version
+            );
+            return Ok(false);
+        }
+    } else if !protoc_path.is_file() {
+        // A release for another machine can't be run here, so there is less to check.
+        logging::warn!("Installed protoc {protoc_path:?} is missing.");
+        return Ok(false);
+    }
+    if fetcher.verify_installed_binary {
+        let recorded = Manifest::read(protoc_dir)?.protoc_sha256;
+        let actual = digest::sha256_file(&protoc_path, fetcher.buffer_size)?;
+        if recorded.as_deref() != Some(actual.as_str()) {
+            logging::warn!("Installed protoc {protoc_path:?} doesn't match its recorded digest.");
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Restores the permissions the install in `protoc_dir` needs on Unix, in case something took
+/// them away: a cache restored by a tool that drops mode bits, a restrictive umask, or an entry
+/// re-extracted with the mode its archive records. The binaries in `bin/` and the directories
+/// leading to them get mode 0755. Returns whether anything needed repairing. Failing to repair
+/// (e.g. in a read-only cache) isn't an error; running protoc then tells whether it matters.
+fn repair_permissions(protoc_dir: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        let bin_dir = protoc_dir.join("bin");
+        let mut paths = vec![protoc_dir.to_path_buf(), bin_dir.clone()];
+        if let Ok(entries) = fs::read_dir(&bin_dir) {
+            paths.extend(
+                entries
+                    .flatten()
+                    .map(|entry| entry.path())
+                    .filter(|p| p.is_file()),
+            );
+        }
+        let mut repaired = false;
+        for path in paths {
+            let Ok(metadata) = fs::metadata(&path) else {
+                continue;
+            };
+            let mode = metadata.permissions().mode() & 0o777;
+            if mode == 0o755 {
+                continue;
+            }
+            match fs::set_permissions(&path, fs::Permissions::from_mode(0o755)) {
+                Ok(()) => {
+                    logging::debug!("Changed the mode of {path:?} from {mode:o} to 755.");
+                    repaired = true;
+                }
+                Err(err) => logging::debug!("Can't change the mode of {path:?}: {err}"),
+            }
+        }
+        repaired
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = protoc_dir;
+        false
+    }
+}
+
+/// Removes the `com.apple.quarantine` attribute from the binary at `path`, if the fetcher
+/// [strips it](ProtocFetcher::strip_quarantine). Failing to is logged; the binary may run anyway.
+pub(crate) fn strip_quarantine(fetcher: &ProtocFetcher, path: &Path) {
+    if !fetcher.strip_quarantine {
+        return;
+    }
+    match quarantine::strip(path) {
+        Ok(true) => logging::debug!("Removed the quarantine attribute from {path:?}."),
+        Ok(false) => {}
+        Err(err) => {
+            logging::warn!("Failed to remove the quarantine attribute from {path:?}: {err}")
+        }
+    }
+}
+
+/// Returns the error for the binary at `path` failing to run with `err`. A binary that can't be
+/// run despite being executable is most likely on a file system mounted `noexec`, which is worth
+/// saying, since the error alone doesn't.
+pub(crate) fn broken_binary(path: PathBuf, err: &anyhow::Error) -> Error {
+    let mut reason = format!("{err:#}");
+    let denied = err
+        .downcast_ref::<std::io::Error>()
+        .is_some_and(|err| err.kind() == std::io::ErrorKind::PermissionDenied);
+    if denied {
+        reason.push_str(
+            "; the file system it is on may be mounted noexec, in which case install protoc \
+             elsewhere (see ProtocFetcher::install_dir)",
+        );
+    }
+    Error::BrokenBinary { path, reason }
+}
+
+/// Checks whether the install in `protoc_dir` was extracted from an archive with the expected
+/// digest, if any (see [`expected_sha256`]).
+fn installed_archive_is_expected(
+    fetcher: &ProtocFetcher,
+    protoc_dir: &Path,
+) -> anyhow::Result<bool> {
+    let Some(expected) = expected_sha256(fetcher)? else {
+        return Ok(true);
+    };
+    let recorded = Manifest::read(protoc_dir)?.archive_sha256;
+    Ok(recorded.is_some_and(|recorded| recorded.eq_ignore_ascii_case(&expected)))
+}
+
+/// Returns the digest the protoc release archive must have: the one given to
+/// [`ProtocFetcher::sha256`], or with [strict checksums](ProtocFetcher::strict_checksums) the one
+/// built into the crate, failing if there is none.
+pub(crate) fn expected_sha256(fetcher: &ProtocFetcher) -> anyhow::Result<Option<String>> {
+    if let Some(expected) = &fetcher.expected_sha256 {
+        return Ok(Some(expected.clone()));
+    }
+    if !fetcher.strict_checksums {
+        return Ok(None);
+    }
+    let version = resolve::tag_version(&fetcher.version);
+    let platform = release_platform(fetcher)?;
+    match checksums::known_sha256(&version, &platform) {
+        Some(expected) => Ok(Some(expected.to_string())),
+        None => bail!(Error::UnknownDigest { version, platform }),
+    }
+}
+
+/// Returns the directory that the install in `protoc_dir` is staged in until it is complete.
+pub(crate) fn staging_dir(protoc_dir: &Path) -> PathBuf {
+    let mut staging_dir = protoc_dir.as_os_str().to_owned();
+    staging_dir.push(STAGING_SUFFIX);
+    PathBuf::from(staging_dir)
+}
+
+/// Empties `dir`, creating it if need be.
+pub(crate) fn reset_dir(dir: &Path) -> anyhow::Result<()> {
+    if dir.exists() {
+        fs::remove_dir_all(dir)?;
+    }
+    fs::create_dir_all(dir)?;
+    Ok(())
+}
+
+/// Returns whether an install failed because of the release archive itself, i.e. it couldn't be
+/// (safely) extracted, or the protoc in it is missing or doesn't run.
+fn is_broken_release(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<Error>(),
+        Some(
+            Error::Extraction { .. }
+                | Error::UnsafeEntry { .. }
+                | Error::ArchiveTooLarge { .. }
+                | Error::MissingBinary { .. }
+                | Error::BrokenBinary { .. }
+        )
+    )
+}
+
+/// Checks whether a release archive retained from an earlier download is present and still
+/// matches the digest recorded in the manifest.
+fn retained_archive_is_valid(
+    fetcher: &ProtocFetcher,
+    protoc_dir: &Path,
+    archive_path: &Path,
+) -> anyhow::Result<bool> {
+    if !archive_path.exists() {
+        return Ok(false);
+    }
+    let Some(expected) = Manifest::read(protoc_dir)?.archive_sha256 else {
+        return Ok(false);
+    };
+    let actual = digest::sha256_file(archive_path, fetcher.buffer_size)?;
+    if actual != expected {
+        logging::warn!("Retained archive does not match its recorded digest, discarding it.");
+        fs::remove_file(archive_path)?;
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+/// Extracts the `include/` tree into `protoc_dir` if that hasn't happened yet, downloading the
+/// release archive again if it is no longer present.
+///
+/// The caller must hold the install lock for `protoc_dir`.
+pub(crate) fn ensure_include_extracted(
+    fetcher: &ProtocFetcher,
+    protoc_dir: &Path,
+) -> anyhow::Result<()> {
+    let mut manifest = Manifest::read(protoc_dir)?;
+    if manifest.include_extracted {
+        return Ok(());
+    }
+
+    let archive_path = ensure_archive(fetcher, protoc_dir, &mut manifest)?;
+    let include = Glob::new("include/**");
+    fetcher.report(Progress::Extracting {
+        archive: &archive_path,
+    });
+    extract::extract_archive(
+        &archive_path,
+        protoc_dir,
+        &fetcher.extract_options(),
+        |name| include.matches(name),
+    )?;
+    wkt::verify_include_tree(&protoc_dir.join("include"))?;
+    logging::info!("Extracted include directory.");
+
+    manifest.include_extracted = true;
+    manifest.write(protoc_dir)?;
+    extract::normalize_tree(protoc_dir)
+}
+
+/// Downloads the protobuf source release and extracts the protos in its `src/` tree into
+/// `protoc_dir/source`, if that hasn't happened yet. Only the protos are kept, and the (rather
+/// large) source archive is deleted afterwards.
+///
+/// The caller must hold the install lock for `protoc_dir`.
+pub(crate) fn ensure_source_extracted(
+    fetcher: &ProtocFetcher,
+    protoc_dir: &Path,
+) -> anyhow::Result<()> {
+    let mut manifest = Manifest::read(protoc_dir)?;
+    if manifest.source_extracted {
+        return Ok(());
+    }
+
+    let source_dir = protoc_dir.join("source");
+    if source_dir.exists() {
+        fs::remove_dir_all(&source_dir)?;
+    }
+    let source_name = source_release_name(fetcher);
+    let archive_path = protoc_dir.join(format!("{source_name}.zip"));
+    logging::info!(
+        "Downloading protobuf v{} source release...",
+        fetcher.version
+    );
+    download_archive(fetcher, &archive_path, &source_name, None)?;
+    // The source archive wraps everything in a `protobuf-<version>/` directory, which is stripped
+    // automatically; the fetcher's strip_components and archive_format apply to protoc release
+    // archives only.
+    let options = extract::ExtractOptions {
+        strip_components: None,
+        format: None,
+        ..fetcher.extract_options()
+    };
+    let protos = Glob::new("src/**/*.proto");
+    fetcher.report(Progress::Extracting {
+        archive: &archive_path,
+    });
+    extract::extract_archive(&archive_path, &source_dir, &options, |name| {
+        protos.matches(name)
+    })?;
+    fs::remove_file(&archive_path)?;
+    if !source_dir.join("src").is_dir() {
+        bail!(
+            "The protobuf source release for v{} contains no src/ protos",
+            fetcher.version
+        );
+    }
+    logging::info!("Extracted source release protos.");
+
+    manifest.source_extracted = true;
+    manifest.write(protoc_dir)?;
+    extract::normalize_tree(protoc_dir)
+}
+
+/// Returns the name of the protobuf source release of the fetcher's version, without the
+/// extension, e.g. `protobuf-21.2`.
+pub(crate) fn source_release_name(fetcher: &ProtocFetcher) -> String {
+    format!("protobuf-{}", resolve::asset_version(&fetcher.version))
+}
+
+/// Extracts the archive entries matching `patterns` (e.g. the fetcher's [`extract_also`]
+/// patterns) into `protoc_dir`, unless they were already extracted by an earlier fetch.
+///
+/// The caller must hold the install lock for `protoc_dir`.
+///
+/// [`extract_also`]: ProtocFetcher::extract_also
+pub(crate) fn ensure_entries_extracted(
+    fetcher: &ProtocFetcher,
+    protoc_dir: &Path,
+    patterns: &[Glob],
+) -> anyhow::Result<()> {
+    let mut manifest = Manifest::read(protoc_dir)?;
+    let missing: Vec<&Glob> = patterns
+        .iter()
+        .filter(|glob| !manifest.extra_entries.iter().any(|p| p == glob.as_str()))
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    #[cfg(feature = "build-from-source")]
+    if source_build::applies(fetcher)? {
+        logging::warn!(
+            "protoc was built from source, so there is no release archive to extract more from."
+        );
+        return Ok(());
+    }
+
+    let archive_path = ensure_archive(fetcher, protoc_dir, &mut manifest)?;
+    fetcher.report(Progress::Extracting {
+        archive: &archive_path,
+    });
+    extract::extract_archive(
+        &archive_path,
+        protoc_dir,
+        &fetcher.extract_options(),
+        |name| missing.iter().any(|glob| glob.matches(name)),
+    )?;
+    logging::info!("Extracted archive entries matching {missing:?}.");
+
+    let missing: Vec<String> = missing
+        .iter()
+        .map(|glob| glob.as_str().to_string())
+        .collect();
+    manifest.extra_entries.extend(missing);
+    manifest.write(protoc_dir)?;
+    // Re-extracted binaries get the mode the archive records, which may not be executable.
+    repair_permissions(protoc_dir);
+    extract::normalize_tree(protoc_dir)
+}
+
+/// Returns the path to the release archive retained in `protoc_dir`, downloading it again (and
+/// updating `manifest` accordingly) if it is missing or doesn't match its recorded digest.
+fn ensure_archive(
+    fetcher: &ProtocFetcher,
+    protoc_dir: &Path,
+    manifest: &mut Manifest,
+) -> anyhow::Result<PathBuf> {
+    let release_name = get_protoc_release_name(fetcher)?;
+    let archive_path = protoc_dir.join(release_asset_name(fetcher, &release_name));
+    if !retained_archive_is_valid(fetcher, protoc_dir, &archive_path)? {
+        logging::info!("Release archive not found, fetching it again...");
+        if let Some(expected) = release_tag::pinned(fetcher) {
+            release_tag::verify(fetcher, expected)?;
+        }
+        manifest.archive_url = obtain_release_archive(fetcher, &archive_path, &release_name)?;
+        manifest.archive_sha256 = Some(digest::sha256_file(&archive_path, fetcher.buffer_size)?);
+    }
+    Ok(archive_path)
+}
+
+/// Extracts only the protoc binary from the release archive into `protoc_dir`. The archive is kept
+/// so that the `include/` tree can be extracted later (and the binary re-extracted if need be)
+/// without re-downloading. `archive_url` is where the archive was downloaded from, if it was.
+fn install_from_archive(
+    fetcher: &ProtocFetcher,
+    protoc_dir: &Path,
+    archive_path: &Path,
+    archive_url: Option<String>,
+) -> anyhow::Result<()> {
+    let bin = Glob::new("bin/**");
+    fetcher.report(Progress::Extracting {
+        archive: archive_path,
+    });
+    extract::extract_archive(
+        archive_path,
+        protoc_dir,
+        &fetcher.extract_options(),
+        |name| bin.matches(name) || fetcher.extra_entries.iter().any(|glob| glob.matches(name)),
+    )?;
+    logging::debug!("Extracted archive.");
+
+    let platform = release_platform(fetcher)?;
+    let binary = protoc_binary(&platform);
+    let protoc_path = protoc_path(protoc_dir, &platform);
+    if !protoc_path.exists() {
+        return Err(Error::MissingBinary {
+            archive: archive_path.to_path_buf(),
+            binary: binary.to_string(),
+        }
+        .into());
+    }
+    // Some zip tooling drops Unix mode bits, so don't rely on the archive for these.
+    for entry in fs::read_dir(protoc_dir.join("bin"))? {
+        let path = entry?.path();
+        if path.is_file() {
+            extract::make_executable(&path)?;
+            strip_quarantine(fetcher, &path);
+        }
+    }
+    post_install::run(fetcher, &platform, protoc_dir, &protoc_path)?;
+    if !runs_on_host(fetcher) {
+        logging::info!("Not running {protoc_path:?}, which is built for another platform.");
+    } else if let Err(err) = get_protoc_version(&protoc_path) {
+        // What fails is running the missing glibc loader, reported as the unhelpful "No such file
+        // or directory".
+        if platform::host_is_musl() {
+            bail!(Error::Musl { path: protoc_path });
+        }
+        if quarantine::is_quarantined(&protoc_path) {
+            bail!(Error::Quarantined { path: protoc_path });
+        }
+        bail!(broken_binary(protoc_path, &err));
+    }
+
+    let archive_sha256 = digest::sha256_file(archive_path, fetcher.buffer_size)?;
+    Manifest {
+        include_extracted: false,
+        source_extracted: false,
+        archive_sha256: Some(archive_sha256.clone()),
+        archive_url: archive_url.clone(),
+        protoc_sha256: Some(digest::sha256_file(&protoc_path, fetcher.buffer_size)?),
+        extra_entries: fetcher
+            .extra_entries
+            .iter()
+            .map(|glob| glob.as_str().to_string())
+            .collect(),
+    }
+    .write(protoc_dir)?;
+    InstallRecord::new(fetcher, archive_url, &archive_sha256)?.write(protoc_dir)?;
+    // Only now is the install complete.
+    fs::write(
+        protoc_dir.join(COMPLETE_MARKER),
+        format!("{archive_sha256}\n"),
+    )?;
+    extract::normalize_tree(protoc_dir)?;
+
+    Ok(())
+}
+
+/// Puts the protoc release archive at `archive_path`: copied from the fetcher's vendored
+/// [`archive`](ProtocFetcher::archive) if it has one, and downloaded otherwise. Either way, the
+/// archive is checked against the expected digest and, with the `attestations` feature, its
+/// provenance is verified as configured. Returns the URL the archive was downloaded from, if it
+/// was.
+pub(crate) fn obtain_release_archive(
+    fetcher: &ProtocFetcher,
+    archive_path: &Path,
+    release_name: &str,
+) -> anyhow::Result<Option<String>> {
+    let url = match &fetcher.archive {
+        Some(archive) => {
+            copy_vendored_archive(fetcher, archive, archive_path)?;
+            None
+        }
+        None => match &fetcher.archive_store {
+            Some(store_dir) => Some(archive_store::obtain(
+                fetcher,
+                store_dir,
+                archive_path,
+                release_name,
+            )?),
+            None => Some(download_archive(
+                fetcher,
+                archive_path,
+                release_name,
+                expected_sha256(fetcher)?.as_deref(),
+            )?),
+        },
+    };
+    #[cfg(feature = "attestations")]
+    if let Err(err) = crate::attestation::verify(fetcher, archive_path) {
+        fs::remove_file(archive_path)?;
+        return Err(err);
+    }
+    Ok(url)
+}
+
+/// Copies the vendored `archive` to `archive_path`, checking it against the expected digest.
+fn copy_vendored_archive(
+    fetcher: &ProtocFetcher,
+    archive: &VendoredArchive,
+    archive_path: &Path,
+) -> anyhow::Result<()> {
+    let mut part_path = archive_path.as_os_str().to_owned();
+    part_path.push(".part");
+    let part_path = PathBuf::from(part_path);
+    match archive {
+        VendoredArchive::Path(path) => {
+            logging::info!("Installing from vendored archive {path:?}.");
+            fs::copy(path, &part_path)
+                .with_context(|| format!("Failed to copy vendored archive {path:?}"))?;
+        }
+        VendoredArchive::Bytes(bytes) => {
+            logging::info!("Installing from vendored archive bytes.");
+            fs::write(&part_path, bytes)?;
+        }
+    }
+    if let Some(expected) = expected_sha256(fetcher)? {
+        let actual = digest::sha256_file(&part_path, fetcher.buffer_size)?;
+        if !actual.eq_ignore_ascii_case(&expected) {
+            fs::remove_file(&part_path)?;
+            return Err(verification_error(archive_path, &expected, actual))
+                .context("The vendored archive is not the expected release");
+        }
+    }
+    fs::rename(&part_path, archive_path)?;
+    Ok(())
+}
+
+/// Streams the release archive to `archive_path`, so that memory usage doesn't depend on the size
+/// of the archive. The download goes to a temporary file that is only moved into place once
+/// complete.
+///
+/// Failures that may well be transient (connection problems, server errors, rate limiting and
+/// interrupted transfers) are retried as configured with [`ProtocFetcher::retries`]. A retry of
+/// an interrupted transfer resumes it with a range request, if the server supports those.
+///
+/// The download is then checked against `expected_sha256` and, if enabled with
+/// [`ProtocFetcher::verify_checksum_asset`], against the release's `.sha256` asset. An archive
+/// that fails either check is deleted.
+///
+/// With several [`mirrors`](ProtocFetcher::mirrors), each is tried in turn until one succeeds.
+/// Returns the URL the archive was downloaded from.
+pub(crate) fn download_archive(
+    fetcher: &ProtocFetcher,
+    archive_path: &Path,
+    release_name: &str,
+    expected_sha256: Option<&str>,
+) -> anyhow::Result<String> {
+    let archive_urls = protoc_release_archive_urls(fetcher, release_name)?;
+    let mut archive_urls = archive_urls.iter().peekable();
+    while let Some(archive_url) = archive_urls.next() {
+        match download_file(fetcher, archive_url, archive_path, expected_sha256) {
+            Ok(()) => {
+                logging::info!("Downloaded {release_name} from {archive_url}.");
+                return Ok(archive_url.clone());
+            }
+            Err(err) if archive_urls.peek().is_some() && !is_offline(&err) => {
+                logging::warn!("{err:#}; trying the next mirror.");
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    unreachable!("there is always at least one download URL")
+}
+
+fn is_offline(err: &anyhow::Error) -> bool {
+    matches!(err.downcast_ref::<Error>(), Some(Error::Offline { .. }))
+}
+
+/// Downloads `url` to `archive_path` the way [`download_archive`] downloads release archives:
+/// streamed, retried, verified, and only moved into place once complete.
+pub(crate) fn download_file(
+    fetcher: &ProtocFetcher,
+    archive_url: &str,
+    archive_path: &Path,
+    expected_sha256: Option<&str>,
+) -> anyhow::Result<()> {
+    if let Some(parent) = archive_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut part_path = archive_path.as_os_str().to_owned();
+    part_path.push(".part");
+
+    // A retry resumes where the failed attempt left off; whatever is left over from an earlier
+    // fetch may not be of the same download, though.
+    let part_path = PathBuf::from(part_path);
+    if part_path.exists() {
+        fs::remove_file(&part_path)?;
+    }
+    let mut attempt = 0;
+    loop {
+        match download(fetcher, archive_url, &part_path) {
+            Ok(()) => break,
+            Err(err)
+                if attempt < fetcher.retries
+                    && http::is_transient(&err)
+                    && fetcher.cancellation.check().is_ok() =>
+            {
+                attempt += 1;
+                logging::warn!(
+                    "Download failed ({err:#}), retrying ({attempt}/{})...",
+                    fetcher.retries
+                );
+                thread::sleep(fetcher.cancellation.clamp(retry_delay(fetcher, attempt)));
+            }
+            Err(err) => {
+                // Nothing resumes the download of a fetch that was stopped, so don't leave it
+                // behind.
+                if fetcher.cancellation.check().is_err() {
+                    let _ = fs::remove_file(&part_path);
+                }
+                return Err(err).with_context(|| format!("Failed to download {archive_path:?}"));
+            }
+        }
+    }
+    if let Err(err) = verify_download(
+        fetcher,
+        archive_url,
+        &part_path,
+        archive_path,
+        expected_sha256,
+    ) {
+        fs::remove_file(&part_path)?;
+        return Err(err);
+    }
+    fs::rename(&part_path, archive_path)?;
+    logging::debug!("Download successful.");
+
+    Ok(())
+}
+
+/// Checks the digest of the file at `path`, downloaded from `url` for `archive_path`, against
+/// `expected_sha256` and the checksum asset if configured.
+fn verify_download(
+    fetcher: &ProtocFetcher,
+    url: &str,
+    path: &Path,
+    archive_path: &Path,
+    expected_sha256: Option<&str>,
+) -> anyhow::Result<()> {
+    if expected_sha256.is_none() && !fetcher.verify_checksum_asset {
+        return Ok(());
+    }
+    let actual = digest::sha256_file(path, fetcher.buffer_size)?;
+    if let Some(expected) = expected_sha256 {
+        if !actual.eq_ignore_ascii_case(expected) {
+            return Err(verification_error(archive_path, expected, actual).into());
+        }
+    }
+    if fetcher.verify_checksum_asset {
+        let checksum_url = format!("{url}.sha256");
+        let mut contents = String::new();
+        http::get(fetcher, &checksum_url)?
+            .read_to_string(&mut contents)
+            .with_context(|| format!("Failed to download {checksum_url}"))?;
+        let published = digest::parse_checksum_file(&contents)
+            .with_context(|| format!("{checksum_url} does not contain a SHA-256 digest"))?;
+        if !actual.eq_ignore_ascii_case(published) {
+            return Err(verification_error(archive_path, published, actual))
+                .with_context(|| format!("{checksum_url} lists a different digest"));
+        }
+    }
+    logging::info!("Verified SHA-256 digest {actual}.");
+    Ok(())
+}
+
+fn verification_error(path: &Path, expected: &str, actual: String) -> Error {
+    Error::Verification {
+        path: path.to_path_buf(),
+        expected: expected.to_lowercase(),
+        actual,
+    }
+}
+
+/// The longest wait between retries of a failed download.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Returns how long to wait before retry number `attempt` (starting at 1) of a failed download:
+/// the fetcher's [`retry_backoff`](ProtocFetcher::retry_backoff), doubled for every retry before.
+fn retry_delay(fetcher: &ProtocFetcher, attempt: u32) -> Duration {
+    let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+    fetcher
+        .retry_backoff
+        .saturating_mul(factor)
+        .min(MAX_RETRY_DELAY)
+}
+
+/// Downloads `url` to `path` in a single attempt, resuming from what an earlier attempt left at
+/// `path` if the server supports range requests.
+fn download(fetcher: &ProtocFetcher, url: &str, path: &Path) -> anyhow::Result<()> {
+    let downloaded = fs::metadata(path).map_or(0, |metadata| metadata.len());
+    let response = http::get_from(fetcher, url, downloaded)?;
+    let offset = response.offset;
+    let total_bytes = response.content_length.map(|len| offset + len);
+    fetcher.report(Progress::DownloadStarted { url, total_bytes });
+    let mut file = if offset > 0 {
+        logging::info!("Resuming download of {url} at byte {offset}.");
+        File::options().append(true).open(path)?
+    } else {
+        File::create(path)?
+    };
+    let mut body = ProgressReader {
+        inner: response,
+        fetcher,
+        url,
+        total_bytes,
+        downloaded_bytes: offset,
+        heartbeat: Heartbeat::start(fetcher),
+    };
+    let started = Instant::now();
+    let received =
+        stream::copy(&mut body, &mut file, fetcher.buffer_size).map_err(|err| match err {
+            // The transfer was cut off.
+            CopyError::Read(err) => anyhow::Error::from(http::network_error(url, err)),
+            CopyError::Write(err) => err.into(),
+        })?;
+    file.sync_all()?;
+    logging::debug!(
+        "Received {received} bytes of {url} in {:.1?}.",
+        started.elapsed()
+    );
+    fetcher.report(Progress::DownloadFinished { url });
+    Ok(())
+}
+
+/// Returns the file name of the release asset named `release_name`: `{release_name}.zip`, or with
+/// the extension of the configured [archive format](ProtocFetcher::archive_format).
+pub(crate) fn release_asset_name(fetcher: &ProtocFetcher, release_name: &str) -> String {
+    let extension = fetcher
+        .archive_format
+        .map_or(".zip", ArchiveFormat::extension);
+    format!("{release_name}{extension}")
+}
+
+/// Returns the URLs to download the release asset named `release_name` from, in the order they
+/// are to be tried.
+pub(crate) fn protoc_release_archive_urls(
+    fetcher: &ProtocFetcher,
+    release_name: &str,
+) -> anyhow::Result<Vec<String>> {
+    let mirror = env::var(MIRROR_ENV_VAR).ok();
+    archive_urls(fetcher, release_name, mirror.as_deref())
+}
+
+/// Like [`protoc_release_archive_urls`], with `mirror` as the value of the `PROTOC_FETCHER_MIRROR`
+/// env var.
+fn archive_urls(
+    fetcher: &ProtocFetcher,
+    release_name: &str,
+    mirror: Option<&str>,
+) -> anyhow::Result<Vec<String>> {
+    let templates = if !fetcher.download_url_templates.is_empty() {
+        fetcher.download_url_templates.clone()
+    } else {
+        match mirror {
+            Some(mirror) if !mirror.is_empty() => vec![url_template(mirror)],
+            _ => vec![forge::download_url_template(fetcher)?],
+        }
+    };
+    let version = resolve::tag_version(&fetcher.version);
+    let platform = release_platform(fetcher)?;
+    let asset = release_asset_name(fetcher, release_name);
+    templates
+        .iter()
+        .map(|template| {
+            if !template.contains("{asset}") && !template.contains("{platform}") {
+                bail!(
+                    "Download URL template {template:?} contains neither {{asset}} nor \
+                     {{platform}}"
+                );
+            }
+            let archive_url = template
+                .replace("{tag}", &format!("v{version}"))
+                .replace("{version}", &version)
+                .replace("{platform}", &platform)
+                .replace("{asset}", &asset);
+            logging::debug!("Release URL: {archive_url}");
+            Ok(archive_url)
+        })
+        .collect()
+}
+
+/// Turns `mirror` into a download URL template: mirrors given as a base URL are taken to have the
+/// same layout as GitHub's release downloads, i.e. `<base>/v<version>/<asset>`.
+pub(crate) fn url_template(mirror: &str) -> String {
+    if mirror.contains('{') {
+        mirror.to_string()
+    } else {
+        format!("{}/{{tag}}/{{asset}}", mirror.trim_end_matches('/'))
+    }
+}
+
+/// Returns the name of the release archive `fetcher` fetches, without the extension, e.g.
+/// `protoc-21.2-linux-x86_64`, or `protoc-32.0-rc-1-linux-x86_64` for release candidates.
+pub(crate) fn get_protoc_release_name(fetcher: &ProtocFetcher) -> anyhow::Result<String> {
+    Ok(format!(
+        "protoc-{}-{}",
+        resolve::asset_version(&fetcher.version),
+        release_platform(fetcher)?
+    ))
+}
+
+/// Returns the platform of the release `fetcher` fetches, as named in the release archives: the
+/// configured [`platform`](ProtocFetcher::platform), or else that of the host (or, when
+/// [building from source](ProtocFetcher::build_from_source) on a host without releases, the
+/// host's OS and architecture).
+pub(crate) fn release_platform(fetcher: &ProtocFetcher) -> anyhow::Result<String> {
+    #[cfg(feature = "build-from-source")]
+    if fetcher.platform.is_none() {
+        if let Some(platform) = source_build::host_platform(fetcher) {
+            return Ok(platform);
+        }
+    }
+    match &fetcher.platform {
+        Some(platform) => Ok(platform.clone()),
+        None => platform::host_platform(fetcher.allow_emulation)
+            .map(|platform| platform.to_string())
+            .context(
+            "Use ProtocFetcher::platform to name the platform of a protoc build of your own, and \
+             ProtocFetcher::download_url_template to say where it is downloaded from; or build \
+             protoc from source with ProtocFetcher::build_from_source (`build-from-source` \
+             feature)",
+        ),
+    }
+}
+
+/// Returns the path of the protoc binary within an install of a release for `platform`:
+/// `bin/protoc.exe` for the Windows releases, and `bin/protoc` otherwise.
+pub(crate) fn protoc_binary(platform: &str) -> &'static str {
+    if platform.starts_with("win") {
+        "bin/protoc.exe"
+    } else {
+        "bin/protoc"
+    }
+}
+
+/// Returns the path of the protoc binary in the install `protoc_dir` of a release for
+/// `platform`, with the platform's separators throughout.
+pub(crate) fn protoc_path(protoc_dir: &Path, platform: &str) -> PathBuf {
+    long_path::join(protoc_dir, protoc_binary(platform))
+}
+
+/// Returns whether the releases fetched by `fetcher` run on the host, i.e. unless the fetcher's
+/// [`platform`](ProtocFetcher::platform) is an official one that is known not to. Only releases
+/// that run are checked by running them.
+pub(crate) fn runs_on_host(fetcher: &ProtocFetcher) -> bool {
+    let Some(platform) = fetcher.platform.as_deref() else {
+        return true;
+    };
+    let (Ok(platform), Ok(host)) = (
+        platform.parse::<Platform>(),
+        platform::host_platform(fetcher.allow_emulation),
+    ) else {
+        return true;
+    };
+    platform == host
+        || (platform == Platform::OsxUniversal
+            && matches!(host, Platform::OsxAarch64 | Platform::OsxX86_64))
+}
+
+/// Runs `protoc --version`, failing unless it succeeds and reports a version.
+pub(crate) fn get_protoc_version(protoc_path: &Path) -> anyhow::Result<String> {
+    let output = Command::new(protoc_path).arg("--version").output()?;
+    let version = String::from_utf8(output.stdout)?;
+    if !output.status.success() || !version.starts_with("libprotoc ") {
+        bail!(
+            "`protoc --version` failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::RELEASE_DOWNLOADS_URL;
+    #[cfg(unix)]
+    use crate::test_support::{fixtures, FakeRelease, MockHttp, Sandbox};
+
+    const VERSION: &str = "21.2";
+
+    /// Returns a mock serving a fake release of [`VERSION`] as the official one.
+    #[cfg(unix)]
+    fn serve_release() -> MockHttp {
+        MockHttp::new()
+            .fake_release(&FakeRelease::new(VERSION))
+            .unwrap()
+    }
+
+    /// Returns a fetcher of [`VERSION`] into `sandbox` that sends its requests to `http` and
+    /// retries without waiting.
+    #[cfg(unix)]
+    fn fetcher(sandbox: &Sandbox, http: &MockHttp) -> ProtocFetcher {
+        sandbox
+            .fetcher(VERSION)
+            .http_client(http.clone())
+            .retry_backoff(Duration::ZERO)
+    }
+
+    /// Returns the path of the release archive retained in the install of `fetcher`.
+    #[cfg(unix)]
+    fn retained_archive(fetcher: &ProtocFetcher, protoc_dir: &Path) -> PathBuf {
+        let release_name = get_protoc_release_name(fetcher).unwrap();
+        protoc_dir.join(release_asset_name(fetcher, &release_name))
+    }
+
+    // The fake releases' protoc is a shell script, so only Unix-like systems can install them.
+
+    #[cfg(unix)]
+    #[test]
+    fn reuses_a_completed_install() {
+        let http = serve_release();
+        let sandbox = Sandbox::new().unwrap();
+        let first = fetcher(&sandbox, &http).fetch().unwrap();
+        assert!(!first.cache_hit);
+        assert!(first.protoc_dir.join(COMPLETE_MARKER).is_file());
+
+        let second = fetcher(&sandbox, &http).fetch().unwrap();
+        assert!(second.cache_hit);
+        assert_eq!(second.path(), first.path());
+        assert_eq!(http.requests().len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn re_extracts_an_incomplete_install_from_the_retained_archive() {
+        let http = serve_release();
+        let sandbox = Sandbox::new().unwrap();
+        let first = fetcher(&sandbox, &http).fetch().unwrap();
+        fs::remove_file(first.protoc_dir.join(COMPLETE_MARKER)).unwrap();
+        fs::remove_file(first.path()).unwrap();
+
+        let second = fetcher(&sandbox, &http).fetch().unwrap();
+        assert!(!second.cache_hit);
+        assert!(second.path().is_file());
+        assert!(second.protoc_dir.join(COMPLETE_MARKER).is_file());
+        assert_eq!(http.requests().len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn downloads_again_if_the_retained_archive_changed() {
+        let http = serve_release();
+        let sandbox = Sandbox::new().unwrap();
+        let fetcher = fetcher(&sandbox, &http);
+        let first = fetcher.fetch().unwrap();
+        fs::remove_file(first.protoc_dir.join(COMPLETE_MARKER)).unwrap();
+        fs::write(
+            retained_archive(&fetcher, &first.protoc_dir),
+            "not the release",
+        )
+        .unwrap();
+
+        let second = fetcher.fetch().unwrap();
+        assert!(second.path().is_file());
+        assert_eq!(http.requests().len(), 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_a_download_not_matching_the_expected_sha256() {
+        let http = serve_release();
+        let sandbox = Sandbox::new().unwrap();
+        let expected = "0".repeat(64);
+        let fetcher = fetcher(&sandbox, &http).sha256(&expected);
+        let err = fetcher.fetch().unwrap_err();
+        let Error::Verification {
+            expected: wanted,
+            actual,
+            ..
+        } = &err
+        else {
+            panic!("expected a verification error, got {err:?}");
+        };
+        assert_eq!(wanted, &expected);
+        assert_eq!(actual, &fixtures::release_archive_sha256(VERSION).unwrap());
+
+        let protoc_dir = protoc_install_dir(&fetcher, sandbox.path()).unwrap();
+        assert!(!protoc_dir.join(COMPLETE_MARKER).exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn accepts_a_download_matching_the_expected_sha256() {
+        let http = serve_release();
+        let sandbox = Sandbox::new().unwrap();
+        let digest = fixtures::release_archive_sha256(VERSION).unwrap();
+        let protoc = fetcher(&sandbox, &http).sha256(&digest).fetch().unwrap();
+        assert_eq!(
+            Manifest::read(&protoc.protoc_dir).unwrap().archive_sha256,
+            Some(digest)
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn falls_back_to_the_next_mirror() {
+        let sandbox = Sandbox::new().unwrap();
+        let fetcher = sandbox.fetcher(VERSION).mirrors(&[
+            "https://down.example/protoc",
+            "https://up.example/{version}/{asset}",
+        ]);
+        let asset = release_asset_name(&fetcher, &get_protoc_release_name(&fetcher).unwrap());
+        let down = format!("https://down.example/protoc/v{VERSION}/{asset}");
+        let up = format!("https://up.example/{VERSION}/{asset}");
+        let http = MockHttp::new()
+            .status(&down, 503)
+            .respond(&up, FakeRelease::new(VERSION).to_bytes().unwrap());
+
+        let protoc = fetcher
+            .http_client(http.clone())
+            .retries(1)
+            .retry_backoff(Duration::ZERO)
+            .fetch()
+            .unwrap();
+        // The first mirror's download is retried before the next one is tried.
+        assert_eq!(http.requests(), [down.clone(), down, up.clone()]);
+        assert_eq!(
+            Manifest::read(&protoc.protoc_dir).unwrap().archive_url,
+            Some(up)
+        );
+    }
+
+    /// Returns the download URLs of `fetcher`'s release for linux-x86_64, with the
+    /// `PROTOC_FETCHER_MIRROR` env var set to `mirror`.
+    fn linux_urls(fetcher: ProtocFetcher, mirror: Option<&str>) -> anyhow::Result<Vec<String>> {
+        let fetcher = fetcher.platform("linux-x86_64");
+        archive_urls(&fetcher, &get_protoc_release_name(&fetcher)?, mirror)
+    }
+
+    #[test]
+    fn expands_url_templates() {
+        let fetcher = ProtocFetcher::new(VERSION).mirrors(&[
+            "https://mirror.example/releases/",
+            "https://mirror.example/{tag}/{version}/{asset}",
+            "https://builds.example/protoc-{version}/{platform}/protoc.zip",
+        ]);
+        assert_eq!(
+            linux_urls(fetcher, None).unwrap(),
+            [
+                "https://mirror.example/releases/v21.2/protoc-21.2-linux-x86_64.zip",
+                "https://mirror.example/v21.2/21.2/protoc-21.2-linux-x86_64.zip",
+                "https://builds.example/protoc-21.2/linux-x86_64/protoc.zip",
+            ]
+        );
+    }
+
+    #[test]
+    fn names_assets_after_the_archive_format() {
+        let fetcher = ProtocFetcher::new(VERSION).archive_format(ArchiveFormat::TarGz);
+        assert_eq!(
+            linux_urls(fetcher, None).unwrap(),
+            [format!(
+                "{RELEASE_DOWNLOADS_URL}/v21.2/protoc-21.2-linux-x86_64.tar.gz"
+            )]
+        );
+    }
+
+    #[test]
+    fn rejects_templates_without_the_asset_or_platform() {
+        let fetcher =
+            ProtocFetcher::new(VERSION).download_url_template("https://mirror.example/{version}");
+        let err = linux_urls(fetcher, None).unwrap_err();
+        assert!(
+            err.to_string().contains("neither {asset} nor {platform}"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn uses_the_mirror_env_var_unless_mirrors_are_configured() {
+        let fetcher = ProtocFetcher::new(VERSION);
+        assert_eq!(
+            linux_urls(fetcher.clone(), Some("https://mirror.example/")).unwrap(),
+            ["https://mirror.example/v21.2/protoc-21.2-linux-x86_64.zip"]
+        );
+        assert_eq!(
+            linux_urls(fetcher.clone(), Some("")).unwrap(),
+            [format!(
+                "{RELEASE_DOWNLOADS_URL}/v21.2/protoc-21.2-linux-x86_64.zip"
+            )]
+        );
+        assert_eq!(
+            linux_urls(
+                fetcher.download_base_url("https://configured.example"),
+                Some("https://mirror.example")
+            )
+            .unwrap(),
+            ["https://configured.example/v21.2/protoc-21.2-linux-x86_64.zip"]
+        );
+    }
+}
@@ -0,0 +1,141 @@
+//! The `install.json` record written next to each install, for reproducible builds: which release
+//! an install is of, where it came from, and when and by what it was installed.
+
+use crate::fetcher::ProtocFetcher;
+use crate::install;
+use crate::logging;
+use crate::release_tag;
+use crate::resolve;
+use anyhow::Context;
+use serde_json::{json, Value};
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+const RECORD_FILE_NAME: &str = "install.json";
+
+/// The version of the install layout. Installs recorded with another format are reinstalled
+/// rather than reused, so that a change to the layout invalidates older installs cleanly; bump it
+/// with any such change.
+const FORMAT_VERSION: u64 = 1;
+
+/// The contents of an install's `install.json`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct InstallRecord {
+    /// The [`FORMAT_VERSION`] of the install.
+    pub format: u64,
+    /// The version of the release, as spelled in its tag (minus the "v"), e.g. "32.0-rc1".
+    pub version: String,
+    /// The platform of the release, e.g. `linux-x86_64`.
+    pub platform: String,
+    /// The URL the archive was downloaded from, unless it was vendored.
+    pub url: Option<String>,
+    /// The hex-encoded SHA-256 digest of the archive.
+    pub archive_sha256: String,
+    /// The SHA the release tag was verified to be at, with [`ProtocFetcher::tag_sha`].
+    pub tag_sha: Option<String>,
+    /// When the install was made, in seconds since the Unix epoch.
+    pub installed_at: u64,
+    /// The crate (and its version) that made the install, e.g. "protoc-fetcher 0.1.1".
+    pub extractor: String,
+}
+
+impl InstallRecord {
+    /// Returns the record of an install of the release `fetcher` fetches, made just now from the
+    /// archive downloaded from `url` with digest `archive_sha256`.
+    pub fn new(
+        fetcher: &ProtocFetcher,
+        url: Option<String>,
+        archive_sha256: &str,
+    ) -> anyhow::Result<Self> {
+        let installed_at = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        Ok(InstallRecord {
+            format: FORMAT_VERSION,
+            version: resolve::tag_version(&fetcher.version),
+            platform: install::release_platform(fetcher)?,
+            url,
+            archive_sha256: archive_sha256.to_string(),
+            tag_sha: release_tag::pinned(fetcher).map(str::to_ascii_lowercase),
+            installed_at,
+            extractor: format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+        })
+    }
+
+    /// Reads the record of the install in `install_dir`, or returns `None` if it has none (i.e.
+    /// it predates the record) or the record can't be read.
+    pub fn read(install_dir: &Path) -> Option<Self> {
+        let contents = fs::read_to_string(install_dir.join(RECORD_FILE_NAME)).ok()?;
+        let record: Value = match serde_json::from_str(&contents) {
+            Ok(record) => record,
+            Err(err) => {
+                logging::warn!("Ignoring the unreadable install record in {install_dir:?}: {err}");
+                return None;
+            }
+        };
+        Some(InstallRecord {
+            format: record["format"].as_u64()?,
+            version: record["version"].as_str()?.to_string(),
+            platform: record["platform"].as_str()?.to_string(),
+            url: record["url"].as_str().map(str::to_string),
+            archive_sha256: record["archive_sha256"].as_str()?.to_string(),
+            tag_sha: record["tag_sha"].as_str().map(str::to_string),
+            installed_at: record["installed_at"].as_u64().unwrap_or_default(),
+            extractor: record["extractor"].as_str().unwrap_or_default().to_string(),
+        })
+    }
+
+    /// Writes the record into `install_dir`.
+    pub fn write(&self, install_dir: &Path) -> anyhow::Result<()> {
+        let record = json!({
+            "format": self.format,
+            "version": self.version,
+            "platform": self.platform,
+            "url": self.url,
+            "archive_sha256": self.archive_sha256,
+            "tag_sha": self.tag_sha,
+            "installed_at": self.installed_at,
+            "extractor": self.extractor,
+        });
+        let path = install_dir.join(RECORD_FILE_NAME);
+        fs::write(&path, serde_json::to_string_pretty(&record)? + "\n")
+            .with_context(|| format!("Failed to write {path:?}"))
+    }
+}
+
+/// Checks the record of the install in `install_dir` against the release `fetcher` fetches,
+/// returning why the install can't be reused if it doesn't match: it is of another format, or
+/// another release, or wasn't verified against the fetcher's [tag SHA](ProtocFetcher::tag_sha).
+/// An install without a record predates it and isn't checked, except against a tag SHA.
+pub(crate) fn mismatch(
+    fetcher: &ProtocFetcher,
+    install_dir: &Path,
+) -> anyhow::Result<Option<String>> {
+    let Some(record) = InstallRecord::read(install_dir) else {
+        return Ok(
+            release_tag::pinned(fetcher).map(|_| "it has no record of its release tag".to_string())
+        );
+    };
+    let version = resolve::tag_version(&fetcher.version);
+    let platform = install::release_platform(fetcher)?;
+    Ok(if record.format != FORMAT_VERSION {
+        Some(format!(
+            "it has install format {}, not {FORMAT_VERSION}",
+            record.format
+        ))
+    } else if record.version != version || record.platform != platform {
+        Some(format!(
+            "it is recorded as protoc {} for {}, not {version} for {platform}",
+            record.version, record.platform
+        ))
+    } else if let Some(expected) = release_tag::pinned(fetcher) {
+        match &record.tag_sha {
+            Some(sha) if sha.eq_ignore_ascii_case(expected) => None,
+            Some(sha) => Some(format!("its release tag was at {sha}, not {expected}")),
+            None => Some("its release tag wasn't verified".to_string()),
+        }
+    } else {
+        None
+    })
+}
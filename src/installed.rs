@@ -0,0 +1,206 @@
+//! Listing and removing the protoc installs in an install directory, e.g. to garbage-collect old
+//! releases from a long-lived cache.
+
+use crate::deadline::Cancellation;
+use crate::error::Error;
+use crate::fetcher::{LockWait, ProtocFetcher};
+use crate::install::{self, COMPLETE_MARKER, INSTALLS_DIR, STAGING_SUFFIX};
+use crate::lock::{Acquired, InstallLock};
+use crate::logging;
+use crate::plugin::PLUGINS_DIR;
+use crate::resolve;
+use crate::wkt::WKT_DIR;
+use anyhow::Context;
+use std::cmp::Ordering;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// A protoc release installed in an install directory, as listed by [`installed_versions`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstalledRelease {
+    /// The version of the release, e.g. "21.2".
+    pub version: String,
+    /// The platform of the release, as named in the release archives, e.g. "linux-x86_64".
+    pub platform: String,
+    /// The install's directory, containing `bin/protoc`.
+    pub path: PathBuf,
+    /// When the install was last fetched, i.e. installed or reused by a build.
+    pub last_used: SystemTime,
+}
+
+/// Lists the completed protoc installs in `install_dir` (the directory given to
+/// [`ProtocFetcher::install_dir`](crate::ProtocFetcher::install_dir)), oldest version first.
+///
+/// ```no_run
+/// # fn main() -> anyhow::Result<()> {
+/// for release in protoc_fetcher::installed_versions("/ci-cache/protoc")? {
+///     println!("{} ({})", release.version, release.platform);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn installed_versions(install_dir: impl AsRef<Path>) -> Result<Vec<InstalledRelease>, Error> {
+    let installs_dir = install_dir.as_ref().join(INSTALLS_DIR);
+    Ok(read_installs(&installs_dir)
+        .with_context(|| format!("Failed to list the installs in {installs_dir:?}"))?)
+}
+
+fn read_installs(installs_dir: &Path) -> anyhow::Result<Vec<InstalledRelease>> {
+    let mut releases = Vec::new();
+    if !installs_dir.is_dir() {
+        return Ok(releases);
+    }
+    for entry in fs::read_dir(installs_dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !entry.file_type()?.is_dir()
+            || name == PLUGINS_DIR
+            || name == WKT_DIR
+            || name.ends_with(STAGING_SUFFIX)
+        {
+            continue;
+        }
+        let Some((version, platform)) = parse_install_name(&name) else {
+            continue;
+        };
+        let Ok(marker) = fs::metadata(entry.path().join(COMPLETE_MARKER)) else {
+            continue;
+        };
+        releases.push(InstalledRelease {
+            version: resolve::tag_version(version),
+            platform: platform.to_string(),
+            path: entry.path(),
+            last_used: marker.modified()?,
+        });
+    }
+    releases.sort_by(|a, b| {
+        compare_versions(&a.version, &b.version).then_with(|| a.platform.cmp(&b.platform))
+    });
+    Ok(releases)
+}
+
+/// Deletes every install of protoc `version` (for any platform) from `install_dir`, and returns
+/// the directories deleted. Installs that another process is using right now are left alone.
+pub fn uninstall(version: &str, install_dir: impl AsRef<Path>) -> Result<Vec<PathBuf>, Error> {
+    let releases = installed_versions(install_dir)?
+        .into_iter()
+        .filter(|release| release.version == resolve::tag_version(version));
+    Ok(remove_all(releases)?)
+}
+
+/// Deletes the installs in `install_dir` of versions other than the one `fetcher` fetches, for
+/// [`ProtocFetcher::remove_stale_installs`].
+pub(crate) fn remove_other_versions(
+    install_dir: &Path,
+    fetcher: &ProtocFetcher,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let version = resolve::tag_version(&fetcher.version);
+    let releases = installed_versions(install_dir)?
+        .into_iter()
+        .filter(|release| release.version != version);
+    remove_all(releases)
+}
+
+/// Deletes the installs in `install_dir` that haven't been fetched for longer than `age`, and
+/// returns the directories deleted. Installs that another process is using right now are left
+/// alone.
+///
+/// ```no_run
+/// # fn main() -> anyhow::Result<()> {
+/// # use std::time::Duration;
+/// // Drop whatever no build has used for 30 days.
+/// protoc_fetcher::prune_older_than("/ci-cache/protoc", Duration::from_secs(30 * 24 * 60 * 60))?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn prune_older_than(
+    install_dir: impl AsRef<Path>,
+    age: Duration,
+) -> Result<Vec<PathBuf>, Error> {
+    let now = SystemTime::now();
+    let releases = installed_versions(install_dir)?
+        .into_iter()
+        .filter(|release| {
+            now.duration_since(release.last_used)
+                .is_ok_and(|unused| unused > age)
+        });
+    Ok(remove_all(releases)?)
+}
+
+/// Deletes the installs in `install_dir` of all but the newest `n` versions, and returns the
+/// directories deleted. Installs that another process is using right now are left alone.
+pub fn keep_latest(install_dir: impl AsRef<Path>, n: usize) -> Result<Vec<PathBuf>, Error> {
+    let releases = installed_versions(install_dir)?;
+    let mut versions: Vec<&str> = releases
+        .iter()
+        .map(|release| release.version.as_str())
+        .collect();
+    versions.dedup();
+    let keep = &versions[versions.len().saturating_sub(n)..];
+    let releases: Vec<_> = releases
+        .iter()
+        .filter(|release| !keep.contains(&release.version.as_str()))
+        .cloned()
+        .collect();
+    Ok(remove_all(releases)?)
+}
+
+/// Deletes the given installs, skipping the ones that are locked by another process.
+fn remove_all(
+    releases: impl IntoIterator<Item = InstalledRelease>,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+    for release in releases {
+        let path = release.path;
+        let _lock = match InstallLock::acquire(&path, &LockWait::Fail, &Cancellation::default())? {
+            Acquired::Locked(lock) => lock,
+            Acquired::Contended => {
+                logging::warn!("{path:?} is in use by another process, not deleting it.");
+                continue;
+            }
+        };
+        fs::remove_dir_all(&path).with_context(|| format!("Failed to delete {path:?}"))?;
+        let staging_dir = install::staging_dir(&path);
+        if staging_dir.exists() {
+            fs::remove_dir_all(&staging_dir)
+                .with_context(|| format!("Failed to delete {staging_dir:?}"))?;
+        }
+        logging::info!("Deleted {path:?}.");
+        removed.push(path);
+    }
+    Ok(removed)
+}
+
+/// Splits the name of an install directory, e.g. `protoc-21.2-linux-x86_64`, into the version and
+/// the platform.
+pub(crate) fn parse_install_name(name: &str) -> Option<(&str, &str)> {
+    let rest = name.strip_prefix("protoc-")?;
+    // Versions may contain dashes too (e.g. release candidates), but platforms start with an OS.
+    let split = rest
+        .match_indices('-')
+        .map(|(i, _)| i)
+        .find(|&i| {
+            ["linux", "osx", "win"]
+                .iter()
+                .any(|os| rest[i + 1..].starts_with(os))
+        })
+        .or_else(|| rest.find('-'))?;
+    Some((&rest[..split], &rest[split + 1..]))
+}
+
+/// Orders versions numerically by component, e.g. "9.0" before "21.2", with release candidates
+/// before their release; versions that aren't plain numbers sort by name.
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    match (version_key(a), version_key(b)) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        _ => a.cmp(b),
+    }
+}
+
+/// Returns the components of `version`, followed by the number of the release candidate, or
+/// `u32::MAX` for the release itself.
+fn version_key(version: &str) -> Option<(Vec<u32>, u32)> {
+    let (release, rc) = resolve::release_candidate(version).unwrap_or((version, u32::MAX));
+    Some((resolve::parse_components(release)?, rc))
+}
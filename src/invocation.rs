@@ -0,0 +1,150 @@
+//! A typed builder for protoc invocations that the purpose-built methods don't cover.
+
+use crate::compile::{self, DescriptorOptions, Outputs, ProtocOutput};
+use crate::error::Error;
+use crate::protoc::Protoc;
+use anyhow::Context;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A protoc invocation being put together, as returned by [`Protoc::invocation`]. Saves building
+/// the arguments as strings:
+///
+/// ```no_run
+/// # fn main() -> anyhow::Result<()> {
+/// use protoc_fetcher::Protoc;
+///
+/// let output = Protoc::fetch("31.1", "target/protoc")?
+///     .invocation()
+///     .include("proto")
+///     .input("proto/foo.proto")
+///     .descriptor_set_out("target/foo.pb")
+///     .include_imports(true)
+///     .run()?;
+/// output.emit_rerun_if_changed();
+/// # Ok(())
+/// # }
+/// ```
+///
+/// As with [`Protoc::compile`], the release's `include` directory is always an include path, and
+/// output directories are created if need be.
+#[derive(Debug, Clone)]
+pub struct Invocation<'a> {
+    protoc: &'a Protoc,
+    includes: Vec<PathBuf>,
+    inputs: Vec<PathBuf>,
+    outputs: Outputs,
+    descriptor_set_out: Option<PathBuf>,
+    descriptor_options: DescriptorOptions,
+    args: Vec<OsString>,
+}
+
+impl<'a> Invocation<'a> {
+    pub(crate) fn new(protoc: &'a Protoc) -> Self {
+        Invocation {
+            protoc,
+            includes: Vec::new(),
+            inputs: Vec::new(),
+            outputs: Outputs::new(),
+            descriptor_set_out: None,
+            descriptor_options: DescriptorOptions::default(),
+            args: Vec::new(),
+        }
+    }
+
+    /// Adds an include path (`-I`), searched for imports and inputs in the order added.
+    pub fn include(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.includes.push(dir.into());
+        self
+    }
+
+    /// Adds a proto to compile. It must be within one of the include paths.
+    pub fn input(mut self, proto: impl Into<PathBuf>) -> Self {
+        self.inputs.push(proto.into());
+        self
+    }
+
+    /// Adds several protos to compile, as with [`input`](Self::input).
+    pub fn inputs(mut self, protos: impl IntoIterator<Item = impl Into<PathBuf>>) -> Self {
+        self.inputs.extend(protos.into_iter().map(Into::into));
+        self
+    }
+
+    /// Sets the code generators to run; see [`Outputs`].
+    pub fn outputs(mut self, outputs: Outputs) -> Self {
+        self.outputs = outputs;
+        self
+    }
+
+    /// Writes the `FileDescriptorSet` of the inputs to `path` (`--descriptor_set_out`).
+    pub fn descriptor_set_out(mut self, path: impl Into<PathBuf>) -> Self {
+        self.descriptor_set_out = Some(path.into());
+        self
+    }
+
+    /// Sets whether the descriptor set includes the (transitive) imports of the inputs too
+    /// (`--include_imports`). Defaults to false.
+    pub fn include_imports(mut self, include_imports: bool) -> Self {
+        self.descriptor_options.include_imports = include_imports;
+        self
+    }
+
+    /// Sets whether the descriptor set keeps comments and source locations
+    /// (`--include_source_info`). Defaults to false.
+    pub fn include_source_info(mut self, include_source_info: bool) -> Self {
+        self.descriptor_options.include_source_info = include_source_info;
+        self
+    }
+
+    /// Adds a raw argument, for flags that have no method of their own, e.g.
+    /// `--experimental_allow_proto3_optional`.
+    pub fn arg(mut self, arg: impl AsRef<OsStr>) -> Self {
+        self.args.push(arg.as_ref().to_os_string());
+        self
+    }
+
+    /// Returns the invocation as a [`Command`], to run it yourself. Unlike [`run`](Self::run),
+    /// this doesn't create the output directories.
+    pub fn to_command(&self) -> Result<Command, Error> {
+        let mut command = self.protoc.command()?;
+        command
+            .args(compile::include_args(&self.includes))
+            .args(self.outputs.args())
+            .args(self.descriptor_args())
+            .args(&self.args)
+            .args(&self.inputs);
+        Ok(command)
+    }
+
+    /// Runs the invocation. If protoc runs but fails, the error is an
+    /// [`Error::ProtocFailed`].
+    pub fn run(&self) -> Result<ProtocOutput, Error> {
+        if let Some(parent) = self.descriptor_set_out.as_deref().and_then(Path::parent) {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create {parent:?}"))?;
+            }
+        }
+        let mut command = self.protoc.command()?;
+        command.args(self.descriptor_args()).args(&self.args);
+        Ok(self
+            .protoc
+            .compile_with(command, &self.inputs, &self.includes, &self.outputs)?)
+    }
+
+    fn descriptor_args(&self) -> Vec<OsString> {
+        let Some(out) = &self.descriptor_set_out else {
+            return Vec::new();
+        };
+        let mut args = vec![compile::path_arg("descriptor_set_out", out)];
+        args.extend(
+            self.descriptor_options
+                .args()
+                .into_iter()
+                .map(OsString::from),
+        );
+        args
+    }
+}
@@ -1,18 +1,101 @@
 //! Download official protobuf compiler (protoc) releases with a single command, pegged to the
 //! version of your choice.
 
-use anyhow::bail;
-use reqwest::StatusCode;
-use std::io::Cursor;
+mod api_cache;
+mod archive_store;
+#[cfg(feature = "attestations")]
+mod attestation;
+mod batch;
+pub mod build;
+mod cache;
+mod capabilities;
+mod checksums;
+#[cfg(any(feature = "prost", feature = "tonic"))]
+mod codegen;
+pub mod compat;
+mod compile;
+mod config;
+mod deadline;
+mod diagnostics;
+mod digest;
+mod error;
+mod extract;
+mod fetcher;
+mod forge;
+mod glob;
+mod http;
+mod install;
+mod install_record;
+mod installed;
+mod invocation;
+mod lock;
+mod logging;
+mod long_path;
+mod manifest;
+mod metadata;
+mod plan;
+mod platform;
+mod plugin;
+mod post_install;
+mod progress;
+mod protoc;
+mod proxy;
+mod quarantine;
+mod release_asset;
+mod release_tag;
+mod requirements;
+mod resolve;
+mod seed;
+#[cfg(feature = "build-from-source")]
+mod source_build;
+mod stream;
+mod system;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+mod toolchain;
+mod transport;
+mod validate;
+mod version;
+mod version_req;
+mod wkt;
+
+#[cfg(feature = "attestations")]
+pub use crate::attestation::AttestationPolicy;
+pub use crate::batch::{fetch_many, FetchedTool, ToolSpec};
+pub use crate::cache::{cargo_target_dir, user_cache_dir};
+pub use crate::capabilities::Capabilities;
+pub use crate::compile::{DescriptorOptions, Outputs, ProtocFailure, ProtocOutput};
+pub use crate::diagnostics::{Diagnostic, Severity};
+pub use crate::error::Error;
+pub use crate::extract::ArchiveFormat;
+pub use crate::fetcher::{ExtractionPolicy, LockWait, ProtocFetcher, VendoredArchive};
+pub use crate::installed::{
+    installed_versions, keep_latest, prune_older_than, uninstall, InstalledRelease,
+};
+pub use crate::invocation::Invocation;
+pub use crate::logging::Verbosity;
+pub use crate::metadata::ProtocInstall;
+pub use crate::plan::FetchPlan;
+pub use crate::platform::Platform;
+pub use crate::plugin::KNOWN_PLUGINS;
+pub use crate::post_install::PostInstall;
+pub use crate::progress::Progress;
+pub use crate::protoc::Protoc;
+pub use crate::requirements::{min_protoc_version, ProtocFeature};
+pub use crate::seed::PlatformAsset;
+pub use crate::toolchain::Toolchain;
+pub use crate::transport::{HttpClient, HttpRequest, HttpResponse};
+pub use crate::validate::{validate_cache, CacheProblem, CacheReport, InstallReport};
+pub use crate::version::{ensure_min_version, ProtocVersion};
+pub use crate::wkt::{ExistingFiles, WktAvailability, WELL_KNOWN_TYPES};
+use std::env;
 use std::path::{Path, PathBuf};
-use std::process::Command;
-use std::{env, fs};
 
 /// Downloads an official [release] of the protobuf compiler (protoc) and returns the path to it.
 ///
 /// The release archive matching the given `version` will be downloaded, and the protoc binary will
 /// be extracted into a subdirectory of `out_dir`. You can choose a `version` from the
-/// [release] page, for example "21.2". Don't prefix it with a "v".
+/// [release] page, for example "21.2". There's no need to prefix it with a "v".
 ///
 /// `out_dir` can be anywhere you want, but if calling this function from a build script, you should
 /// probably use the `OUT_DIR` env var (which is set by Cargo automatically for build scripts).
@@ -20,6 +103,9 @@ use std::{env, fs};
 /// A previously downloaded protoc binary of the correct version will be reused if already present
 /// in `out_dir`.
 ///
+/// Only the protoc binary is extracted; the bundled `include/` tree is extracted on demand the
+/// first time [`include_path`] is called.
+///
 /// # Examples:
 ///
 /// ```no_run
@@ -46,87 +132,364 @@ use std::{env, fs};
 /// [release]: https://github.com/protocolbuffers/protobuf/releases
 /// [tonic-build]: https://crates.io/crates/tonic-build
 /// [prost-build]: https://crates.io/crates/prost-build
-pub fn protoc(version: &str, out_dir: &Path) -> anyhow::Result<PathBuf> {
-    let protoc_path = ensure_protoc_installed(version, out_dir)?;
+pub fn protoc(version: &str, out_dir: &Path) -> Result<PathBuf, Error> {
+    let protoc = ProtocFetcher::new(version).install_dir(out_dir).fetch()?;
 
-    Ok(protoc_path)
+    Ok(protoc.path().to_path_buf())
 }
 
-/// Checks for an existing protoc of the given version; if not found, then the official protoc
-/// release is downloaded and "installed", i.e., the binary is copied from the release archive
-/// into the `generated` directory.
-fn ensure_protoc_installed(version: &str, install_dir: &Path) -> anyhow::Result<PathBuf> {
-    let release_name = get_protoc_release_name(version);
-
-    let protoc_dir = install_dir.join(format!("protoc-fetcher/{release_name}"));
-    let protoc_path = protoc_dir.join("bin/protoc");
-    if protoc_path.exists() {
-        println!("protoc with correct version is already installed.");
-    } else {
-        println!("protoc v{version} not found, downloading...");
-        download_protoc(&protoc_dir, &release_name, version)?;
+/// Returns what [`protoc`] would download and where it would install it, without any network I/O;
+/// see [`ProtocFetcher::plan`].
+///
+/// # Examples:
+///
+/// ```no_run
+/// # use std::path::Path;
+/// let plan = protoc_fetcher::plan("21.2", Path::new("target/protoc")).unwrap();
+/// println!("{} from {:?}", plan.asset_name, plan.urls);
+/// ```
+pub fn plan(version: &str, out_dir: &Path) -> Result<FetchPlan, Error> {
+    ProtocFetcher::new(version).install_dir(out_dir).plan()
+}
+
+/// Fetches protoc as with [`protoc`], but the version to fetch (along with the optional `sha256`
+/// and `mirror` settings) is read from a config file rather than given: a `protoc-version.toml`,
+/// or the `[package.metadata.protoc-fetcher]` or `[workspace.metadata.protoc-fetcher]` table of
+/// `Cargo.toml`, in the crate's directory or a parent directory such as the workspace root. See
+/// [`ProtocFetcher::from_config`].
+///
+/// # Examples:
+///
+/// ```toml
+/// # In the workspace's Cargo.toml...
+/// [workspace.metadata.protoc-fetcher]
+/// version = "21.2"
+/// ```
+///
+/// ```no_run
+/// # use std::env;
+/// # use std::path::Path;
+/// // From within build.rs...
+/// let out_dir = env::var("OUT_DIR").unwrap();
+/// let protoc_path = protoc_fetcher::protoc_from_config(Path::new(&out_dir));
+/// ```
+pub fn protoc_from_config(out_dir: &Path) -> Result<PathBuf, Error> {
+    let config = config::find()?;
+    let mut fetcher = config.fetcher();
+    // A `cache-dir` in the config takes the place of `out_dir`.
+    if config.cache_dir.is_none() {
+        fetcher = fetcher.install_dir(out_dir);
     }
-    println!(
-        "`protoc --version`: {}",
-        get_protoc_version(&protoc_path).unwrap()
-    );
+    let protoc = fetcher.fetch()?;
 
-    Ok(protoc_path)
+    Ok(protoc.path().to_path_buf())
 }
 
-fn download_protoc(protoc_dir: &Path, release_name: &str, version: &str) -> anyhow::Result<()> {
-    let archive_url = protoc_release_archive_url(release_name, version);
-    let response = reqwest::blocking::get(archive_url)?;
-    if response.status() != StatusCode::OK {
-        bail!(
-            "Error downloading release archive: {} {}",
-            response.status(),
-            response.text().unwrap_or_default()
-        );
-    }
-    println!("Download successful.");
+/// Returns the path to the `include` directory of an official protoc [release], which contains the
+/// well-known types (e.g. `google/protobuf/timestamp.proto`).
+///
+/// The release is fetched into `out_dir` exactly as with [`protoc`] if it isn't already present.
+/// The `include/` tree is only extracted from the release archive the first time this function is
+/// called for a given install.
+///
+/// # Examples:
+///
+/// ```no_run
+/// # use std::env;
+/// # use std::path::Path;
+/// // From within build.rs...
+/// let out_dir = env::var("OUT_DIR").unwrap();
+/// let include_path = protoc_fetcher::include_path("21.2", Path::new(&out_dir));
+/// ```
+///
+/// [release]: https://github.com/protocolbuffers/protobuf/releases
+pub fn include_path(version: &str, out_dir: &Path) -> Result<PathBuf, Error> {
+    ProtocFetcher::new(version)
+        .install_dir(out_dir)
+        .fetch()?
+        .include_path()
+}
 
-    fs::create_dir_all(protoc_dir)?;
-    let cursor = Cursor::new(response.bytes()?);
-    zip_extract::extract(cursor, protoc_dir, false)?;
-    println!("Extracted archive.");
+/// Fetches only the well-known types of an official protoc [release] into `out_dir`, and returns
+/// the path to the release's `include` directory. Unlike [`include_path`], protoc itself isn't
+/// installed: only the `include/` tree is extracted from the release archive, for builds that
+/// already have a protoc but need the well-known protos of a pinned version. See
+/// [`ProtocFetcher::fetch_well_known_types`].
+///
+/// # Examples:
+///
+/// ```no_run
+/// # use std::env;
+/// # use std::path::Path;
+/// // From within build.rs...
+/// let out_dir = env::var("OUT_DIR").unwrap();
+/// let include_path = protoc_fetcher::fetch_well_known_types("21.2", Path::new(&out_dir));
+/// ```
+///
+/// [release]: https://github.com/protocolbuffers/protobuf/releases
+pub fn fetch_well_known_types(version: &str, out_dir: &Path) -> Result<PathBuf, Error> {
+    ProtocFetcher::new(version)
+        .install_dir(out_dir)
+        .fetch_well_known_types()
+}
 
-    let protoc_path = protoc_dir.join("bin/protoc");
-    if !protoc_path.exists() {
-        bail!("Extracted protoc archive, but could not find bin/protoc!");
-    }
+/// Downloads the release archives of every platform for the given protoc `version` into
+/// `out_dir`, without installing any of them, e.g. to pre-populate a mirror or Docker images.
+/// The archives are laid out as `<out_dir>/v<version>/<asset>`, next to a `manifest.json`
+/// listing them. See [`ProtocFetcher::fetch_all_platforms`].
+///
+/// # Examples:
+///
+/// ```no_run
+/// # use std::path::Path;
+/// let assets = protoc_fetcher::fetch_all_platforms("21.2", Path::new("mirror")).unwrap();
+/// ```
+pub fn fetch_all_platforms(version: &str, out_dir: &Path) -> Result<Vec<PlatformAsset>, Error> {
+    ProtocFetcher::new(version).fetch_all_platforms(out_dir)
+}
+
+/// Downloads the asset named `asset_name` of the release tagged `tag` in the GitHub repository
+/// `repo` into `out_dir` (unless already there), and returns the path to it, e.g. for release
+/// assets the fetcher doesn't know of. See [`ProtocFetcher::fetch_release_asset`].
+///
+/// # Examples:
+///
+/// ```no_run
+/// # use std::path::Path;
+/// let archive = protoc_fetcher::fetch_release_asset(
+///     "protocolbuffers/protobuf",
+///     "v31.1",
+///     "protoc-31.1-osx-universal_binary.zip",
+///     Path::new("downloads"),
+/// )
+/// .unwrap();
+/// ```
+pub fn fetch_release_asset(
+    repo: &str,
+    tag: &str,
+    asset_name: &str,
+    out_dir: &Path,
+) -> Result<PathBuf, Error> {
+    // A fetcher needs a version, but fetching a release asset never looks at it: the asset is
+    // named by the repo, tag and asset name alone, so the version is neither parsed nor pinned.
+    ProtocFetcher::new(tag).fetch_release_asset(repo, tag, asset_name, out_dir)
+}
+
+/// Fetches an official protoc [release] exactly as with [`protoc`], and returns the path to the
+/// binary together with the path to the release's `include` directory. Saves fetching twice when a
+/// build needs both, e.g. to pass the include directory to prost-build or tonic-build for imports
+/// of the well-known types.
+///
+/// # Examples:
+///
+/// ```no_run
+/// # use std::env;
+/// # use std::path::Path;
+/// // From within build.rs...
+/// let out_dir = env::var("OUT_DIR").unwrap();
+/// let (protoc_path, include_path) =
+///     protoc_fetcher::protoc_with_includes("21.2", Path::new(&out_dir)).unwrap();
+/// env::set_var("PROTOC", &protoc_path);
+/// tonic_build::configure()
+///     .compile(&["protos/my_service.proto"], &[Path::new("protos"), &include_path])
+///     .unwrap();
+/// ```
+///
+/// [release]: https://github.com/protocolbuffers/protobuf/releases
+pub fn protoc_with_includes(version: &str, out_dir: &Path) -> Result<(PathBuf, PathBuf), Error> {
+    let protoc = ProtocFetcher::new(version).install_dir(out_dir).fetch()?;
+    let include_path = protoc.include_path()?;
 
-    println!("protoc installed successfully: {:?}", &protoc_path);
-    Ok(())
+    Ok((protoc.path().to_path_buf(), include_path))
+}
+
+/// Downloads a prebuilt protoc plugin from its official releases and returns the path to the
+/// binary, e.g. to register it with [`Toolchain::plugin`].
+///
+/// `name` is one of [`KNOWN_PLUGINS`], such as "protoc-gen-go" or "protoc-gen-grpc-web", and
+/// `version` is the plugin's own version (e.g. "1.34.1"), not protoc's. Plugins are installed into
+/// a subdirectory of `out_dir` next to the protoc installs, and reused if already present.
+///
+/// If `sha256` is given, the download (the archive, for plugins released in one) must have that
+/// hex-encoded SHA-256 digest, and an existing install of any other download is replaced. As with
+/// [`ProtocFetcher::sha256`], the download differs per platform.
+///
+/// gRPC doesn't publish binaries of its C++-based plugins such as `grpc_cpp_plugin`, so those
+/// can't be fetched.
+///
+/// # Examples:
+///
+/// ```no_run
+/// # fn main() -> anyhow::Result<()> {
+/// # use std::env;
+/// # use std::path::Path;
+/// use protoc_fetcher::{ProtocFetcher, Toolchain};
+///
+/// // From within build.rs...
+/// let out_dir = env::var("OUT_DIR")?;
+/// let grpc_web =
+///     protoc_fetcher::fetch_plugin("protoc-gen-grpc-web", "1.5.0", None, Path::new(&out_dir))?;
+/// let toolchain =
+///     Toolchain::new(ProtocFetcher::new("21.2").fetch()?).plugin("grpc-web", grpc_web);
+/// # Ok(())
+/// # }
+/// ```
+pub fn fetch_plugin(
+    name: &str,
+    version: &str,
+    sha256: Option<&str>,
+    out_dir: &Path,
+) -> Result<PathBuf, Error> {
+    let fetcher = ProtocFetcher::new(version).install_dir(out_dir);
+    Ok(plugin::fetch(&fetcher, name, version, sha256)?)
 }
 
-fn protoc_release_archive_url(release_name: &str, version: &str) -> String {
-    let archive_url =
-        format!("https://github.com/protocolbuffers/protobuf/releases/download/v{version}/{release_name}.zip");
-    println!("Release URL: {archive_url}");
+/// Installs protoc from a release archive that is already at hand, e.g. vendored into the
+/// repository for air-gapped builds, and returns the path to the binary. Nothing is downloaded:
+/// `archive` (a path, or the bytes of the archive) must be the release of `version` for the host
+/// platform, as downloaded from the [release] page.
+///
+/// Otherwise this works as [`protoc`] does, including reusing an existing install in `out_dir`. See
+/// [`ProtocFetcher::archive`] and [`ProtocFetcher::offline`] for more control.
+///
+/// # Examples:
+///
+/// ```no_run
+/// # use std::env;
+/// # use std::path::Path;
+/// // From within build.rs...
+/// let out_dir = env::var("OUT_DIR").unwrap();
+/// let protoc_path = protoc_fetcher::install_from_archive(
+///     Path::new("vendor/protoc-21.2-linux-x86_64.zip"),
+///     "21.2",
+///     Path::new(&out_dir),
+/// );
+/// ```
+///
+/// [release]: https://github.com/protocolbuffers/protobuf/releases
+pub fn install_from_archive(
+    archive: impl Into<VendoredArchive>,
+    version: &str,
+    out_dir: &Path,
+) -> Result<PathBuf, Error> {
+    let protoc = ProtocFetcher::new(version)
+        .install_dir(out_dir)
+        .archive(archive)
+        .offline(true)
+        .fetch()?;
+
+    Ok(protoc.path().to_path_buf())
+}
 
-    archive_url
+/// Fetches protoc into `OUT_DIR` and runs [prost-build] with it to generate Rust code for
+/// `protos`, with `includes` as the include paths; saves setting the `PROTOC` env var by hand. Call
+/// this from `build.rs`. See [`Protoc::compile_with_prost`] to configure prost-build.
+///
+/// # Examples:
+///
+/// ```no_run
+/// // From within build.rs...
+/// protoc_fetcher::compile_with_prost("21.2", &["protos/my_messages.proto"], &["protos"]).unwrap();
+/// ```
+///
+/// [prost-build]: https://crates.io/crates/prost-build
+#[cfg(feature = "prost")]
+pub fn compile_with_prost(
+    version: &str,
+    protos: &[impl AsRef<Path>],
+    includes: &[impl AsRef<Path>],
+) -> Result<(), Error> {
+    ProtocFetcher::new(version).fetch()?.compile_with_prost(
+        prost_build::Config::new(),
+        protos,
+        includes,
+    )
 }
 
-fn get_protoc_release_name(version: &str) -> String {
-    let mut platform = env::consts::OS;
-    let mut arch = env::consts::ARCH;
-    println!("Detected: {}, {}", platform, arch);
+/// Fetches protoc into `OUT_DIR` and runs [tonic-build] with it to generate Rust code (including
+/// gRPC clients and servers) for `protos`, with `includes` as the include paths; saves setting the
+/// `PROTOC` env var by hand. Call this from `build.rs`. See [`Protoc::compile_with_tonic`] to
+/// configure tonic-build.
+///
+/// # Examples:
+///
+/// ```no_run
+/// // From within build.rs...
+/// protoc_fetcher::compile_with_tonic("21.2", &["protos/my_service.proto"], &["protos"]).unwrap();
+/// ```
+///
+/// [tonic-build]: https://crates.io/crates/tonic-build
+#[cfg(feature = "tonic")]
+pub fn compile_with_tonic(
+    version: &str,
+    protos: &[impl AsRef<Path>],
+    includes: &[impl AsRef<Path>],
+) -> Result<(), Error> {
+    ProtocFetcher::new(version).fetch()?.compile_with_tonic(
+        tonic_build::configure(),
+        protos,
+        includes,
+    )
+}
 
-    // Adjust values to match the protoc release names. Examples:
-    //   - linux 64-bit: protoc-21.2-linux-x86_64.zip
-    //   - macos ARM: protoc-21.2-osx-aarch_64.zip
-    if platform == "macos" {
-        platform = "osx"; // protoc is stuck in the past XD
-    }
-    if arch == "aarch64" {
-        arch = "aarch_64";
-    }
+/// Fetches protoc `version` and writes a self-contained `FileDescriptorSet` for `protos` (with
+/// the descriptors of all their imports, as `--include_imports` does) to `out_path`, for
+/// pipelines that only need descriptors, e.g. for reflection or validation. Returns `out_path`.
+///
+/// protoc is installed into `OUT_DIR` within build scripts and the user cache otherwise, and
+/// reused from there. See [`Protoc::descriptor_set`] for more control, or to get the bytes
+/// without writing a file.
+///
+/// # Examples:
+///
+/// ```no_run
+/// # fn main() -> anyhow::Result<()> {
+/// let descriptors = protoc_fetcher::generate_descriptor_set(
+///     "21.2",
+///     &["protos/my_service.proto"],
+///     &["protos"],
+///     "target/my_service.pb",
+/// )?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn generate_descriptor_set(
+    version: &str,
+    protos: &[impl AsRef<Path>],
+    includes: &[impl AsRef<Path>],
+    out_path: impl AsRef<Path>,
+) -> Result<PathBuf, Error> {
+    let out_path = out_path.as_ref();
+    let options = DescriptorOptions {
+        include_imports: true,
+        ..DescriptorOptions::default()
+    };
+    ProtocFetcher::new(version)
+        .user_cache(env::var_os("OUT_DIR").is_none())
+        .fetch()?
+        .write_descriptor_set(protos, includes, options, out_path)?;
 
-    format!("protoc-{version}-{platform}-{arch}")
+    Ok(out_path.to_path_buf())
 }
 
-fn get_protoc_version(protoc_path: &Path) -> anyhow::Result<String> {
-    let version = String::from_utf8(Command::new(&protoc_path).arg("--version").output()?.stdout)?;
-    Ok(version)
+/// Like [`protoc`], but for async code running on a tokio runtime (e.g. build orchestration
+/// tools), where [`protoc`] would panic. See [`ProtocFetcher::fetch_async`].
+///
+/// # Examples:
+///
+/// ```no_run
+/// # async fn example() -> anyhow::Result<()> {
+/// # use std::path::Path;
+/// let protoc_path = protoc_fetcher::protoc_async("21.2", Path::new("target/protoc")).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(feature = "async")]
+pub async fn protoc_async(version: &str, out_dir: &Path) -> Result<PathBuf, Error> {
+    let protoc = ProtocFetcher::new(version)
+        .install_dir(out_dir)
+        .fetch_async()
+        .await?;
+
+    Ok(protoc.path().to_path_buf())
 }
@@ -0,0 +1,204 @@
+//! Cross-process locking of install directories.
+
+use crate::deadline::Cancellation;
+use crate::fetcher::LockWait;
+use crate::logging;
+use std::fs::{self, File, TryLockError};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// An exclusive, advisory lock on an install directory, released when dropped.
+pub(crate) struct InstallLock {
+    _file: File,
+}
+
+/// The result of trying to lock an install directory.
+pub(crate) enum Acquired {
+    Locked(InstallLock),
+    /// Another process holds the lock and the [`LockWait`] policy says not to wait for it (any
+    /// longer).
+    Contended,
+}
+
+impl InstallLock {
+    /// Locks the install directory `protoc_dir`, waiting for other processes according to
+    /// `lock_wait`. The lock file is created next to the directory, so that the directory itself
+    /// can be deleted and re-created while locked.
+    ///
+    /// The wait is part of the fetch that `cancellation` belongs to, so it fails as soon as the
+    /// fetch runs out of time or is cancelled, however long `lock_wait` would wait.
+    pub fn acquire(
+        protoc_dir: &Path,
+        lock_wait: &LockWait,
+        cancellation: &Cancellation,
+    ) -> anyhow::Result<Acquired> {
+        let mut lock_path = protoc_dir.as_os_str().to_owned();
+        lock_path.push(".lock");
+        if let Some(parent) = protoc_dir.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&lock_path)?;
+
+        let timeout = match lock_wait {
+            LockWait::Wait(timeout) => *timeout,
+            LockWait::Fail | LockWait::Fallback(_) => Duration::ZERO,
+        };
+        let start = Instant::now();
+        let mut reported = false;
+        loop {
+            match file.try_lock() {
+                Ok(()) => return Ok(Acquired::Locked(InstallLock { _file: file })),
+                Err(TryLockError::WouldBlock) => {}
+                Err(TryLockError::Error(err)) => return Err(err.into()),
+            }
+            let elapsed = start.elapsed();
+            if elapsed >= timeout {
+                return Ok(Acquired::Contended);
+            }
+            cancellation.check()?;
+            if !reported {
+                logging::info!("Waiting for another process to finish installing protoc...");
+                reported = true;
+            }
+            thread::sleep(cancellation.clamp(POLL_INTERVAL.min(timeout - elapsed)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use crate::install;
+    use crate::test_support::Sandbox;
+
+    /// Locks `protoc_dir` as another process would, without waiting.
+    fn hold(protoc_dir: &Path) -> InstallLock {
+        match InstallLock::acquire(protoc_dir, &LockWait::Fail, &Cancellation::default()) {
+            Ok(Acquired::Locked(lock)) => lock,
+            _ => panic!("{protoc_dir:?} is locked already"),
+        }
+    }
+
+    fn acquire(protoc_dir: &Path, lock_wait: LockWait) -> Acquired {
+        InstallLock::acquire(protoc_dir, &lock_wait, &Cancellation::default()).unwrap()
+    }
+
+    #[test]
+    fn fail_is_contended_at_once() {
+        let sandbox = Sandbox::new().unwrap();
+        let protoc_dir = sandbox.path().join("protoc");
+        let _held = hold(&protoc_dir);
+
+        let start = Instant::now();
+        assert!(matches!(
+            acquire(&protoc_dir, LockWait::Fail),
+            Acquired::Contended
+        ));
+        assert!(start.elapsed() < POLL_INTERVAL);
+    }
+
+    #[test]
+    fn wait_gives_up_after_the_timeout() {
+        let sandbox = Sandbox::new().unwrap();
+        let protoc_dir = sandbox.path().join("protoc");
+        let _held = hold(&protoc_dir);
+
+        let timeout = Duration::from_millis(300);
+        let start = Instant::now();
+        assert!(matches!(
+            acquire(&protoc_dir, LockWait::Wait(timeout)),
+            Acquired::Contended
+        ));
+        let waited = start.elapsed();
+        assert!(waited >= timeout, "{waited:?}");
+        assert!(waited < timeout + Duration::from_secs(5), "{waited:?}");
+    }
+
+    #[test]
+    fn wait_locks_once_released() {
+        let sandbox = Sandbox::new().unwrap();
+        let protoc_dir = sandbox.path().join("protoc");
+        let held = hold(&protoc_dir);
+        let releaser = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            drop(held);
+        });
+
+        let lock_wait = LockWait::Wait(Duration::from_secs(30));
+        assert!(matches!(
+            acquire(&protoc_dir, lock_wait),
+            Acquired::Locked(_)
+        ));
+        releaser.join().unwrap();
+    }
+
+    #[test]
+    fn fallback_is_contended_at_once() {
+        let sandbox = Sandbox::new().unwrap();
+        let protoc_dir = sandbox.path().join("protoc");
+        let _held = hold(&protoc_dir);
+        let lock_wait = LockWait::Fallback(sandbox.path().join("fallback"));
+        assert!(matches!(
+            acquire(&protoc_dir, lock_wait),
+            Acquired::Contended
+        ));
+    }
+
+    /// Locks the install of `fetcher`'s release in `sandbox`, as another process would.
+    fn hold_install(sandbox: &Sandbox) -> InstallLock {
+        let fetcher = sandbox.fetcher("21.2");
+        hold(&install::protoc_install_dir(&fetcher, sandbox.path()).unwrap())
+    }
+
+    #[test]
+    fn fetches_fail_with_locked() {
+        let sandbox = Sandbox::new().unwrap();
+        let _held = hold_install(&sandbox);
+        let timeout = Duration::from_millis(200);
+        for (lock_wait, waited) in [
+            (LockWait::Fail, Duration::ZERO),
+            (LockWait::Wait(timeout), timeout),
+        ] {
+            let err = sandbox
+                .fetcher("21.2")
+                .lock_wait(lock_wait)
+                .offline(true)
+                .fetch()
+                .unwrap_err();
+            let Error::Locked { waited: actual, .. } = err else {
+                panic!("expected Error::Locked, got {err:?}");
+            };
+            assert_eq!(actual, waited);
+        }
+    }
+
+    // The fake releases' protoc is a shell script, so only Unix-like systems can install them.
+    #[cfg(unix)]
+    #[test]
+    fn fetches_install_into_the_fallback_dir() {
+        use crate::test_support::{FakeRelease, MockHttp};
+
+        let sandbox = Sandbox::new().unwrap();
+        let fallback = Sandbox::new().unwrap();
+        let _held = hold_install(&sandbox);
+        let http = MockHttp::new()
+            .fake_release(&FakeRelease::new("21.2"))
+            .unwrap();
+        let protoc = sandbox
+            .fetcher("21.2")
+            .lock_wait(LockWait::Fallback(fallback.path().to_path_buf()))
+            .http_client(http)
+            .fetch()
+            .unwrap();
+        assert!(protoc.path().starts_with(fallback.path()));
+    }
+}
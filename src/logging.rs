@@ -0,0 +1,85 @@
+//! How much a fetch logs, as set with [`ProtocFetcher::verbosity`].
+//!
+//! The crate logs through the `log` facade, with the macros here in place of `log`'s own, so that
+//! what reaches the logger follows the verbosity of the fetch being made. The verbosity is
+//! [scoped](scope) to the thread making the fetch for its duration.
+
+use crate::fetcher::ProtocFetcher;
+use std::cell::Cell;
+
+/// How much a fetch logs, and prints as `cargo:warning` directives; see
+/// [`ProtocFetcher::verbosity`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Verbosity {
+    /// Nothing at all; failures are only reported through the errors returned.
+    Silent,
+    /// Warnings, and what is being installed from where (at the info level), with the details at
+    /// the debug level.
+    #[default]
+    Normal,
+    /// Warnings and what is being done, as for `Normal`, plus the details at the info level: the
+    /// detected platform, the URLs requested with the HTTP status and timing of each response,
+    /// and why an existing install or cached API response was (or wasn't) reused.
+    Verbose,
+}
+
+thread_local! {
+    static CURRENT: Cell<Verbosity> = const { Cell::new(Verbosity::Normal) };
+}
+
+/// Restores the previous verbosity of the thread when dropped; see [`scope`].
+pub(crate) struct Scope {
+    previous: Verbosity,
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.set(self.previous));
+    }
+}
+
+/// Sets the verbosity of the current thread to that of `fetcher` until the returned guard is
+/// dropped.
+pub(crate) fn scope(fetcher: &ProtocFetcher) -> Scope {
+    Scope {
+        previous: CURRENT.with(|current| current.replace(fetcher.verbosity)),
+    }
+}
+
+/// Returns the verbosity of the fetch being made on the current thread.
+pub(crate) fn current() -> Verbosity {
+    CURRENT.with(Cell::get)
+}
+
+/// Logs a warning, unless the fetch is [silent](Verbosity::Silent).
+macro_rules! log_warn {
+    ($($arg:tt)+) => {
+        if $crate::logging::current() != $crate::logging::Verbosity::Silent {
+            log::warn!($($arg)+)
+        }
+    };
+}
+
+/// Logs what is being done at the info level, unless the fetch is [silent](Verbosity::Silent).
+macro_rules! log_info {
+    ($($arg:tt)+) => {
+        if $crate::logging::current() != $crate::logging::Verbosity::Silent {
+            log::info!($($arg)+)
+        }
+    };
+}
+
+/// Logs a detail: at the info level if the fetch is [verbose](Verbosity::Verbose), at the debug
+/// level normally, and not at all if it is [silent](Verbosity::Silent).
+macro_rules! log_debug {
+    ($($arg:tt)+) => {
+        match $crate::logging::current() {
+            $crate::logging::Verbosity::Silent => {}
+            $crate::logging::Verbosity::Normal => log::debug!($($arg)+),
+            $crate::logging::Verbosity::Verbose => log::info!($($arg)+),
+        }
+    };
+}
+
+// Named apart from what they are used as, because `warn` would clash with the `warn` attribute.
+pub(crate) use {log_debug as debug, log_info as info, log_warn as warn};
@@ -0,0 +1,88 @@
+//! Paths beyond the Windows limit of 260 characters (`MAX_PATH`).
+//!
+//! Deep `OUT_DIR`s, as in workspaces with long crate names, easily exceed the limit once the
+//! release's include tree is extracted below them, and most Windows APIs fail on such paths unless
+//! they are in the extended-length form, e.g. `\\?\C:\...`. Extraction therefore writes through
+//! extended-length paths, while the paths handed out (e.g. to spawn protoc with) are turned back
+//! into the usual form where they fit, since not every program accepts extended-length ones.
+
+use std::path::{Component, Path, PathBuf, Prefix};
+
+/// The longest path most Windows APIs accept, unless it is in the extended-length form.
+const MAX_PATH: usize = 260;
+
+const VERBATIM_PREFIX: &str = r"\\?\";
+const VERBATIM_UNC_PREFIX: &str = r"\\?\UNC\";
+
+/// Returns `path` in the extended-length form on Windows, i.e. absolute and prefixed with `\\?\`
+/// (or `\\?\UNC\` for a share, such as `\\server\share`). Elsewhere, and for paths that can't be
+/// converted (e.g. device paths), returns `path` as is.
+///
+/// Extended-length paths are taken literally, so anything joined onto the result must be joined
+/// component by component, as [`join`] does; a `/` would not be taken as a separator.
+pub(crate) fn extended(path: &Path) -> PathBuf {
+    if !cfg!(windows) {
+        return path.to_path_buf();
+    }
+    // This also normalizes separators and resolves `.` and `..`, which extended-length paths
+    // don't allow.
+    let Ok(absolute) = std::path::absolute(path) else {
+        return path.to_path_buf();
+    };
+    let Some(absolute_str) = absolute.to_str() else {
+        return absolute;
+    };
+    let Some(Component::Prefix(prefix)) = absolute.components().next() else {
+        return absolute;
+    };
+    match prefix.kind() {
+        kind if kind.is_verbatim() => absolute,
+        Prefix::Disk(_) => PathBuf::from(format!("{VERBATIM_PREFIX}{absolute_str}")),
+        Prefix::UNC(..) => PathBuf::from(format!(
+            "{VERBATIM_UNC_PREFIX}{}",
+            absolute_str.trim_start_matches('\\')
+        )),
+        _ => absolute,
+    }
+}
+
+/// Returns `path` in the usual form if it is an extended-length path that fits within
+/// `MAX_PATH` without its prefix, and as is otherwise.
+pub(crate) fn simplified(path: &Path) -> PathBuf {
+    let Some(path_str) = path.to_str() else {
+        return path.to_path_buf();
+    };
+    let simple = if let Some(rest) = path_str.strip_prefix(VERBATIM_UNC_PREFIX) {
+        format!(r"\\{rest}")
+    } else if let Some(rest) = path_str
+        .strip_prefix(VERBATIM_PREFIX)
+        .filter(|rest| rest.get(1..3) == Some(r":\"))
+    {
+        rest.to_string()
+    } else {
+        return path.to_path_buf();
+    };
+    if simple.len() < MAX_PATH {
+        PathBuf::from(simple)
+    } else {
+        path.to_path_buf()
+    }
+}
+
+/// Returns `path` as it is best handed to other programs: on Windows, absolute, with `\`
+/// separators throughout, and not in the extended-length form where it fits without. Elsewhere,
+/// returns `path` as is.
+pub(crate) fn normalized(path: &Path) -> PathBuf {
+    if !cfg!(windows) {
+        return path.to_path_buf();
+    }
+    simplified(&std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()))
+}
+
+/// Joins the '/'-separated `name` onto `dir` component by component, so that the result uses the
+/// platform's separators throughout and stays valid if `dir` is an [`extended`] path.
+pub(crate) fn join(dir: &Path, name: &str) -> PathBuf {
+    let mut path = dir.to_path_buf();
+    path.extend(name.split('/').filter(|component| !component.is_empty()));
+    path
+}
@@ -0,0 +1,217 @@
+//! `protoc-fetcher`: fetches protoc releases from the command line, e.g. in Makefiles,
+//! Dockerfiles and dev setup scripts. Requires the `cli` feature:
+//!
+//! ```sh
+//! cargo install protoc-fetcher --features cli
+//! protoc-fetcher fetch 31.1
+//! ```
+
+use anyhow::{bail, Context};
+use protoc_fetcher::{validate_cache, InstallReport, ProtocFetcher, Verbosity};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const USAGE: &str = "\
+Usage: protoc-fetcher <command> [options]
+
+Commands:
+  fetch <version>    Fetch protoc (unless already installed) and print the path to it
+  path <version>     Print the path to an installed protoc, without fetching it
+  list               List the installed protoc releases
+  clean [<version>]  Delete the installed releases of <version>, or all of them
+  prune <days>       Delete the installed releases that haven't been used for <days> days
+
+Options:
+  --dir <dir>        The install directory (default: $PROTOC_FETCHER_DIR, or else the user's
+                     cache directory)
+  --include          With fetch: print the path to the include directory instead
+  -v, --verbose      Log what is being done to stderr, in detail
+  -h, --help         Print this help
+";
+
+/// The parsed command line.
+struct Args {
+    command: String,
+    version: Option<String>,
+    dir: Option<PathBuf>,
+    include: bool,
+    verbose: bool,
+}
+
+fn main() {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            print!("{USAGE}");
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {err:#}\n\n{USAGE}");
+            std::process::exit(2);
+        }
+    };
+    if args.verbose {
+        log::set_logger(&StderrLogger).expect("no other logger is set");
+        log::set_max_level(log::LevelFilter::Info);
+    }
+    if let Err(err) = run(&args) {
+        eprintln!("error: {err:#}");
+        std::process::exit(1);
+    }
+}
+
+/// Parses the arguments, returning `None` if help was asked for.
+fn parse_args(args: impl Iterator<Item = String>) -> anyhow::Result<Option<Args>> {
+    let mut positional = Vec::new();
+    let mut dir = None;
+    let mut include = false;
+    let mut verbose = false;
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--dir" => dir = Some(PathBuf::from(args.next().context("--dir needs a value")?)),
+            "--include" => include = true,
+            "-v" | "--verbose" => verbose = true,
+            _ if arg.starts_with('-') => bail!("Unknown option {arg}"),
+            _ => positional.push(arg),
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    let Some(command) = positional.next() else {
+        return Ok(None);
+    };
+    let version = positional.next();
+    if let Some(extra) = positional.next() {
+        bail!("Unexpected argument {extra}");
+    }
+    let needs_version = matches!(command.as_str(), "fetch" | "path");
+    match command.as_str() {
+        "fetch" | "path" | "list" | "clean" | "prune" => {}
+        _ => bail!("Unknown command {command}"),
+    }
+    if needs_version && version.is_none() {
+        bail!("{command} needs a version");
+    }
+    let days = version.as_deref().map(str::parse::<u64>);
+    if command == "prune" && !matches!(days, Some(Ok(_))) {
+        bail!("prune needs a number of days");
+    }
+    if command == "list" && version.is_some() {
+        bail!("list takes no version");
+    }
+    Ok(Some(Args {
+        command,
+        version,
+        dir,
+        include,
+        verbose,
+    }))
+}
+
+fn run(args: &Args) -> anyhow::Result<()> {
+    let dir = match &args.dir {
+        Some(dir) => dir.clone(),
+        None => match env::var_os("PROTOC_FETCHER_DIR").filter(|dir| !dir.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => protoc_fetcher::user_cache_dir()?,
+        },
+    };
+    let version = args.version.as_deref();
+    match args.command.as_str() {
+        "fetch" => {
+            let verbosity = if args.verbose {
+                Verbosity::Verbose
+            } else {
+                Verbosity::Normal
+            };
+            let protoc = ProtocFetcher::new(version.unwrap_or_default())
+                .install_dir(&dir)
+                .verbosity(verbosity)
+                .fetch()?;
+            if args.include {
+                println!("{}", protoc.include_path()?.display());
+            } else {
+                println!("{}", protoc.path().display());
+            }
+        }
+        "path" => {
+            let version = version.unwrap_or_default();
+            let install = installs(&dir, Some(version))?
+                .into_iter()
+                .find(|install| install.problems.is_empty())
+                .with_context(|| format!("protoc {version} is not installed in {dir:?}"))?;
+            println!("{}", install.protoc_path.display());
+        }
+        "list" => {
+            for install in installs(&dir, None)? {
+                let name = install
+                    .path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy();
+                if install.problems.is_empty() {
+                    println!("{name}");
+                } else {
+                    let problems: Vec<_> = install.problems.iter().map(|p| p.to_string()).collect();
+                    println!("{name} (broken: {})", problems.join("; "));
+                }
+            }
+        }
+        "clean" => {
+            for install in installs(&dir, version)? {
+                fs::remove_dir_all(&install.path)
+                    .with_context(|| format!("Failed to delete {:?}", install.path))?;
+                println!("Deleted {}", install.path.display());
+            }
+        }
+        "prune" => {
+            let days: u64 = version.unwrap_or_default().parse()?;
+            let age = Duration::from_secs(days * 24 * 60 * 60);
+            for path in protoc_fetcher::prune_older_than(&dir, age)? {
+                println!("Deleted {}", path.display());
+            }
+        }
+        _ => unreachable!("commands are checked when parsing"),
+    }
+    Ok(())
+}
+
+/// Returns the protoc installs in `dir`, only those of `version` if given.
+fn installs(dir: &Path, version: Option<&str>) -> anyhow::Result<Vec<InstallReport>> {
+    let mut installs = validate_cache(dir)?.installs;
+    if let Some(version) = version {
+        // Installs are named after the release assets, which spell release candidates such as
+        // "32.0-rc1" as "32.0-rc-1".
+        let asset_version = match version.split_once("-rc") {
+            Some((release, rc)) if !rc.starts_with('-') => format!("{release}-rc-{rc}"),
+            _ => version.to_string(),
+        };
+        let prefix = format!("protoc-{asset_version}-");
+        installs.retain(|install| {
+            install
+                .path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with(&prefix))
+        });
+    }
+    Ok(installs)
+}
+
+/// Logs to stderr, so that stdout only has the output of the command.
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        eprintln!("{}", record.args());
+    }
+
+    fn flush(&self) {}
+}
@@ -0,0 +1,99 @@
+//! A small manifest file stored next to each install, recording where the install came from and
+//! what has been extracted from the release archive so far.
+
+use std::fs;
+use std::path::Path;
+
+const MANIFEST_FILE_NAME: &str = "manifest";
+
+/// The contents of an install's manifest.
+///
+/// The manifest is stored as plain `key=value` lines so that it can be inspected (and, if need
+/// be, edited) by hand. Unknown keys are ignored.
+#[derive(Debug, Default)]
+pub(crate) struct Manifest {
+    /// Whether the `include/` tree has been extracted from the release archive.
+    pub include_extracted: bool,
+    /// The hex-encoded SHA-256 digest of the release archive the install was extracted from.
+    pub archive_sha256: Option<String>,
+    /// The URL the release archive was downloaded from, unless it was vendored.
+    pub archive_url: Option<String>,
+    /// The hex-encoded SHA-256 digest of `bin/protoc` as extracted.
+    pub protoc_sha256: Option<String>,
+    /// Whether the protos of the protobuf source release have been extracted into `source/`.
+    pub source_extracted: bool,
+    /// Glob patterns of additional entries that have been extracted from the release archive.
+    pub extra_entries: Vec<String>,
+}
+
+impl Manifest {
+    /// Reads the manifest from `install_dir`.
+    ///
+    /// Installs created before the manifest existed extracted the whole archive, so when there is
+    /// no manifest the state is inferred from the install directory itself.
+    pub fn read(install_dir: &Path) -> anyhow::Result<Self> {
+        let path = install_dir.join(MANIFEST_FILE_NAME);
+        if !path.exists() {
+            return Ok(Manifest {
+                include_extracted: install_dir.join("include").is_dir(),
+                ..Manifest::default()
+            });
+        }
+
+        let mut manifest = Manifest::default();
+        for line in fs::read_to_string(path)?.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim();
+            match key.trim() {
+                "include" => manifest.include_extracted = value == "present",
+                "source" => manifest.source_extracted = value == "present",
+                "archive_sha256" => manifest.archive_sha256 = Some(value.to_string()),
+                "archive_url" => manifest.archive_url = Some(value.to_string()),
+                "protoc_sha256" => manifest.protoc_sha256 = Some(value.to_string()),
+                "extra_entries" => {
+                    manifest.extra_entries = value
+                        .split(',')
+                        .filter(|pattern| !pattern.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                }
+                _ => {}
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// Writes the manifest into `install_dir`.
+    pub fn write(&self, install_dir: &Path) -> anyhow::Result<()> {
+        fs::write(install_dir.join(MANIFEST_FILE_NAME), self.contents())?;
+        Ok(())
+    }
+
+    /// Returns the manifest as it is stored.
+    pub fn contents(&self) -> String {
+        let include = if self.include_extracted {
+            "present"
+        } else {
+            "absent"
+        };
+        let mut contents = format!("include={include}\n");
+        if self.source_extracted {
+            contents.push_str("source=present\n");
+        }
+        if let Some(archive_sha256) = &self.archive_sha256 {
+            contents.push_str(&format!("archive_sha256={archive_sha256}\n"));
+        }
+        if let Some(archive_url) = &self.archive_url {
+            contents.push_str(&format!("archive_url={archive_url}\n"));
+        }
+        if let Some(protoc_sha256) = &self.protoc_sha256 {
+            contents.push_str(&format!("protoc_sha256={protoc_sha256}\n"));
+        }
+        if !self.extra_entries.is_empty() {
+            contents.push_str(&format!("extra_entries={}\n", self.extra_entries.join(",")));
+        }
+        contents
+    }
+}
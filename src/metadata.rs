@@ -0,0 +1,81 @@
+//! Metadata about a fetched protoc, for recording its provenance.
+
+use crate::error::Error;
+use crate::install;
+use crate::manifest::Manifest;
+use crate::protoc::Protoc;
+use std::path::PathBuf;
+
+/// Where a fetched protoc came from and where it is installed, as returned by
+/// [`Protoc::metadata`]; e.g. to record the provenance of the protoc used by a build.
+///
+/// With the `serde` feature, this implements `Serialize` and `Deserialize`, so that it can be
+/// written into a build manifest:
+///
+/// ```no_run
+/// # #[cfg(feature = "serde")]
+/// # fn main() -> anyhow::Result<()> {
+/// use protoc_fetcher::ProtocFetcher;
+///
+/// let metadata = ProtocFetcher::new("21.2").fetch()?.metadata()?;
+/// std::fs::write("protoc-provenance.json", serde_json::to_string_pretty(&metadata)?)?;
+/// # Ok(())
+/// # }
+/// # #[cfg(not(feature = "serde"))]
+/// # fn main() {}
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProtocInstall {
+    /// The version of the release, e.g. "21.2".
+    pub version: String,
+    /// The platform of the release, as named in the release archives, e.g. `linux-x86_64`.
+    pub platform: String,
+    /// The URL the release archive was downloaded from, or `None` if it was installed from a
+    /// vendored [`archive`](crate::ProtocFetcher::archive) or is a system protoc.
+    pub download_url: Option<String>,
+    /// The hex-encoded SHA-256 digest of the release archive, or `None` for a system protoc.
+    pub archive_sha256: Option<String>,
+    /// The directory the release is installed in; see [`Protoc::install_dir`].
+    pub install_path: PathBuf,
+    /// The path to the protoc binary.
+    pub protoc_path: PathBuf,
+    /// The path to the release's `include` directory, or `None` if it hasn't been extracted (see
+    /// [`Protoc::include_path`]).
+    pub include_path: Option<PathBuf>,
+    /// Whether the fetch reused an existing install rather than installing the release. Always
+    /// true for a system protoc.
+    pub cache_hit: bool,
+}
+
+impl Protoc {
+    /// Returns metadata about this protoc: its version and platform, where it was downloaded
+    /// from, the digest of the release archive, where it is installed, and whether the fetch
+    /// reused an existing install.
+    pub fn metadata(&self) -> Result<ProtocInstall, Error> {
+        let platform = install::release_platform(&self.fetcher)?;
+        let (download_url, archive_sha256, include_path) = if self.system {
+            let include_path = self.protoc_dir.join("include");
+            let has_include = include_path
+                .join("google/protobuf/descriptor.proto")
+                .is_file();
+            (None, None, has_include.then_some(include_path))
+        } else {
+            let manifest = Manifest::read(&self.protoc_dir)?;
+            let include_path = manifest
+                .include_extracted
+                .then(|| self.protoc_dir.join("include"));
+            (manifest.archive_url, manifest.archive_sha256, include_path)
+        };
+        Ok(ProtocInstall {
+            version: self.version().to_string(),
+            platform,
+            download_url,
+            archive_sha256,
+            install_path: self.protoc_dir.clone(),
+            protoc_path: self.path().to_path_buf(),
+            include_path,
+            cache_hit: self.cache_hit,
+        })
+    }
+}
@@ -0,0 +1,84 @@
+//! Reporting what a fetch would download and install, without doing it.
+
+use crate::error::Error;
+use crate::fetcher::ProtocFetcher;
+use crate::install::{self, COMPLETE_MARKER};
+use crate::logging;
+use crate::resolve;
+use anyhow::anyhow;
+use std::path::PathBuf;
+
+/// What a fetch would download and where it would install it, as returned by
+/// [`ProtocFetcher::plan`]; e.g. for CI to log or pre-approve downloads, or to generate a mirror
+/// script from.
+///
+/// With the `serde` feature, this implements `Serialize` and `Deserialize`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FetchPlan {
+    /// The version of the release, e.g. "21.2".
+    pub version: String,
+    /// The name of the release, e.g. `protoc-21.2-linux-x86_64`.
+    pub release_name: String,
+    /// The file name of the release asset, e.g. `protoc-21.2-linux-x86_64.zip`.
+    pub asset_name: String,
+    /// The URLs the asset would be downloaded from, in the order they would be tried (see
+    /// [`ProtocFetcher::mirrors`]). Empty if it would be installed from a vendored
+    /// [`archive`](ProtocFetcher::archive).
+    pub urls: Vec<String>,
+    /// The hex-encoded SHA-256 digest the asset must have, if one is known (see
+    /// [`ProtocFetcher::sha256`]).
+    pub expected_sha256: Option<String>,
+    /// The directory the release would be installed in.
+    pub install_path: PathBuf,
+    /// Whether the release is already installed there, in which case the fetch would most likely
+    /// reuse the install rather than download anything.
+    pub installed: bool,
+}
+
+impl ProtocFetcher {
+    /// Returns what [`fetch`](Self::fetch) would download and where it would install it, without
+    /// any network I/O. The env vars documented on [`fetch`](Self::fetch) are taken into account.
+    ///
+    /// As resolving "latest" or a partial version needs the GitHub API, this fails for those;
+    /// plan with a concrete version such as "31.1".
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use protoc_fetcher::ProtocFetcher;
+    ///
+    /// let plan = ProtocFetcher::new("31.1").install_dir("target/protoc").plan()?;
+    /// for url in &plan.urls {
+    ///     println!("would download {url} into {:?}", plan.install_path);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn plan(&self) -> Result<FetchPlan, Error> {
+        let _verbosity = logging::scope(self);
+        let fetcher = self.with_env_overrides()?;
+        if resolve::needs_resolution(&fetcher.version) {
+            return Err(anyhow!(
+                "Can't plan fetching protoc {:?} without network access to resolve it; plan with \
+                 a concrete version such as \"31.1\"",
+                fetcher.version
+            )
+            .into());
+        }
+        let release_name = install::get_protoc_release_name(&fetcher)?;
+        let urls = match fetcher.archive {
+            Some(_) => Vec::new(),
+            None => install::protoc_release_archive_urls(&fetcher, &release_name)?,
+        };
+        let install_path = install::protoc_install_dir(&fetcher, &fetcher.resolve_install_dir()?)?;
+        Ok(FetchPlan {
+            version: resolve::tag_version(&fetcher.version),
+            asset_name: install::release_asset_name(&fetcher, &release_name),
+            release_name,
+            urls,
+            expected_sha256: install::expected_sha256(&fetcher)?,
+            installed: install_path.join(COMPLETE_MARKER).exists(),
+            install_path,
+        })
+    }
+}
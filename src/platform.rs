@@ -0,0 +1,248 @@
+//! Working out which platform's release to fetch.
+
+use crate::error::Error;
+use crate::logging::{self, Verbosity};
+use anyhow::{anyhow, bail};
+use std::env;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// A platform that official protoc releases are published for. Its [`Display`](fmt::Display)
+/// form is the platform part of the release names, e.g. `linux-x86_64` as in
+/// `protoc-21.2-linux-x86_64.zip`, and it parses from the same.
+///
+/// Pass one to [`ProtocFetcher::platform`](crate::ProtocFetcher::platform) to fetch the release
+/// for another machine than the host, e.g. to prepare a Docker build context:
+///
+/// ```no_run
+/// # fn main() -> anyhow::Result<()> {
+/// use protoc_fetcher::{Platform, ProtocFetcher};
+///
+/// let protoc = ProtocFetcher::new("31.1")
+///     .platform(Platform::LinuxAarch64)
+///     .install_dir("docker/context")
+///     .fetch()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Platform {
+    LinuxX86_64,
+    LinuxAarch64,
+    LinuxX86_32,
+    LinuxPpcle64,
+    LinuxS390x,
+    OsxX86_64,
+    OsxAarch64,
+    /// The macOS release that runs natively on both x86_64 and ARM.
+    OsxUniversal,
+    Win64,
+    Win32,
+}
+
+impl Platform {
+    /// Every platform, in the order of the release assets.
+    pub const ALL: &'static [Platform] = &[
+        Platform::LinuxAarch64,
+        Platform::LinuxPpcle64,
+        Platform::LinuxS390x,
+        Platform::LinuxX86_32,
+        Platform::LinuxX86_64,
+        Platform::OsxAarch64,
+        Platform::OsxUniversal,
+        Platform::OsxX86_64,
+        Platform::Win32,
+        Platform::Win64,
+    ];
+
+    /// Returns the platform protoc runs on during the build, as used by default (see
+    /// [`ProtocFetcher::platform`](crate::ProtocFetcher::platform)): the `HOST` of a build
+    /// script, and otherwise the platform this crate was compiled for. Hosts without releases of
+    /// their own get the platform whose releases run there under emulation, if any.
+    pub fn host() -> Result<Platform, Error> {
+        Ok(host_platform(true)?)
+    }
+
+    /// Returns the platform part of the release names, e.g. `linux-x86_64`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Platform::LinuxX86_64 => "linux-x86_64",
+            Platform::LinuxAarch64 => "linux-aarch_64",
+            Platform::LinuxX86_32 => "linux-x86_32",
+            Platform::LinuxPpcle64 => "linux-ppcle_64",
+            Platform::LinuxS390x => "linux-s390_64",
+            // protoc is stuck in the past XD
+            Platform::OsxX86_64 => "osx-x86_64",
+            Platform::OsxAarch64 => "osx-aarch_64",
+            Platform::OsxUniversal => "osx-universal_binary",
+            Platform::Win64 => "win64",
+            Platform::Win32 => "win32",
+        }
+    }
+
+    /// Returns whether releases for this platform are Windows builds, i.e. `bin/protoc.exe`.
+    pub fn is_windows(&self) -> bool {
+        matches!(self, Platform::Win64 | Platform::Win32)
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl AsRef<str> for Platform {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl FromStr for Platform {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self, Error> {
+        match Platform::ALL
+            .iter()
+            .find(|platform| platform.as_str() == name)
+        {
+            Some(platform) => Ok(*platform),
+            None => Err(anyhow!(
+                "Unknown platform {name:?}; official releases are published for {}",
+                Platform::ALL
+                    .iter()
+                    .map(Platform::as_str)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+            .into()),
+        }
+    }
+}
+
+/// Returns the platform part of the release names for the platform protoc has to run on, e.g.
+/// `linux-x86_64` as in `protoc-21.2-linux-x86_64.zip`.
+///
+/// That is the host of the build: protoc runs during the build, so when cross-compiling, a
+/// release for the target would be of no use. Build scripts are told the host in the `HOST` env
+/// var; elsewhere, the platform this crate was compiled for is the one it runs on.
+///
+/// If no releases are published for the host, but those of another platform run on it under
+/// emulation, those are used if `allow_emulation` is set.
+pub(crate) fn host_platform(allow_emulation: bool) -> anyhow::Result<Platform> {
+    let (os, arch) = host_os_arch();
+    // The host is looked up many times per fetch, but only worth logging once.
+    static LOGGED: AtomicBool = AtomicBool::new(false);
+    if logging::current() != Verbosity::Silent && !LOGGED.swap(true, Ordering::Relaxed) {
+        if let Ok(target) = env::var("TARGET") {
+            if env::var("HOST").is_ok_and(|host| host != target) {
+                logging::info!(
+                    "Cross-compiling for {target}, fetching protoc for the host {os}, {arch}."
+                );
+            }
+        }
+        logging::debug!("Detected: {os}, {arch}");
+    }
+    match release_platform(&os, &arch) {
+        Err(err) if allow_emulation => match emulated_platform(&os, &arch) {
+            Some(platform) => {
+                logging::info!(
+                    "No protoc releases are published for {os}, {arch}; using the {platform} \
+                     release, which runs under emulation."
+                );
+                Ok(platform)
+            }
+            None => Err(err),
+        },
+        result => result,
+    }
+}
+
+/// Returns the OS (named as in [`env::consts::OS`]) and architecture of the build host, e.g.
+/// ("linux", "x86_64").
+pub(crate) fn host_os_arch() -> (String, String) {
+    match env::var("HOST") {
+        Ok(host) if !host.is_empty() => parse_triple(&host),
+        _ => compiled_platform(),
+    }
+}
+
+/// Returns whether the build host uses musl rather than glibc, e.g. Alpine Linux, where the
+/// official Linux releases (which are linked against glibc) don't run.
+pub(crate) fn host_is_musl() -> bool {
+    match env::var("HOST") {
+        Ok(host) if !host.is_empty() => host.contains("-linux-musl"),
+        _ => cfg!(all(target_os = "linux", target_env = "musl")),
+    }
+}
+
+/// Returns whether the build host is the platform of the target triple `triple`, e.g. for
+/// `x86_64-unknown-linux-gnu` on 64-bit x86 Linux. Build scripts are told the host's triple,
+/// which must match exactly; elsewhere, only the OS and architecture of the triple are compared.
+pub(crate) fn host_matches_triple(triple: &str) -> bool {
+    match env::var("HOST") {
+        Ok(host) if !host.is_empty() => host == triple,
+        _ => parse_triple(triple) == compiled_platform(),
+    }
+}
+
+/// Returns the OS and architecture this crate was compiled for, named as in target triples.
+fn compiled_platform() -> (String, String) {
+    let arch = match env::consts::ARCH {
+        "powerpc64" if cfg!(target_endian = "little") => "powerpc64le",
+        arch => arch,
+    };
+    (env::consts::OS.to_string(), arch.to_string())
+}
+
+/// Extracts the OS (named as in [`env::consts::OS`]) and architecture from a target triple such
+/// as `x86_64-unknown-linux-gnu` or `aarch64-apple-darwin`.
+fn parse_triple(triple: &str) -> (String, String) {
+    let arch = match triple.split('-').next().unwrap_or_default() {
+        "i386" | "i586" | "i686" => "x86",
+        arch => arch,
+    };
+    let os = if triple.contains("-linux") {
+        "linux"
+    } else if triple.contains("-apple-darwin") {
+        "macos"
+    } else if triple.contains("-windows") {
+        "windows"
+    } else {
+        triple.split('-').nth(2).unwrap_or(triple)
+    };
+    (os.to_string(), arch.to_string())
+}
+
+/// Returns the platform whose releases run under emulation on an OS and architecture that has no
+/// releases of its own: Windows on ARM64 runs x64 binaries.
+fn emulated_platform(os: &str, arch: &str) -> Option<Platform> {
+    match (os, arch) {
+        ("windows", "aarch64") => Some(Platform::Win64),
+        _ => None,
+    }
+}
+
+/// Maps an OS and architecture to the platform of the releases. Examples:
+///   - linux 64-bit: protoc-21.2-linux-x86_64.zip
+///   - macos ARM: protoc-21.2-osx-aarch_64.zip
+///   - windows 64-bit: protoc-21.2-win64.zip
+fn release_platform(os: &str, arch: &str) -> anyhow::Result<Platform> {
+    Ok(match (os, arch) {
+        ("linux", "x86_64") => Platform::LinuxX86_64,
+        ("linux", "aarch64") => Platform::LinuxAarch64,
+        ("linux", "x86") => Platform::LinuxX86_32,
+        ("linux", "powerpc64le") => Platform::LinuxPpcle64,
+        ("linux", "s390x") => Platform::LinuxS390x,
+        ("macos", "x86_64") => Platform::OsxX86_64,
+        ("macos", "aarch64") => Platform::OsxAarch64,
+        ("windows", "x86_64") => Platform::Win64,
+        ("windows", "x86") => Platform::Win32,
+        _ => bail!(Error::UnsupportedPlatform {
+            os: os.to_string(),
+            arch: arch.to_string(),
+        }),
+    })
+}
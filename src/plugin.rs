@@ -0,0 +1,292 @@
+//! Fetching prebuilt protoc plugins from their official releases.
+
+use crate::deadline;
+use crate::digest;
+use crate::error::Error;
+use crate::extract;
+use crate::fetcher::{LockWait, ProtocFetcher};
+use crate::install::{self, COMPLETE_MARKER, INSTALLS_DIR};
+use crate::lock::{Acquired, InstallLock};
+use crate::logging;
+use crate::progress::Progress;
+use anyhow::{bail, Context};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// The plugins that [`fetch_plugin`](crate::fetch_plugin) knows the official releases of.
+pub const KNOWN_PLUGINS: &[&str] = &[
+    "protoc-gen-go",
+    "protoc-gen-go-grpc",
+    "protoc-gen-grpc-java",
+    "protoc-gen-grpc-web",
+    "protoc-gen-js",
+    "protoc-gen-ts",
+];
+
+/// The subdirectory of the installs directory that plugins are installed into, one per release.
+pub(crate) const PLUGINS_DIR: &str = "plugins";
+
+/// Where a plugin release is downloaded from and how it is packaged.
+struct PluginRelease {
+    url: String,
+    /// The path of the binary within the download if it is an archive, rather than the binary
+    /// itself.
+    archive_entry: Option<String>,
+}
+
+/// Returns the release of plugin `name` at `version` for the host platform.
+fn plugin_release(name: &str, version: &str) -> anyhow::Result<PluginRelease> {
+    let os = env::consts::OS;
+    let arch = env::consts::ARCH;
+    let exe = env::consts::EXE_SUFFIX;
+    let unsupported = || {
+        anyhow::Error::from(Error::UnsupportedPlatform {
+            os: os.to_string(),
+            arch: arch.to_string(),
+        })
+        .context(format!("{name} does not publish binaries for {os}, {arch}"))
+    };
+    match name {
+        "protoc-gen-go" | "protoc-gen-go-grpc" => {
+            let go_os = match os {
+                "linux" | "windows" => os,
+                "macos" => "darwin",
+                _ => return Err(unsupported()),
+            };
+            let go_arch = match arch {
+                "x86_64" => "amd64",
+                "aarch64" => "arm64",
+                _ => return Err(unsupported()),
+            };
+            let ext = if os == "windows" { "zip" } else { "tar.gz" };
+            let base = if name == "protoc-gen-go" {
+                format!(
+                    "https://github.com/protocolbuffers/protobuf-go/releases/download/v{version}"
+                )
+            } else {
+                format!(
+                    "https://github.com/grpc/grpc-go/releases/download/cmd%2F{name}%2Fv{version}"
+                )
+            };
+            Ok(PluginRelease {
+                url: format!("{base}/{name}.v{version}.{go_os}.{go_arch}.{ext}"),
+                archive_entry: Some(format!("{name}{exe}")),
+            })
+        }
+        "protoc-gen-grpc-java" => {
+            // Published to Maven Central with the same platform names as protoc itself.
+            let maven_os = match os {
+                "linux" | "windows" => os,
+                "macos" => "osx",
+                _ => return Err(unsupported()),
+            };
+            let maven_arch = match arch {
+                "x86_64" => "x86_64",
+                "aarch64" => "aarch_64",
+                _ => return Err(unsupported()),
+            };
+            Ok(PluginRelease {
+                url: format!(
+                    "https://repo1.maven.org/maven2/io/grpc/{name}/{version}/\
+                     {name}-{version}-{maven_os}-{maven_arch}.exe"
+                ),
+                archive_entry: None,
+            })
+        }
+        "protoc-gen-grpc-web" => {
+            let web_os = match os {
+                "linux" | "windows" => os,
+                "macos" => "darwin",
+                _ => return Err(unsupported()),
+            };
+            if !matches!(arch, "x86_64" | "aarch64") {
+                return Err(unsupported());
+            }
+            Ok(PluginRelease {
+                url: format!(
+                    "https://github.com/grpc/grpc-web/releases/download/{version}/\
+                     {name}-{version}-{web_os}-{arch}{exe}"
+                ),
+                archive_entry: None,
+            })
+        }
+        "protoc-gen-js" => {
+            // Released like protoc itself since it moved out of the protobuf repository, as a zip
+            // with the binary in `bin/`.
+            let platform = match (os, arch) {
+                ("linux", "x86_64") => "linux-x86_64",
+                ("linux", "aarch64") => "linux-aarch_64",
+                ("macos", "x86_64") => "osx-x86_64",
+                ("macos", "aarch64") => "osx-aarch_64",
+                ("windows", "x86_64") => "win64",
+                _ => return Err(unsupported()),
+            };
+            Ok(PluginRelease {
+                url: format!(
+                    "https://github.com/protocolbuffers/protobuf-javascript/releases/download/\
+                     v{version}/protobuf-javascript-{version}-{platform}.zip"
+                ),
+                archive_entry: Some(format!("bin/{name}{exe}")),
+            })
+        }
+        "protoc-gen-ts" => {
+            // Single binaries, named with Node's platform names.
+            let node_os = match os {
+                "linux" => "linux",
+                "macos" => "darwin",
+                "windows" => "windows",
+                _ => return Err(unsupported()),
+            };
+            let node_arch = match arch {
+                "x86_64" => "x64",
+                "aarch64" => "arm64",
+                _ => return Err(unsupported()),
+            };
+            Ok(PluginRelease {
+                url: format!(
+                    "https://github.com/thesayyn/protoc-gen-ts/releases/download/{version}/\
+                     {name}-{node_os}-{node_arch}{exe}"
+                ),
+                archive_entry: None,
+            })
+        }
+        _ if name.starts_with("grpc_") && name.ends_with("_plugin") => bail!(
+            "gRPC doesn't publish binaries of {name}; build it from the gRPC sources and register \
+             it with Toolchain::plugin"
+        ),
+        _ => bail!("Don't know where to fetch {name} from; known plugins are {KNOWN_PLUGINS:?}"),
+    }
+}
+
+/// Installs plugin `name` at `version` into the install directory of `fetcher` (unless already
+/// installed) and returns the path to the binary. This is a fetch of its own, subject to the
+/// fetcher's [`deadline`](ProtocFetcher::deadline).
+///
+/// If `expected_sha256` is given, the download must have that hex-encoded SHA-256 digest, and an
+/// existing install of another download is replaced.
+pub(crate) fn fetch(
+    fetcher: &ProtocFetcher,
+    name: &str,
+    version: &str,
+    expected_sha256: Option<&str>,
+) -> anyhow::Result<PathBuf> {
+    deadline::run(fetcher, |fetcher| {
+        install(fetcher, name, version, expected_sha256)
+    })
+}
+
+fn install(
+    fetcher: &ProtocFetcher,
+    name: &str,
+    version: &str,
+    expected_sha256: Option<&str>,
+) -> anyhow::Result<PathBuf> {
+    let _verbosity = logging::scope(fetcher);
+    let release = plugin_release(name, version)?;
+    let plugin_dir = fetcher
+        .resolve_install_dir()?
+        .join(INSTALLS_DIR)
+        .join(PLUGINS_DIR)
+        .join(format!("{name}-{version}"));
+
+    let _lock = match InstallLock::acquire(&plugin_dir, &fetcher.lock_wait, &fetcher.cancellation)?
+    {
+        Acquired::Locked(lock) => lock,
+        Acquired::Contended => match &fetcher.lock_wait {
+            LockWait::Fallback(fallback_dir) => {
+                logging::warn!(
+                    "{name} install is locked by another process, using {fallback_dir:?}"
+                );
+                let fallback = ProtocFetcher {
+                    install_dir: Some(fallback_dir.clone()),
+                    lock_wait: LockWait::default(),
+                    ..fetcher.clone()
+                };
+                return install(&fallback, name, version, expected_sha256);
+            }
+            LockWait::Wait(timeout) => bail!(Error::Locked {
+                path: plugin_dir,
+                waited: *timeout,
+            }),
+            LockWait::Fail => bail!(Error::Locked {
+                path: plugin_dir,
+                waited: Duration::ZERO,
+            }),
+        },
+    };
+
+    let binary_name = release
+        .archive_entry
+        .clone()
+        .unwrap_or_else(|| format!("{name}{}", env::consts::EXE_SUFFIX));
+    let binary_path = plugin_dir.join(&binary_name);
+    // The completion marker holds the digest of the download the install was made from.
+    let marker = fs::read_to_string(plugin_dir.join(COMPLETE_MARKER)).ok();
+    let complete = match (&marker, expected_sha256) {
+        (Some(marker), Some(expected)) => marker.trim().eq_ignore_ascii_case(expected),
+        (marker, None) => marker.is_some(),
+        (None, Some(_)) => false,
+    };
+    if complete && binary_path.is_file() && fetcher.reuse_cache {
+        logging::info!("{name} v{version} is already installed.");
+        return Ok(binary_path);
+    }
+    if marker.is_some() && !complete {
+        logging::warn!(
+            "Existing {name} install was not made from the expected download, replacing it."
+        );
+    }
+
+    // As for protoc, install into a staging directory, and only move it into place once complete.
+    let staging_dir = install::staging_dir(&plugin_dir);
+    install::reset_dir(&staging_dir)?;
+    logging::info!(
+        "{name} v{version} not found, downloading from {}...",
+        release.url
+    );
+    let download_name = release.url.rsplit('/').next().unwrap_or(name);
+    let download_path = staging_dir.join(download_name);
+    install::download_file(fetcher, &release.url, &download_path, expected_sha256)?;
+    let download_sha256 = digest::sha256_file(&download_path, fetcher.buffer_size)?;
+    let staged_binary = staging_dir.join(&binary_name);
+    if release.archive_entry.is_some() {
+        let options = extract::ExtractOptions {
+            strip_components: None,
+            format: None,
+            ..fetcher.extract_options()
+        };
+        fetcher.report(Progress::Extracting {
+            archive: &download_path,
+        });
+        extract::extract_archive(&download_path, &staging_dir, &options, |entry| {
+            entry == binary_name
+        })?;
+        fs::remove_file(&download_path)?;
+    } else {
+        fs::rename(&download_path, &staged_binary)?;
+    }
+    if !staged_binary.is_file() {
+        bail!(Error::MissingBinary {
+            archive: download_path,
+            binary: binary_name,
+        });
+    }
+    extract::make_executable(&staged_binary)?;
+    install::strip_quarantine(fetcher, &staged_binary);
+    fs::write(
+        staging_dir.join(COMPLETE_MARKER),
+        format!("{download_sha256}\n"),
+    )?;
+    extract::normalize_tree(&staging_dir)?;
+
+    if plugin_dir.exists() {
+        fs::remove_dir_all(&plugin_dir)?;
+    }
+    fs::rename(&staging_dir, &plugin_dir)
+        .with_context(|| format!("Failed to move {staging_dir:?} to {plugin_dir:?}"))?;
+    logging::info!("{name} installed successfully: {binary_path:?}");
+    fetcher.report(Progress::Installed { path: &binary_path });
+    Ok(binary_path)
+}
@@ -0,0 +1,65 @@
+//! The hook set with [`ProtocFetcher::post_install`], run on every new install before it is used.
+
+use crate::fetcher::ProtocFetcher;
+use crate::logging;
+use anyhow::Context;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+/// A release that was just installed, as passed to the hook set with
+/// [`ProtocFetcher::post_install`](crate::ProtocFetcher::post_install).
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub struct PostInstall<'a> {
+    /// The version of the release, e.g. "21.2".
+    pub version: &'a str,
+    /// The platform of the release, as named in the release archives, e.g. `linux-x86_64`.
+    pub platform: &'a str,
+    /// The directory the release is installed in. It is a staging directory, moved into place
+    /// once the install is complete, so paths within it shouldn't be recorded.
+    pub install_dir: &'a Path,
+    /// The path to the protoc binary within `install_dir`.
+    pub protoc_path: &'a Path,
+}
+
+type Hook = dyn Fn(&PostInstall) -> anyhow::Result<()> + Send + Sync;
+
+/// The hook set with [`ProtocFetcher::post_install`].
+#[derive(Clone)]
+pub(crate) struct PostInstallHook(Arc<Hook>);
+
+impl PostInstallHook {
+    pub fn new(hook: impl Fn(&PostInstall) -> anyhow::Result<()> + Send + Sync + 'static) -> Self {
+        PostInstallHook(Arc::new(hook))
+    }
+}
+
+impl fmt::Debug for PostInstallHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PostInstallHook")
+    }
+}
+
+/// Runs the fetcher's hook, if it has one, on the release of `platform` installed into
+/// `install_dir` with its binary at `protoc_path`.
+pub(crate) fn run(
+    fetcher: &ProtocFetcher,
+    platform: &str,
+    install_dir: &Path,
+    protoc_path: &Path,
+) -> anyhow::Result<()> {
+    let Some(hook) = &fetcher.post_install else {
+        return Ok(());
+    };
+    logging::debug!("Running the post-install hook on {install_dir:?}.");
+    (hook.0)(&PostInstall {
+        version: &fetcher.version,
+        platform,
+        install_dir,
+        protoc_path,
+    })
+    .with_context(|| {
+        format!("The post-install hook failed on {install_dir:?}, so the install is discarded")
+    })
+}
@@ -0,0 +1,134 @@
+//! Reporting the progress of fetches, e.g. for rendering progress bars.
+
+use crate::fetcher::ProtocFetcher;
+use crate::logging::Verbosity;
+use std::env;
+use std::fmt;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// A step of a fetch, reported to the callback set with
+/// [`ProtocFetcher::progress`](crate::ProtocFetcher::progress).
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub enum Progress<'a> {
+    /// A download has started.
+    DownloadStarted {
+        url: &'a str,
+        /// The size of the download, if the server said.
+        total_bytes: Option<u64>,
+    },
+    /// More of a download has arrived. Reported every time a buffer's worth (see
+    /// [`ProtocFetcher::buffer_size`](crate::ProtocFetcher::buffer_size)) has been written.
+    Downloading {
+        url: &'a str,
+        downloaded_bytes: u64,
+        total_bytes: Option<u64>,
+    },
+    /// A download has completed.
+    DownloadFinished { url: &'a str },
+    /// Entries are being extracted from a downloaded archive.
+    Extracting { archive: &'a Path },
+    /// A release has been installed, with its binary at `path`.
+    Installed { path: &'a Path },
+}
+
+/// The callback set with [`ProtocFetcher::progress`](crate::ProtocFetcher::progress).
+#[derive(Clone)]
+pub(crate) struct ProgressCallback(Arc<dyn Fn(&Progress) + Send + Sync>);
+
+impl ProgressCallback {
+    pub fn new(callback: impl Fn(&Progress) + Send + Sync + 'static) -> Self {
+        ProgressCallback(Arc::new(callback))
+    }
+
+    pub fn report(&self, progress: &Progress) {
+        (self.0)(progress)
+    }
+}
+
+impl fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressCallback")
+    }
+}
+
+/// Reports [`Progress::Downloading`] to the fetcher's callback for everything read through it, and
+/// stops reading once the fetch has run out of time or was cancelled.
+pub(crate) struct ProgressReader<'a, R> {
+    pub inner: R,
+    pub fetcher: &'a ProtocFetcher,
+    pub url: &'a str,
+    pub total_bytes: Option<u64>,
+    pub downloaded_bytes: u64,
+    pub heartbeat: Option<Heartbeat>,
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.fetcher
+            .cancellation
+            .check()
+            .map_err(io::Error::other)?;
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            self.downloaded_bytes += n as u64;
+            self.fetcher.report(Progress::Downloading {
+                url: self.url,
+                downloaded_bytes: self.downloaded_bytes,
+                total_bytes: self.total_bytes,
+            });
+            if let Some(heartbeat) = &mut self.heartbeat {
+                heartbeat.beat(self.url, self.downloaded_bytes, self.total_bytes);
+            }
+        }
+        Ok(n)
+    }
+}
+
+/// Prints a `cargo:warning` directive about a running download every so often; see
+/// [`ProtocFetcher::download_heartbeat`].
+pub(crate) struct Heartbeat {
+    interval: Duration,
+    last: Instant,
+}
+
+impl Heartbeat {
+    /// Returns the heartbeat for a download that starts now, or `None` if the fetcher shouldn't
+    /// print one: outside of build scripts, with a progress callback, or for a silent fetch.
+    pub fn start(fetcher: &ProtocFetcher) -> Option<Self> {
+        let interval = fetcher.download_heartbeat?;
+        if fetcher.progress.is_some()
+            || fetcher.verbosity == Verbosity::Silent
+            || env::var_os("OUT_DIR").is_none()
+        {
+            return None;
+        }
+        Some(Heartbeat {
+            interval,
+            last: Instant::now(),
+        })
+    }
+
+    fn beat(&mut self, url: &str, downloaded_bytes: u64, total_bytes: Option<u64>) {
+        if self.last.elapsed() < self.interval {
+            return;
+        }
+        self.last = Instant::now();
+        let total = match total_bytes {
+            Some(total) => format!(" of {}", megabytes(total)),
+            None => String::new(),
+        };
+        println!(
+            "cargo:warning=Still downloading {url}: {}{total} received",
+            megabytes(downloaded_bytes)
+        );
+    }
+}
+
+/// Formats a byte count in megabytes, e.g. "2.5 MB".
+fn megabytes(bytes: u64) -> String {
+    format!("{:.1} MB", bytes as f64 / 1_000_000.0)
+}
@@ -0,0 +1,427 @@
+//! A handle to an installed protoc.
+
+use crate::compile::{self, DescriptorOptions, ProtocFailure, ProtocOutput};
+use crate::deadline::Cancellation;
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::error::Error;
+use crate::fetcher::ProtocFetcher;
+use crate::install;
+use crate::invocation::Invocation;
+use crate::long_path;
+use crate::wkt::{self, ExistingFiles, WktAvailability};
+use anyhow::{bail, Context};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use std::{env, fs, process};
+
+/// An installed protoc release, as returned by [`ProtocFetcher::fetch`].
+#[derive(Debug, Clone)]
+pub struct Protoc {
+    /// The fetcher that installed this release, for extracting more of it on demand. If the
+    /// install had to fall back to another directory, this points at that directory.
+    pub(crate) fetcher: ProtocFetcher,
+    pub(crate) protoc_dir: PathBuf,
+    path: PathBuf,
+    /// Whether this is a protoc found on the system rather than an installed release.
+    pub(crate) system: bool,
+    /// Whether the fetch reused an existing install.
+    pub(crate) cache_hit: bool,
+    /// The `current` link pointed at this install, with [`ProtocFetcher::link_current`].
+    pub(crate) current_dir: Option<PathBuf>,
+    timeout: Option<Duration>,
+}
+
+impl Protoc {
+    pub(crate) fn new(fetcher: ProtocFetcher, protoc_dir: PathBuf, cache_hit: bool) -> Self {
+        let platform = install::release_platform(&fetcher).unwrap_or_default();
+        let path = long_path::normalized(&install::protoc_path(&protoc_dir, &platform));
+        Protoc {
+            // Later uses of the fetcher (e.g. extracting the include tree) are fetches of their
+            // own, with a deadline of their own.
+            fetcher: ProtocFetcher {
+                cancellation: Cancellation::default(),
+                ..fetcher
+            },
+            protoc_dir,
+            path,
+            system: false,
+            cache_hit,
+            current_dir: None,
+            timeout: None,
+        }
+    }
+
+    /// Wraps a protoc found on the system, at `path`. `fetcher` fetches the matching release, for
+    /// the files that the system protoc doesn't come with.
+    pub(crate) fn system(fetcher: ProtocFetcher, path: PathBuf) -> Self {
+        // Usually `<prefix>/bin/protoc`, with the well-known types in `<prefix>/include`.
+        let protoc_dir = path
+            .parent()
+            .and_then(Path::parent)
+            .unwrap_or(Path::new(""))
+            .to_path_buf();
+        Protoc {
+            fetcher: ProtocFetcher {
+                cancellation: Cancellation::default(),
+                ..fetcher
+            },
+            protoc_dir,
+            path,
+            system: true,
+            cache_hit: true,
+            current_dir: None,
+            timeout: None,
+        }
+    }
+
+    /// Fetches protoc `version` into `install_dir`, reusing an existing install; shorthand for
+    /// [`ProtocFetcher::new`] with [`install_dir`](ProtocFetcher::install_dir) and
+    /// [`fetch`](ProtocFetcher::fetch).
+    pub fn fetch(version: &str, install_dir: impl Into<PathBuf>) -> Result<Protoc, Error> {
+        ProtocFetcher::new(version).install_dir(install_dir).fetch()
+    }
+
+    /// Sets how long the protoc invocations made through this handle (e.g.
+    /// [`compile`](Self::compile)) may take before protoc is killed and the invocation fails, so
+    /// that a hung protoc can't wedge a build. By default there is no limit.
+    ///
+    /// This doesn't apply to [`command`](Self::command), which leaves running protoc to you.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Returns the path to the protoc binary.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the path to the protoc binary through the `current` link, which stays the same
+    /// across versions, if the fetcher maintains one (see [`ProtocFetcher::link_current`]).
+    pub fn current_path(&self) -> Option<PathBuf> {
+        let binary = self.path.strip_prefix(&self.protoc_dir).ok()?;
+        self.current_dir.as_ref().map(|dir| dir.join(binary))
+    }
+
+    /// Returns the version of this release, as given to [`ProtocFetcher::new`] or, if that was
+    /// "latest" or a partial version, as resolved from it.
+    pub fn version(&self) -> &str {
+        &self.fetcher.version
+    }
+
+    /// Returns the directory the release is installed in, containing `bin/protoc`. For a system
+    /// protoc, this is the parent of the directory containing the binary.
+    pub fn install_dir(&self) -> &Path {
+        &self.protoc_dir
+    }
+
+    /// Returns a [`Command`] that runs this protoc with the release's `include` directory as an
+    /// include path (`-I`), so that imports of the well-known types resolve. Add the remaining
+    /// arguments and run it:
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use protoc_fetcher::ProtocFetcher;
+    ///
+    /// let protoc = ProtocFetcher::new("21.2").fetch()?;
+    /// let status = protoc
+    ///     .command()?
+    ///     .args(["-Iprotos", "--cpp_out=gen", "protos/my_service.proto"])
+    ///     .status()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// The `include` directory comes first, so files given with later `-I` flags can't shadow the
+    /// well-known types.
+    pub fn command(&self) -> Result<Command, Error> {
+        let mut command = Command::new(&self.path);
+        command.args(compile::include_args(&[self.include_path()?]));
+        Ok(command)
+    }
+
+    /// Starts a protoc invocation with typed arguments, for what [`compile`](Self::compile),
+    /// [`descriptor_set`](Self::descriptor_set) and the like don't cover; see [`Invocation`].
+    pub fn invocation(&self) -> Invocation<'_> {
+        Invocation::new(self)
+    }
+
+    /// Returns the path to the release's `include` directory, which contains the well-known types
+    /// (e.g. `google/protobuf/timestamp.proto`). The directory is extracted from the release
+    /// archive the first time this is called for an install.
+    ///
+    /// For a system protoc (see [`ProtocFetcher::system_protoc`]), this is the `include`
+    /// directory next to its `bin` directory if that has the well-known types, and otherwise that
+    /// of the matching release, which is fetched for it.
+    pub fn include_path(&self) -> Result<PathBuf, Error> {
+        if self.system {
+            let include_path = self.protoc_dir.join("include");
+            if include_path
+                .join("google/protobuf/descriptor.proto")
+                .is_file()
+            {
+                return Ok(include_path);
+            }
+        }
+        // If the install lock is contended, this may end up using a fallback install.
+        let protoc = self
+            .fetcher
+            .with_install(install::ensure_include_extracted)?;
+        Ok(long_path::normalized(&protoc.protoc_dir.join("include")))
+    }
+
+    /// Returns the path to the directory containing the well-known type protos themselves, i.e.
+    /// `include/google/protobuf`, for code generators and validators that need their sources.
+    /// The `include` directory is extracted on first use, as with
+    /// [`include_path`](Self::include_path).
+    pub fn wkt_dir(&self) -> Result<PathBuf, Error> {
+        Ok(long_path::join(&self.include_path()?, "google/protobuf"))
+    }
+
+    /// Reports which of `protos` (paths relative to the `include` directory, e.g.
+    /// `google/protobuf/cpp_features.proto`) this release provides, since the bundled set varies
+    /// between versions. Pass [`WELL_KNOWN_TYPES`](crate::WELL_KNOWN_TYPES) for the protos every
+    /// release has.
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use protoc_fetcher::ProtocFetcher;
+    ///
+    /// let protoc = ProtocFetcher::new("27.0").fetch()?;
+    /// protoc
+    ///     .check_well_known_types(&["google/protobuf/cpp_features.proto"])?
+    ///     .require_all()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn check_well_known_types(&self, protos: &[&str]) -> Result<WktAvailability, Error> {
+        Ok(WktAvailability::check(&self.include_path()?, protos))
+    }
+
+    /// Returns the path to `google/protobuf/descriptor.proto`, which descriptor-set tooling and
+    /// custom options build on.
+    pub fn descriptor_proto(&self) -> Result<PathBuf, Error> {
+        Ok(self.wkt_dir()?.join("descriptor.proto"))
+    }
+
+    /// Returns the path to `google/protobuf/compiler/plugin.proto`, which defines the protocol
+    /// between protoc and its code generator plugins.
+    pub fn plugin_proto(&self) -> Result<PathBuf, Error> {
+        Ok(long_path::join(&self.wkt_dir()?, "compiler/plugin.proto"))
+    }
+
+    /// Configures [prost-build] and [tonic-build] consistently from one call: sets the env vars
+    /// like [`set_env`](Self::set_env), and returns `includes` with the release's `include`
+    /// directory appended, to pass as the include paths of either crate's compile call.
+    ///
+    /// The crates (and their versions) differ in whether they honor `PROTOC_INCLUDE` and in where
+    /// they put it relative to the given includes, so passing the `include` directory explicitly
+    /// as well makes the well-known types resolve the same way with either of them.
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use protoc_fetcher::ProtocFetcher;
+    ///
+    /// let protoc = ProtocFetcher::new("21.2").fetch()?;
+    /// let includes = protoc.codegen_includes(&["protos"])?;
+    ///
+    /// // With prost-build...
+    /// prost_build::compile_protos(&["protos/my_service.proto"], &includes)?;
+    /// // ...or with tonic-build.
+    /// tonic_build::configure().compile(&["protos/my_service.proto"], &includes)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [prost-build]: https://crates.io/crates/prost-build
+    /// [tonic-build]: https://crates.io/crates/tonic-build
+    pub fn codegen_includes(&self, includes: &[impl AsRef<Path>]) -> Result<Vec<PathBuf>, Error> {
+        self.set_env()?;
+        let mut includes: Vec<PathBuf> = includes
+            .iter()
+            .map(|include| include.as_ref().to_path_buf())
+            .collect();
+        includes.push(self.include_path()?);
+        Ok(includes)
+    }
+
+    /// Returns the path to the `src/` proto tree of the protobuf source release, which contains
+    /// protos that aren't bundled with protoc, such as
+    /// `google/protobuf/test_messages_proto3.proto` and the conformance protos. Use it as an
+    /// include path.
+    ///
+    /// The source release is downloaded the first time this is called for an install, unless
+    /// [`ProtocFetcher::fetch_source`] already did so.
+    pub fn source_path(&self) -> Result<PathBuf, Error> {
+        let protoc = self
+            .fetcher
+            .with_install(install::ensure_source_extracted)?;
+        Ok(protoc.protoc_dir.join("source/src"))
+    }
+
+    /// Points [prost-build] (and so [tonic-build]) at this install by setting the `PROTOC` env var
+    /// to the protoc binary and `PROTOC_INCLUDE` to the release's `include` directory, which is
+    /// extracted if need be. Call this from `build.rs` before invoking either crate.
+    ///
+    /// prost-build passes `PROTOC_INCLUDE` to protoc as an extra `-I` (after the include paths
+    /// given to it directly), so imports of the well-known types resolve against the files that
+    /// match this protoc release, rather than failing or picking up a system-wide copy.
+    ///
+    /// The env vars are set for the current process only, so this must be called from the build
+    /// script that runs the code generation.
+    ///
+    /// [prost-build]: https://crates.io/crates/prost-build
+    /// [tonic-build]: https://crates.io/crates/tonic-build
+    pub fn set_env(&self) -> Result<(), Error> {
+        let include_path = self.include_path()?;
+        env::set_var("PROTOC", &self.path);
+        env::set_var("PROTOC_INCLUDE", include_path);
+        Ok(())
+    }
+
+    /// Copies the release's `include` tree (the well-known types) into `dest`, e.g. to vendor them
+    /// into a repository alongside other proto dependencies. `existing` decides what happens to
+    /// files already present in `dest`; other files in `dest` are never touched.
+    ///
+    /// Returns the paths of the files written, relative to `dest`.
+    pub fn export_well_known_types(
+        &self,
+        dest: impl AsRef<Path>,
+        existing: ExistingFiles,
+    ) -> Result<Vec<PathBuf>, Error> {
+        Ok(wkt::export_tree(
+            &self.include_path()?,
+            dest.as_ref(),
+            existing,
+        )?)
+    }
+
+    /// Runs protoc over the well-known types and writes the resulting serialized
+    /// `FileDescriptorSet` to `out`, for reflection-based tools and dynamic-message libraries that
+    /// need the descriptors of the well-known types at build time.
+    pub fn write_well_known_types_descriptor_set(
+        &self,
+        out: impl AsRef<Path>,
+    ) -> Result<(), Error> {
+        let options = DescriptorOptions {
+            include_imports: true,
+            include_source_info: false,
+        };
+        let no_includes: &[&Path] = &[];
+        self.write_descriptor_set(wkt::WELL_KNOWN_TYPES, no_includes, options, out)
+            .context("Failed to build the well-known types descriptor set")?;
+        Ok(())
+    }
+
+    /// Like [`write_well_known_types_descriptor_set`](Self::write_well_known_types_descriptor_set),
+    /// but returns the serialized `FileDescriptorSet` instead of writing it to a file.
+    pub fn well_known_types_descriptor_set(&self) -> Result<Vec<u8>, Error> {
+        Ok(with_temp_file("wkt.pb", |out| {
+            self.write_well_known_types_descriptor_set(out)?;
+            fs::read(out).with_context(|| format!("Failed to read {out:?}"))
+        })?)
+    }
+
+    /// Runs `command`, which must be a protoc command, capturing its output. Fails with a
+    /// [`ProtocFailure`] (which becomes an [`Error::ProtocFailed`]) if protoc exits unsuccessfully,
+    /// and kills protoc if it exceeds the [`timeout`](Self::timeout).
+    pub(crate) fn run(&self, command: Command) -> anyhow::Result<ProtocOutput> {
+        self.run_with_input(command, None)
+    }
+
+    /// Like [`run`](Self::run), but feeds `input` (if any) to protoc's stdin.
+    pub(crate) fn run_with_input(
+        &self,
+        mut command: Command,
+        input: Option<Vec<u8>>,
+    ) -> anyhow::Result<ProtocOutput> {
+        let stdin = if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        };
+        let mut child = command
+            .stdin(stdin)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to run {:?}", self.path))?;
+        // Feed stdin and drain both pipes while waiting, so that protoc can't block on a full pipe.
+        if let (Some(input), Some(mut pipe)) = (input, child.stdin.take()) {
+            // Dropping the pipe afterwards closes it, which ends protoc's input.
+            thread::spawn(move || pipe.write_all(&input));
+        }
+        let stdout = read_in_background(child.stdout.take());
+        let stderr = read_in_background(child.stderr.take());
+        let status = match self.timeout {
+            None => child.wait()?,
+            Some(timeout) => {
+                let deadline = Instant::now() + timeout;
+                loop {
+                    if let Some(status) = child.try_wait()? {
+                        break status;
+                    }
+                    if Instant::now() >= deadline {
+                        let _ = child.kill();
+                        let _ = child.wait();
+                        bail!("protoc did not finish within {timeout:?} and was killed");
+                    }
+                    thread::sleep(Duration::from_millis(10));
+                }
+            }
+        };
+        let stdout = stdout.join().unwrap_or_default();
+        let stderr = String::from_utf8_lossy(&stderr.join().unwrap_or_default()).into_owned();
+
+        let mut diagnostics = Diagnostic::parse_all(&stderr);
+        if status.success() {
+            // Nothing was an error if protoc succeeded, whether or not it said "warning".
+            for diagnostic in &mut diagnostics {
+                diagnostic.severity = Severity::Warning;
+            }
+        } else {
+            return Err(ProtocFailure {
+                status,
+                stdout,
+                stderr,
+                diagnostics,
+            }
+            .into());
+        }
+        Ok(ProtocOutput {
+            stdout,
+            stderr,
+            diagnostics,
+            dependencies: Vec::new(),
+        })
+    }
+}
+
+/// Reads `pipe` to the end on another thread.
+fn read_in_background(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut bytes);
+        }
+        bytes
+    })
+}
+
+/// Runs `f` with the path of a temporary file named after `name`, deleting the file afterwards.
+pub(crate) fn with_temp_file<T, E>(
+    name: &str,
+    f: impl FnOnce(&Path) -> Result<T, E>,
+) -> Result<T, E> {
+    // Unique per call, so that concurrent calls (from any process) don't clobber each other.
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    let call = CALLS.fetch_add(1, Ordering::Relaxed);
+    let path = env::temp_dir().join(format!("protoc-fetcher-{}-{call}-{name}", process::id()));
+    let result = f(&path);
+    let _ = fs::remove_file(&path);
+    result
+}
@@ -0,0 +1,81 @@
+//! The `com.apple.quarantine` attribute that macOS puts on files downloaded by some HTTP stacks,
+//! which gets binaries blocked by Gatekeeper when they are run outside of a Terminal; see
+//! [`ProtocFetcher::strip_quarantine`].
+
+use std::io;
+use std::path::Path;
+
+/// Removes the quarantine attribute from the file at `path`, returning whether it had one.
+/// Elsewhere than on macOS, there is no such attribute.
+pub(crate) fn strip(path: &Path) -> io::Result<bool> {
+    #[cfg(target_os = "macos")]
+    return xattr::remove(path);
+    #[cfg(not(target_os = "macos"))]
+    {
+        let _ = path;
+        Ok(false)
+    }
+}
+
+/// Returns whether the file at `path` has the quarantine attribute.
+pub(crate) fn is_quarantined(path: &Path) -> bool {
+    #[cfg(target_os = "macos")]
+    return xattr::has(path);
+    #[cfg(not(target_os = "macos"))]
+    {
+        let _ = path;
+        false
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod xattr {
+    use std::ffi::{c_char, c_int, c_void, CString};
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    use std::ptr;
+
+    const QUARANTINE: &std::ffi::CStr = c"com.apple.quarantine";
+    /// The `errno` of a file without the attribute.
+    const ENOATTR: i32 = 93;
+
+    extern "C" {
+        fn getxattr(
+            path: *const c_char,
+            name: *const c_char,
+            value: *mut c_void,
+            size: usize,
+            position: u32,
+            options: c_int,
+        ) -> isize;
+        fn removexattr(path: *const c_char, name: *const c_char, options: c_int) -> c_int;
+    }
+
+    fn c_path(path: &Path) -> io::Result<CString> {
+        CString::new(path.as_os_str().as_bytes())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+    }
+
+    pub(super) fn remove(path: &Path) -> io::Result<bool> {
+        let path = c_path(path)?;
+        // SAFETY: both are valid NUL-terminated strings.
+        if unsafe { removexattr(path.as_ptr(), QUARANTINE.as_ptr(), 0) } == 0 {
+            return Ok(true);
+        }
+        let err = io::Error::last_os_error();
+        match err.raw_os_error() {
+            Some(ENOATTR) => Ok(false),
+            _ => Err(err),
+        }
+    }
+
+    pub(super) fn has(path: &Path) -> bool {
+        let Ok(path) = c_path(path) else {
+            return false;
+        };
+        // SAFETY: both are valid NUL-terminated strings, and a null buffer of size 0 only asks
+        // for the size of the value.
+        unsafe { getxattr(path.as_ptr(), QUARANTINE.as_ptr(), ptr::null_mut(), 0, 0, 0) >= 0 }
+    }
+}
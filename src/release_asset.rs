@@ -0,0 +1,189 @@
+//! Downloading arbitrary GitHub release assets, e.g. ones the fetcher doesn't know of such as
+//! `protoc-<version>-osx-universal_binary.zip`.
+
+use crate::deadline;
+use crate::digest;
+use crate::error::Error;
+use crate::fetcher::{LockWait, ProtocFetcher};
+use crate::forge;
+use crate::install;
+use crate::lock::{Acquired, InstallLock};
+use crate::logging;
+use anyhow::{bail, Context};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+impl ProtocFetcher {
+    /// Downloads the asset named `asset_name` of the release tagged `tag` in the GitHub
+    /// repository `repo` (e.g. `protocolbuffers/protobuf`) to `<out_dir>/<asset_name>`, unless it
+    /// is already there, and returns the path to it. Nothing is extracted or installed.
+    ///
+    /// This is the building block below [`fetch`](Self::fetch), for assets the fetcher doesn't
+    /// know of, such as the `protoc-<version>-osx-universal_binary.zip` of some releases. The
+    /// download is retried, resumed and verified as configured for protoc releases: a
+    /// [`sha256`](Self::sha256) digest pins the asset (an existing download with another digest is
+    /// replaced), [`verify_checksum_asset`](Self::verify_checksum_asset) checks it against its
+    /// `.sha256` asset, and with the `attestations` feature, assets of the
+    /// [`release_repo`](Self::release_repo) are verified as
+    /// [`verify_attestations`](Self::verify_attestations) says. Assets are downloaded from the
+    /// [`github_host`](Self::github_host); the fetcher's version, platform and mirrors don't
+    /// apply.
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use protoc_fetcher::ProtocFetcher;
+    ///
+    /// let archive = ProtocFetcher::new("31.1").fetch_release_asset(
+    ///     "protocolbuffers/protobuf",
+    ///     "v31.1",
+    ///     "protoc-31.1-osx-universal_binary.zip",
+    ///     "downloads",
+    /// )?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn fetch_release_asset(
+        &self,
+        repo: &str,
+        tag: &str,
+        asset_name: &str,
+        out_dir: impl AsRef<Path>,
+    ) -> Result<PathBuf, Error> {
+        let _verbosity = logging::scope(self);
+        Ok(deadline::run(self, |fetcher| {
+            fetcher.download_release_asset(repo, tag, asset_name, out_dir.as_ref())
+        })?)
+    }
+
+    fn download_release_asset(
+        &self,
+        repo: &str,
+        tag: &str,
+        asset_name: &str,
+        out_dir: &Path,
+    ) -> anyhow::Result<PathBuf> {
+        forge::validate_repo(repo)?;
+        if asset_name.is_empty()
+            || asset_name.contains(['/', '\\'])
+            || matches!(asset_name, "." | "..")
+        {
+            bail!("Invalid release asset name {asset_name:?}");
+        }
+        let path = out_dir.join(asset_name);
+        let _lock = match InstallLock::acquire(&path, &self.lock_wait, &self.cancellation)? {
+            Acquired::Locked(lock) => lock,
+            Acquired::Contended => bail!(Error::Locked {
+                path,
+                waited: match self.lock_wait {
+                    LockWait::Wait(timeout) => timeout,
+                    LockWait::Fail | LockWait::Fallback(_) => Duration::ZERO,
+                },
+            }),
+        };
+
+        if self.reuse_cache && path.is_file() {
+            match &self.expected_sha256 {
+                Some(expected) => {
+                    let actual = digest::sha256_file(&path, self.buffer_size)?;
+                    if actual.eq_ignore_ascii_case(expected) {
+                        logging::info!("{path:?} is already present.");
+                        return Ok(path);
+                    }
+                    logging::warn!("{path:?} has digest {actual}, not {expected}; downloading it.");
+                }
+                None => {
+                    logging::info!("{path:?} is already present.");
+                    return Ok(path);
+                }
+            }
+        }
+
+        let url = format!(
+            "{}/{repo}/releases/download/{tag}/{asset_name}",
+            forge::web_url(self)
+        );
+        logging::info!("Downloading {asset_name} from {url}...");
+        install::download_file(self, &url, &path, self.expected_sha256.as_deref())
+            .with_context(|| format!("Failed to fetch {asset_name} of {repo} {tag}"))?;
+        #[cfg(feature = "attestations")]
+        if repo == forge::repo(self) {
+            if let Err(err) = crate::attestation::verify(self, &path) {
+                std::fs::remove_file(&path)?;
+                return Err(err);
+            }
+        }
+        logging::info!("Downloaded {asset_name} to {path:?}.");
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{MockHttp, Sandbox};
+    use std::fs;
+
+    const ASSET: &str = "protoc-31.1-osx-universal_binary.zip";
+    const URL: &str = concat!(
+        "https://github.com/protocolbuffers/protobuf/releases/download/v31.1/",
+        "protoc-31.1-osx-universal_binary.zip"
+    );
+
+    fn fetch(http: &MockHttp, sandbox: &Sandbox) -> Result<PathBuf, Error> {
+        ProtocFetcher::new("31.1")
+            .http_client(http.clone())
+            .fetch_release_asset("protocolbuffers/protobuf", "v31.1", ASSET, sandbox.path())
+    }
+
+    #[test]
+    fn downloads_the_asset_once() {
+        let http = MockHttp::new().respond(URL, "universal protoc");
+        let sandbox = Sandbox::new().unwrap();
+        let path = fetch(&http, &sandbox).unwrap();
+        assert_eq!(path, sandbox.path().join(ASSET));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "universal protoc");
+
+        assert_eq!(fetch(&http, &sandbox).unwrap(), path);
+        assert_eq!(http.requests(), [URL]);
+    }
+
+    #[test]
+    fn downloads_from_the_github_host() {
+        let url = "https://github.example/acme/protobuf/releases/download/v1.0/protoc.tar.gz";
+        let http = MockHttp::new().respond(url, "protoc");
+        let sandbox = Sandbox::new().unwrap();
+        ProtocFetcher::new("31.1")
+            .github_host("github.example")
+            .http_client(http.clone())
+            .fetch_release_asset("acme/protobuf", "v1.0", "protoc.tar.gz", sandbox.path())
+            .unwrap();
+        assert_eq!(http.requests(), [url]);
+    }
+
+    #[test]
+    fn replaces_a_download_with_another_digest() {
+        let http = MockHttp::new().respond(URL, "universal protoc");
+        let sandbox = Sandbox::new().unwrap();
+        fs::write(sandbox.path().join(ASSET), "stale protoc").unwrap();
+        let path = ProtocFetcher::new("31.1")
+            .sha256(&digest::sha256_bytes(b"universal protoc"))
+            .http_client(http.clone())
+            .fetch_release_asset("protocolbuffers/protobuf", "v31.1", ASSET, sandbox.path())
+            .unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "universal protoc");
+        assert_eq!(http.requests(), [URL]);
+    }
+
+    #[test]
+    fn rejects_asset_names_that_arent_file_names() {
+        let http = MockHttp::new();
+        let sandbox = Sandbox::new().unwrap();
+        for name in ["", ".", "..", "../protoc.zip", "bin/protoc", "bin\\protoc"] {
+            ProtocFetcher::new("31.1")
+                .http_client(http.clone())
+                .fetch_release_asset("protocolbuffers/protobuf", "v31.1", name, sandbox.path())
+                .unwrap_err();
+        }
+        assert_eq!(http.requests(), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,69 @@
+//! Verifying the release tag against the SHA pinned with [`ProtocFetcher::tag_sha`], through the
+//! GitHub API.
+
+use crate::error::Error;
+use crate::fetcher::ProtocFetcher;
+use crate::forge;
+use crate::http;
+use crate::logging;
+use crate::resolve;
+use anyhow::{bail, Context};
+use serde_json::Value;
+
+/// Returns the SHA the release tag is pinned to with [`ProtocFetcher::tag_sha`], unless the
+/// release is installed from a vendored [`archive`](ProtocFetcher::archive), which it doesn't
+/// apply to.
+pub(crate) fn pinned(fetcher: &ProtocFetcher) -> Option<&str> {
+    fetcher
+        .tag_sha
+        .as_deref()
+        .filter(|_| fetcher.archive.is_none())
+}
+
+/// Checks that the release tag of the version `fetcher` fetches points where `expected` says:
+/// `expected` is either the SHA of the tag object (for an annotated tag) or that of the commit
+/// it is on. Fails with [`Error::TagMoved`] if it doesn't, and without sending any request if
+/// the fetcher is [`offline`](ProtocFetcher::offline).
+pub(crate) fn verify(fetcher: &ProtocFetcher, expected: &str) -> anyhow::Result<()> {
+    if !matches!(expected.len(), 40 | 64) || !expected.bytes().all(|b| b.is_ascii_hexdigit()) {
+        bail!("Invalid tag SHA {expected:?}: expected the full hex-encoded SHA of a tag or commit");
+    }
+    let tag = format!("v{}", resolve::tag_version(&fetcher.version));
+    if fetcher.offline {
+        bail!(
+            "Offline mode is on, so the tag {tag} can't be verified to be at {expected} through \
+             the GitHub API; pre-populate the install directory, or unset ProtocFetcher::tag_sha"
+        );
+    }
+    let api_base_url = forge::api_base_url(fetcher)?;
+
+    let reference = get_json(fetcher, &format!("{api_base_url}/git/ref/tags/{tag}"))?;
+    let object = &reference["object"];
+    let sha = object["sha"]
+        .as_str()
+        .with_context(|| format!("The GitHub API returned no SHA for the tag {tag}"))?;
+    let mut shas = vec![sha.to_string()];
+    if object["type"].as_str() == Some("tag") {
+        // An annotated tag: the ref points at the tag object, which points at the commit.
+        let tag_object = get_json(fetcher, &format!("{api_base_url}/git/tags/{sha}"))?;
+        if let Some(commit) = tag_object["object"]["sha"].as_str() {
+            shas.push(commit.to_string());
+        }
+    }
+    if !shas.iter().any(|sha| sha.eq_ignore_ascii_case(expected)) {
+        bail!(Error::TagMoved {
+            tag,
+            expected: expected.to_ascii_lowercase(),
+            actual: shas.join(", on commit "),
+        });
+    }
+    logging::info!("Verified that the tag {tag} is at {expected}.");
+    Ok(())
+}
+
+/// Gets `url` from the API, bypassing the [API cache](ProtocFetcher::api_cache_ttl): a tag that
+/// was just moved must not go unnoticed.
+fn get_json(fetcher: &ProtocFetcher, url: &str) -> anyhow::Result<Value> {
+    serde_json::from_reader(http::get(fetcher, url)?)
+        .with_context(|| format!("Invalid JSON from {url}"))
+}
@@ -0,0 +1,87 @@
+//! Working out the oldest protoc release that supports a set of features.
+
+use crate::error::Error;
+use crate::fetcher::ProtocFetcher;
+use crate::wkt::WELL_KNOWN_TYPES;
+use anyhow::bail;
+
+/// A feature that protos may need from protoc, for [`min_protoc_version`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocFeature {
+    /// `optional` fields in proto3 files, without experimental flags.
+    Proto3Optional,
+    /// Edition 2023.
+    Edition2023,
+    /// Edition 2024.
+    Edition2024,
+    /// A proto bundled in the release's `include/` tree, relative to it, e.g.
+    /// `google/protobuf/timestamp.proto`.
+    WellKnownType(String),
+}
+
+/// A release in the numbering protoc has used since 21.0, i.e. 3.15.0 is (15, 0).
+type Release = (u32, u32);
+
+impl ProtocFeature {
+    /// Returns the first release supporting the feature.
+    fn min_release(&self) -> anyhow::Result<Release> {
+        Ok(match self {
+            ProtocFeature::Proto3Optional => (15, 0),
+            ProtocFeature::Edition2023 => (27, 0),
+            ProtocFeature::Edition2024 => (32, 0),
+            ProtocFeature::WellKnownType(proto) => {
+                if WELL_KNOWN_TYPES.contains(&proto.as_str()) {
+                    // Every release this crate can fetch has them.
+                    (0, 0)
+                } else if proto == "google/protobuf/cpp_features.proto" {
+                    (27, 0)
+                } else {
+                    bail!("Don't know which protoc releases bundle {proto}");
+                }
+            }
+        })
+    }
+}
+
+/// Returns the oldest protoc version (e.g. "27.0", or "3.15.0" for releases before 21.0) that
+/// supports all of `features`, for library authors who want the oldest protoc that can build
+/// their protos rather than hardcoding a version.
+///
+/// With no features (or only features every release has), this is "3.0.0".
+pub fn min_protoc_version(features: &[ProtocFeature]) -> Result<String, Error> {
+    let mut min = (0, 0);
+    for feature in features {
+        min = min.max(feature.min_release()?);
+    }
+    Ok(version_string(min))
+}
+
+/// Formats `release` the way the release tags do: 3.x.y before 21.0, x.y since.
+fn version_string((major, minor): Release) -> String {
+    if major < 21 {
+        format!("3.{major}.{minor}")
+    } else {
+        format!("{major}.{minor}")
+    }
+}
+
+impl ProtocFetcher {
+    /// Creates a fetcher for the oldest protoc version that supports all of `features`; see
+    /// [`min_protoc_version`].
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use protoc_fetcher::{ProtocFeature, ProtocFetcher};
+    ///
+    /// let protoc = ProtocFetcher::for_features(&[
+    ///     ProtocFeature::Edition2023,
+    ///     ProtocFeature::Proto3Optional,
+    /// ])?
+    /// .fetch()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn for_features(features: &[ProtocFeature]) -> Result<Self, Error> {
+        Ok(ProtocFetcher::new(&min_protoc_version(features)?))
+    }
+}
@@ -0,0 +1,142 @@
+//! Resolving "latest", partial versions (e.g. "31") and version requirements (e.g. ">=27, <32")
+//! to concrete releases via the GitHub Releases API.
+
+use crate::api_cache;
+use crate::fetcher::ProtocFetcher;
+use crate::forge;
+use crate::install;
+use crate::installed;
+use crate::logging;
+use crate::version_req::VersionReq;
+use anyhow::{bail, Context};
+use serde_json::Value;
+
+/// How many pages of releases to look through for a match before giving up.
+const MAX_PAGES: u32 = 10;
+
+/// Returns whether `version` must be resolved to a concrete release before it can be fetched,
+/// i.e. it is "latest", a [requirement](VersionReq), or leaves out trailing components: "31"
+/// rather than "31.1", or "3.20" rather than "3.20.3" for releases before 21.0.
+pub(crate) fn needs_resolution(version: &str) -> bool {
+    if version == "latest" || VersionReq::is_requirement(version) {
+        return true;
+    }
+    let Some(components) = parse_components(version) else {
+        return false;
+    };
+    let full_len = if components[0] == 3 { 3 } else { 2 };
+    components.len() < full_len
+}
+
+/// Resolves `spec` (see [`needs_resolution`]) to the newest matching release, ignoring drafts and
+/// pre-releases, e.g. "31" to "31.1".
+///
+/// A requirement is resolved to the newest matching release that is already installed if there
+/// is one, and only looked up on GitHub otherwise.
+pub(crate) fn resolve(fetcher: &ProtocFetcher, spec: &str) -> anyhow::Result<String> {
+    if VersionReq::is_requirement(spec) {
+        let req = VersionReq::parse(spec)?;
+        if let Some(version) = newest_installed(fetcher, &req) {
+            logging::info!("Resolved protoc version {spec:?} to {version}, which is installed.");
+            return Ok(version);
+        }
+        return newest_release(fetcher, spec, |version, _| req.matches(version));
+    }
+    let prefix = if spec == "latest" {
+        Vec::new()
+    } else {
+        parse_components(spec).with_context(|| format!("Invalid protoc version {spec:?}"))?
+    };
+    newest_release(fetcher, spec, |_, components| {
+        components.starts_with(&prefix)
+    })
+}
+
+/// Returns the newest release installed for the fetcher's platform that satisfies `req`.
+fn newest_installed(fetcher: &ProtocFetcher, req: &VersionReq) -> Option<String> {
+    let install_dir = fetcher.resolve_install_dir().ok()?;
+    let platform = install::release_platform(fetcher).ok()?;
+    let releases = installed::installed_versions(install_dir).ok()?;
+    // Listed oldest version first.
+    releases
+        .into_iter()
+        .rev()
+        .find(|release| release.platform == platform && req.matches(&release.version))
+        .map(|release| release.version)
+}
+
+/// Returns the newest release published on GitHub whose version (also given split into its
+/// components) is accepted by `matches`, ignoring drafts and pre-releases.
+fn newest_release(
+    fetcher: &ProtocFetcher,
+    spec: &str,
+    matches: impl Fn(&str, &[u32]) -> bool,
+) -> anyhow::Result<String> {
+    let api_base_url = forge::api_base_url(fetcher)?;
+
+    // Releases are listed newest first, so the first page with a match has the newest one.
+    for page in 1..=MAX_PAGES {
+        let url = format!("{api_base_url}/releases?per_page=100&page={page}");
+        let releases = get_json(fetcher, &url)?;
+        let Some(releases) = releases.as_array() else {
+            bail!("Unexpected response from {url}: not a list of releases");
+        };
+        if releases.is_empty() {
+            break;
+        }
+        let newest = releases
+            .iter()
+            .filter(|release| !is_true(&release["draft"]) && !is_true(&release["prerelease"]))
+            .filter_map(|release| release["tag_name"].as_str()?.strip_prefix('v'))
+            .filter_map(|version| Some((parse_components(version)?, version)))
+            .filter(|(components, version)| matches(version, components))
+            .max_by(|(a, _), (b, _)| a.cmp(b));
+        if let Some((_, version)) = newest {
+            logging::info!("Resolved protoc version {spec:?} to {version}.");
+            return Ok(version.to_string());
+        }
+    }
+    bail!("No protoc release matches version {spec:?}")
+}
+
+fn get_json(fetcher: &ProtocFetcher, url: &str) -> anyhow::Result<Value> {
+    let body = api_cache::get(fetcher, url)?;
+    serde_json::from_str(&body).with_context(|| format!("Invalid JSON from {url}"))
+}
+
+fn is_true(value: &Value) -> bool {
+    value.as_bool().unwrap_or(false)
+}
+
+/// Splits a release candidate version into the release and the number of the candidate, e.g.
+/// "32.0-rc1" into ("32.0", 1). The spelling of the release assets, "32.0-rc-1", is accepted too.
+pub(crate) fn release_candidate(version: &str) -> Option<(&str, u32)> {
+    let (release, rc) = version.split_once("-rc")?;
+    let rc = rc.strip_prefix(['-', '.']).unwrap_or(rc).parse().ok()?;
+    Some((release, rc))
+}
+
+/// Returns `version` as spelled in its release tag (minus the "v"), e.g. "32.0-rc1".
+pub(crate) fn tag_version(version: &str) -> String {
+    match release_candidate(version) {
+        Some((release, rc)) => format!("{release}-rc{rc}"),
+        None => version.to_string(),
+    }
+}
+
+/// Returns `version` as spelled in the names of its release assets, which differs from the tag
+/// for release candidates: `v32.0-rc1` has `protoc-32.0-rc-1-linux-x86_64.zip`.
+pub(crate) fn asset_version(version: &str) -> String {
+    match release_candidate(version) {
+        Some((release, rc)) => format!("{release}-rc-{rc}"),
+        None => version.to_string(),
+    }
+}
+
+/// Parses a version made up of dot-separated numbers only, e.g. "31.1" but not "31.0-rc1".
+pub(crate) fn parse_components(version: &str) -> Option<Vec<u32>> {
+    version
+        .split('.')
+        .map(|component| component.parse().ok())
+        .collect()
+}
@@ -0,0 +1,163 @@
+//! Downloading the release archives of every platform at once, e.g. to pre-populate a mirror or
+//! Docker images.
+
+use crate::digest;
+use crate::error::Error;
+use crate::fetcher::ProtocFetcher;
+use crate::install;
+use crate::logging;
+use crate::platform::Platform;
+use crate::resolve;
+use anyhow::Context;
+use serde_json::json;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The manifest written next to the downloaded archives.
+const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// A release archive downloaded by [`ProtocFetcher::fetch_all_platforms`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlatformAsset {
+    /// The platform of the release.
+    pub platform: Platform,
+    /// Where the archive was downloaded to.
+    pub path: PathBuf,
+    /// The URL the archive was downloaded from, or `None` if it was already present.
+    pub url: Option<String>,
+    /// The hex-encoded SHA-256 digest of the archive.
+    pub sha256: String,
+    /// The size of the archive in bytes.
+    pub size: u64,
+}
+
+impl ProtocFetcher {
+    /// Downloads the release archive of every [`Platform`] for the fetcher's version into
+    /// `out_dir`, without installing any of them, e.g. for a release engineer to pre-populate an
+    /// internal mirror or Docker images. Returns the archives, in the order of [`Platform::ALL`].
+    ///
+    /// The archives are laid out as on GitHub's release downloads, as
+    /// `<out_dir>/v<version>/<asset>`, so that serving `out_dir` makes a mirror for
+    /// [`download_base_url`](Self::download_base_url). A `manifest.json` next to them lists each
+    /// archive's platform, file name, source URL, SHA-256 digest and size.
+    ///
+    /// Platforms the release has no archive for (older releases weren't built for some) are
+    /// skipped. Archives already in `out_dir` aren't downloaded again unless
+    /// [`reuse_cache`](Self::reuse_cache) is off. A [`sha256`](Self::sha256) digest pins the
+    /// archive of one platform only, so it is ignored; with
+    /// [`strict_checksums`](Self::strict_checksums), each archive must match its built-in digest.
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use protoc_fetcher::ProtocFetcher;
+    ///
+    /// for asset in ProtocFetcher::new("31.1").fetch_all_platforms("mirror")? {
+    ///     println!("{}: {:?} ({})", asset.platform, asset.path, asset.sha256);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn fetch_all_platforms(
+        &self,
+        out_dir: impl AsRef<Path>,
+    ) -> Result<Vec<PlatformAsset>, Error> {
+        let _verbosity = logging::scope(self);
+        let fetcher = self.with_env_overrides()?;
+        let version = resolve::tag_version(&fetcher.resolve_version()?);
+        let release_dir = out_dir.as_ref().join(format!("v{version}"));
+        fs::create_dir_all(&release_dir)
+            .with_context(|| format!("Failed to create {release_dir:?}"))?;
+
+        let mut assets = Vec::new();
+        for &platform in Platform::ALL {
+            let platform_fetcher = ProtocFetcher {
+                version: version.clone(),
+                expected_sha256: None,
+                ..fetcher.clone()
+            }
+            .platform(platform);
+            if let Some(asset) = fetch_asset(&platform_fetcher, platform, &release_dir)? {
+                assets.push(asset);
+            }
+        }
+
+        let manifest = json!({
+            "version": version,
+            "assets": assets
+                .iter()
+                .map(|asset| json!({
+                    "platform": asset.platform.as_str(),
+                    "asset": asset.path.file_name().unwrap_or_default().to_string_lossy(),
+                    "url": asset.url,
+                    "sha256": asset.sha256,
+                    "size": asset.size,
+                }))
+                .collect::<Vec<_>>(),
+        });
+        let manifest_path = release_dir.join(MANIFEST_FILE_NAME);
+        fs::write(
+            &manifest_path,
+            serde_json::to_string_pretty(&manifest).context("Failed to serialize the manifest")?
+                + "\n",
+        )
+        .with_context(|| format!("Failed to write {manifest_path:?}"))?;
+        logging::info!(
+            "Downloaded {} release archives of protoc {version} into {release_dir:?}.",
+            assets.len()
+        );
+        Ok(assets)
+    }
+}
+
+/// Downloads the release archive of `platform` into `release_dir`, unless it is already there.
+/// Returns `None` if the release has no archive for the platform.
+fn fetch_asset(
+    fetcher: &ProtocFetcher,
+    platform: Platform,
+    release_dir: &Path,
+) -> anyhow::Result<Option<PlatformAsset>> {
+    let release_name = install::get_protoc_release_name(fetcher)?;
+    let path = release_dir.join(install::release_asset_name(fetcher, &release_name));
+    let expected = install::expected_sha256(fetcher)?;
+
+    let url = if fetcher.reuse_cache && path.is_file() {
+        logging::info!("{path:?} is already present.");
+        None
+    } else {
+        match install::download_archive(fetcher, &path, &release_name, expected.as_deref()) {
+            Ok(url) => Some(url),
+            Err(err) if is_not_found(&err) => {
+                logging::info!("There is no {platform} release archive of {release_name}.");
+                return Ok(None);
+            }
+            Err(err) => return Err(err),
+        }
+    };
+
+    let sha256 = digest::sha256_file(&path, fetcher.buffer_size)?;
+    if let Some(expected) = expected {
+        if url.is_none() && !sha256.eq_ignore_ascii_case(&expected) {
+            return Err(Error::Verification {
+                path,
+                expected,
+                actual: sha256,
+            }
+            .into());
+        }
+    }
+    let size = fs::metadata(&path)?.len();
+    Ok(Some(PlatformAsset {
+        platform,
+        path,
+        url,
+        sha256,
+        size,
+    }))
+}
+
+fn is_not_found(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::HttpStatus { status: 404, .. })
+    )
+}
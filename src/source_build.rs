@@ -0,0 +1,242 @@
+//! Building protoc from the protobuf source release, for platforms that no official release runs
+//! on.
+
+use crate::digest;
+use crate::extract;
+use crate::fetcher::ProtocFetcher;
+use crate::install::{self, COMPLETE_MARKER};
+use crate::install_record::InstallRecord;
+use crate::logging;
+use crate::manifest::Manifest;
+use crate::platform::{self, Platform};
+use crate::post_install;
+use crate::progress::Progress;
+use crate::wkt::{self, WELL_KNOWN_TYPES};
+use anyhow::{bail, Context};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+
+/// Where the source release is extracted and built within the staging directory; both are
+/// deleted once protoc is installed.
+const SOURCE_DIR: &str = "protobuf-source";
+const BUILD_DIR: &str = "protobuf-build";
+
+/// How many lines of a failed build's output to include in the error.
+const OUTPUT_TAIL_LINES: usize = 30;
+
+/// Returns the platform that builds from source are installed as when no official release runs
+/// on the host, or `None` if one does (or building from source is off): the host's OS and
+/// architecture, e.g. `freebsd-x86_64`, with `-musl` for musl-based Linux, e.g.
+/// `linux-x86_64-musl`.
+pub(crate) fn host_platform(fetcher: &ProtocFetcher) -> Option<String> {
+    if !fetcher.build_from_source
+        || !fetcher.download_url_templates.is_empty()
+        || fetcher.archive.is_some()
+    {
+        return None;
+    }
+    let musl = platform::host_is_musl();
+    if !musl && platform::host_platform(fetcher.allow_emulation).is_ok() {
+        return None;
+    }
+    let (os, arch) = platform::host_os_arch();
+    Some(if musl {
+        format!("{os}-{arch}-musl")
+    } else {
+        format!("{os}-{arch}")
+    })
+}
+
+/// Returns whether `fetcher` builds protoc from source rather than downloading a release: with
+/// [`ProtocFetcher::build_from_source`], for a platform that has no official releases, unless
+/// a download URL template or vendored archive says where its builds come from.
+pub(crate) fn applies(fetcher: &ProtocFetcher) -> anyhow::Result<bool> {
+    Ok(fetcher.build_from_source
+        && fetcher.download_url_templates.is_empty()
+        && fetcher.archive.is_none()
+        && install::release_platform(fetcher)?
+            .parse::<Platform>()
+            .is_err())
+}
+
+/// Downloads the protobuf source release, builds protoc from it, and installs the binary and the
+/// well-known types into `staging_dir`, as [`install::ensure_protoc_installed`] would install a
+/// release.
+pub(crate) fn install(fetcher: &ProtocFetcher, staging_dir: &Path) -> anyhow::Result<()> {
+    let version = &fetcher.version;
+    let platform = install::release_platform(fetcher)?;
+    let source_name = install::source_release_name(fetcher);
+    let archive_path = staging_dir.join(format!("{source_name}.zip"));
+    logging::warn!(
+        "No official protoc release runs on {platform}; building protoc v{version} from source, \
+         which may take several minutes..."
+    );
+    let expected_sha256 = install::expected_sha256(fetcher)?;
+    let archive_url = install::download_archive(
+        fetcher,
+        &archive_path,
+        &source_name,
+        expected_sha256.as_deref(),
+    )?;
+
+    // The source archive wraps everything in a `protobuf-<version>/` directory, which is
+    // stripped automatically.
+    let source_dir = staging_dir.join(SOURCE_DIR);
+    let options = extract::ExtractOptions {
+        strip_components: None,
+        format: None,
+        ..fetcher.extract_options()
+    };
+    fetcher.report(Progress::Extracting {
+        archive: &archive_path,
+    });
+    extract::extract_archive(&archive_path, &source_dir, &options, |_| true)?;
+
+    let build_dir = staging_dir.join(BUILD_DIR);
+    let built = build(&source_dir, &build_dir)?;
+    let protoc_path = install::protoc_path(staging_dir, &platform);
+    fs::create_dir_all(protoc_path.parent().unwrap_or(staging_dir))?;
+    fs::copy(&built, &protoc_path)
+        .with_context(|| format!("Failed to copy {built:?} to {protoc_path:?}"))?;
+    extract::make_executable(&protoc_path)?;
+    post_install::run(fetcher, &platform, staging_dir, &protoc_path)?;
+    if let Err(err) = install::get_protoc_version(&protoc_path) {
+        bail!(install::broken_binary(protoc_path, &err));
+    }
+    install_well_known_types(&source_dir.join("src"), &staging_dir.join("include"))?;
+    logging::info!("Built protoc v{version} from source.");
+
+    let archive_sha256 = digest::sha256_file(&archive_path, fetcher.buffer_size)?;
+    fs::remove_dir_all(&source_dir)?;
+    fs::remove_dir_all(&build_dir)?;
+    fs::remove_file(&archive_path)?;
+    Manifest {
+        include_extracted: true,
+        source_extracted: false,
+        archive_sha256: Some(archive_sha256.clone()),
+        archive_url: Some(archive_url.clone()),
+        protoc_sha256: Some(digest::sha256_file(&protoc_path, fetcher.buffer_size)?),
+        extra_entries: Vec::new(),
+    }
+    .write(staging_dir)?;
+    InstallRecord::new(fetcher, Some(archive_url), &archive_sha256)?.write(staging_dir)?;
+    // Only now is the install complete.
+    fs::write(
+        staging_dir.join(COMPLETE_MARKER),
+        format!("{archive_sha256}\n"),
+    )?;
+    extract::normalize_tree(staging_dir)
+}
+
+/// Configures and builds the `protoc` target of the protobuf sources in `source_dir` with CMake,
+/// in `build_dir`, and returns the path to the binary.
+///
+/// CMake (`CMAKE` selects the binary) picks the C++ compiler as usual, e.g. from `CXX`. The build
+/// runs `NUM_JOBS` jobs in parallel if that is set, as it is in build scripts, and one per CPU
+/// otherwise.
+fn build(source_dir: &Path, build_dir: &Path) -> anyhow::Result<PathBuf> {
+    // Releases before 21.0 keep their CMake project in `cmake/`.
+    let project_dir = if source_dir.join("CMakeLists.txt").is_file() {
+        source_dir.to_path_buf()
+    } else {
+        source_dir.join("cmake")
+    };
+    let jobs = env::var("NUM_JOBS")
+        .ok()
+        .and_then(|jobs| jobs.parse().ok())
+        .or_else(|| thread::available_parallelism().ok().map(usize::from))
+        .unwrap_or(1);
+
+    logging::info!("Configuring the protobuf sources in {source_dir:?}...");
+    let mut configure = cmake();
+    configure
+        .arg("-S")
+        .arg(&project_dir)
+        .arg("-B")
+        .arg(build_dir)
+        .args([
+            "-DCMAKE_BUILD_TYPE=Release",
+            "-Dprotobuf_BUILD_TESTS=OFF",
+            "-Dprotobuf_BUILD_SHARED_LIBS=OFF",
+            "-Dprotobuf_BUILD_PROTOC_BINARIES=ON",
+            "-DABSL_PROPAGATE_CXX_STD=ON",
+        ]);
+    run(configure, "configure")?;
+
+    logging::info!("Building protoc with {jobs} jobs...");
+    let mut build = cmake();
+    build
+        .arg("--build")
+        .arg(build_dir)
+        .args(["--target", "protoc", "--config", "Release", "--parallel"])
+        .arg(jobs.to_string());
+    run(build, "build")?;
+
+    // Multi-config generators (e.g. Visual Studio) put the binary in a per-config directory.
+    let binary = if cfg!(windows) {
+        "protoc.exe"
+    } else {
+        "protoc"
+    };
+    [
+        build_dir.join(binary),
+        build_dir.join("Release").join(binary),
+    ]
+    .into_iter()
+    .find(|path| path.is_file())
+    .with_context(|| format!("The protobuf build in {build_dir:?} produced no {binary}"))
+}
+
+fn cmake() -> Command {
+    Command::new(env::var_os("CMAKE").unwrap_or_else(|| "cmake".into()))
+}
+
+/// Runs the CMake `step` (e.g. "build"), failing with the end of its output if it fails.
+fn run(mut command: Command, step: &str) -> anyhow::Result<()> {
+    let output = match command.output() {
+        Ok(output) => output,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => bail!(
+            "Building protoc from source needs CMake and a C++ compiler, but CMake isn't \
+             installed (or set CMAKE to its path)"
+        ),
+        Err(err) => return Err(err).context("Failed to run CMake"),
+    };
+    if output.status.success() {
+        return Ok(());
+    }
+    let mut log = String::from_utf8_lossy(&output.stdout).into_owned();
+    log.push_str(&String::from_utf8_lossy(&output.stderr));
+    let lines: Vec<&str> = log.lines().collect();
+    let tail = &lines[lines.len().saturating_sub(OUTPUT_TAIL_LINES)..];
+    bail!(
+        "The CMake {step} of protoc failed ({}):\n{}",
+        output.status,
+        tail.join("\n")
+    )
+}
+
+/// Copies the well-known types (and, where the release has them, the language feature protos
+/// such as `google/protobuf/cpp_features.proto`) from the source tree `src_dir` into
+/// `include_dir`, as laid out in the `include/` tree of a release.
+fn install_well_known_types(src_dir: &Path, include_dir: &Path) -> anyhow::Result<()> {
+    let mut protos: Vec<String> = WELL_KNOWN_TYPES.iter().map(|p| p.to_string()).collect();
+    if let Ok(entries) = fs::read_dir(src_dir.join("google/protobuf")) {
+        for entry in entries {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if name.ends_with("_features.proto") {
+                protos.push(format!("google/protobuf/{name}"));
+            }
+        }
+    }
+    for proto in &protos {
+        let from = src_dir.join(proto);
+        let to = include_dir.join(proto);
+        fs::create_dir_all(to.parent().unwrap_or(include_dir))?;
+        fs::copy(&from, &to).with_context(|| format!("Failed to copy {from:?} to {to:?}"))?;
+    }
+    wkt::verify_include_tree(include_dir)
+}
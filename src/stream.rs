@@ -0,0 +1,53 @@
+//! Copying between readers and writers with a bounded buffer.
+
+use std::fmt;
+use std::io::{self, ErrorKind, Read, Write};
+
+/// An error from [`copy`], telling apart failures to read from failures to write, e.g. a corrupt
+/// archive entry from a full disk.
+#[derive(Debug)]
+pub(crate) enum CopyError {
+    Read(io::Error),
+    Write(io::Error),
+}
+
+impl fmt::Display for CopyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CopyError::Read(err) => write!(f, "read failed: {err}"),
+            CopyError::Write(err) => write!(f, "write failed: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for CopyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CopyError::Read(err) | CopyError::Write(err) => Some(err),
+        }
+    }
+}
+
+/// Copies all of `reader` into `writer` through a single buffer of `buffer_size` bytes, returning
+/// the number of bytes copied. Unlike [`io::copy`], the amount of memory used is under the
+/// caller's control.
+pub(crate) fn copy(
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+    buffer_size: usize,
+) -> Result<u64, CopyError> {
+    let mut buf = vec![0; buffer_size];
+    let mut copied = 0;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(CopyError::Read(err)),
+        };
+        writer.write_all(&buf[..n]).map_err(CopyError::Write)?;
+        copied += n as u64;
+    }
+    writer.flush().map_err(CopyError::Write)?;
+    Ok(copied)
+}
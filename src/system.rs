@@ -0,0 +1,75 @@
+//! Reusing a protoc that is already installed on the system, e.g. by Nix or Bazel.
+
+use crate::fetcher::ProtocFetcher;
+use crate::install;
+use crate::logging;
+use crate::resolve;
+use crate::version_req::VersionReq;
+use std::env;
+use std::path::PathBuf;
+
+/// Looks for a protoc matching the version of `fetcher`: the one named by the `PROTOC` env var if
+/// set, and otherwise the first `protoc` on `PATH`. Returns its path and its concrete version, or
+/// `None` if there is no such protoc or it is another version.
+pub(crate) fn find(fetcher: &ProtocFetcher) -> Option<(PathBuf, String)> {
+    let candidate = match env::var_os("PROTOC") {
+        Some(protoc) if !protoc.is_empty() => PathBuf::from(protoc),
+        _ => find_on_path()?,
+    };
+    let output = match install::get_protoc_version(&candidate) {
+        Ok(output) => output,
+        Err(err) => {
+            logging::debug!("Not using system protoc {candidate:?}: {err:#}");
+            return None;
+        }
+    };
+    let version = reported_version(&output);
+    if !version_matches(&fetcher.version, &version) {
+        logging::info!(
+            "System protoc {candidate:?} is v{version}, not v{}; fetching instead.",
+            fetcher.version
+        );
+        return None;
+    }
+    logging::info!("Using system protoc {candidate:?} (v{version}).");
+    Some((candidate, version))
+}
+
+/// Returns the first `protoc` binary in the directories of the `PATH` env var.
+fn find_on_path() -> Option<PathBuf> {
+    let binary_name = format!("protoc{}", env::consts::EXE_SUFFIX);
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(&binary_name))
+        .find(|path| path.is_file())
+}
+
+/// Returns the version of the release that printed `output` for `protoc --version`, e.g. "21.2"
+/// for `libprotoc 3.21.2`.
+pub(crate) fn reported_version(output: &str) -> String {
+    normalize_version(output.trim().trim_start_matches("libprotoc").trim())
+}
+
+/// Maps the version reported by `protoc --version` to the version of the release: releases 21.x
+/// report themselves as 3.21.x.
+fn normalize_version(version: &str) -> String {
+    match version.strip_prefix("3.") {
+        Some(rest) if rest.split('.').next().is_some_and(|major| major == "21") => rest.to_string(),
+        _ => version.to_string(),
+    }
+}
+
+/// Returns whether the protoc `actual` version satisfies the `requested` one, which may be partial
+/// (e.g. "31" for any 31.x) or a [requirement](VersionReq) (e.g. ">=27, <32"). "latest" can't be
+/// told without asking GitHub, so it never matches.
+pub(crate) fn version_matches(requested: &str, actual: &str) -> bool {
+    if VersionReq::is_requirement(requested) {
+        return VersionReq::parse(requested).is_ok_and(|req| req.matches(actual));
+    }
+    let (Some(requested), Some(actual)) = (
+        resolve::parse_components(requested),
+        resolve::parse_components(actual),
+    ) else {
+        return false;
+    };
+    actual.starts_with(&requested)
+}
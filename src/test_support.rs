@@ -0,0 +1,24 @@
+//! Helpers for testing code that fetches protoc, without network access.
+//!
+//! Fetches can be pointed at a [`FixtureServer`] on localhost, which serves release downloads
+//! from a directory, or answered entirely in memory by a [`MockHttp`], which stands in for every
+//! request and records them. Either way they go through the same download, verification and
+//! install code as real ones, so build scripts built on this crate can be tested hermetically too.
+//!
+//! Only available with the `test-support` feature, which is meant for dev-dependencies:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! protoc-fetcher = { version = "0.1", features = ["test-support"] }
+//! ```
+
+pub mod fixtures;
+mod mock;
+mod release;
+mod sandbox;
+mod server;
+
+pub use self::mock::{MockHttp, RELEASE_DOWNLOADS_URL};
+pub use self::release::FakeRelease;
+pub use self::sandbox::Sandbox;
+pub use self::server::{Fault, FixtureServer};
@@ -0,0 +1,30 @@
+//! Canned fixtures for unit-testing code built around this crate.
+//!
+//! Everything here is generated deterministically, so the same version always yields the same
+//! archive, digest and manifest.
+
+use super::FakeRelease;
+use crate::digest;
+use crate::manifest::Manifest;
+use std::io;
+
+/// Returns a tiny release archive for `version`, as generated by [`FakeRelease`].
+pub fn release_archive(version: &str) -> io::Result<Vec<u8>> {
+    FakeRelease::new(version).to_bytes()
+}
+
+/// Returns the hex-encoded SHA-256 digest of [`release_archive`]`(version)`, as recorded in
+/// the manifest and completion marker of an install of it.
+pub fn release_archive_sha256(version: &str) -> io::Result<String> {
+    Ok(digest::sha256_bytes(&release_archive(version)?))
+}
+
+/// Returns the contents of the `manifest` file of a fresh install of
+/// [`release_archive`]`(version)`, i.e. before its `include/` tree has been extracted.
+pub fn manifest(version: &str) -> io::Result<String> {
+    let manifest = Manifest {
+        archive_sha256: Some(release_archive_sha256(version)?),
+        ..Manifest::default()
+    };
+    Ok(manifest.contents())
+}
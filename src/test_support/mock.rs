@@ -0,0 +1,225 @@
+//! An in-memory HTTP client that answers requests without any network or sockets.
+
+use super::server::{corrupt, fixture_path, Fault};
+use super::FakeRelease;
+use crate::transport::{HttpClient, HttpRequest, HttpResponse};
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, Cursor};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+
+/// Where official releases are downloaded from, and so where [`MockHttp::releases`] serves
+/// fixtures.
+pub const RELEASE_DOWNLOADS_URL: &str =
+    "https://github.com/protocolbuffers/protobuf/releases/download";
+
+/// An [`HttpClient`] that answers every request of a fetch from canned responses, so that tests
+/// of downloading, extraction and caching run without any network access, not even a localhost
+/// server. Requests for URLs it has no response for get a 404 rather than reaching the network.
+///
+/// Unlike a [`FixtureServer`](super::FixtureServer), it stands in for every URL the fetcher
+/// requests (release downloads, checksum assets and the GitHub API alike), so the fetcher needs
+/// no other configuration. Plug it in with [`ProtocFetcher::mock_http`]; clones share their
+/// responses and the requests they have seen.
+///
+/// ```no_run
+/// use protoc_fetcher::test_support::{FakeRelease, MockHttp, Sandbox};
+///
+/// let http = MockHttp::new().fake_release(&FakeRelease::new("21.2")).unwrap();
+/// let sandbox = Sandbox::new().unwrap();
+/// sandbox.fetcher("21.2").mock_http(&http).fetch().unwrap();
+/// // The second fetch reuses the install.
+/// sandbox.fetcher("21.2").mock_http(&http).fetch().unwrap();
+/// assert_eq!(http.requests().len(), 1);
+/// ```
+///
+/// [`ProtocFetcher::mock_http`]: crate::ProtocFetcher::mock_http
+#[derive(Debug, Clone, Default)]
+pub struct MockHttp {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    routes: Vec<Route>,
+    faults: VecDeque<Fault>,
+    requests: Vec<String>,
+}
+
+#[derive(Debug)]
+enum Route {
+    /// Answers requests for exactly this URL.
+    Exact {
+        url: String,
+        status: u16,
+        body: Vec<u8>,
+    },
+    /// Answers requests for URLs under `prefix` with the files under `root`.
+    Dir { prefix: String, root: PathBuf },
+}
+
+impl MockHttp {
+    /// Creates a client without any responses.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answers requests for `url` with a 200 and `body`.
+    pub fn respond(self, url: &str, body: impl Into<Vec<u8>>) -> Self {
+        self.route(Route::Exact {
+            url: url.to_string(),
+            status: 200,
+            body: body.into(),
+        })
+    }
+
+    /// Answers requests for `url` with `status` (e.g. 403 or 500) and an empty body.
+    pub fn status(self, url: &str, status: u16) -> Self {
+        self.route(Route::Exact {
+            url: url.to_string(),
+            status,
+            body: Vec::new(),
+        })
+    }
+
+    /// Answers requests for URLs under `prefix` with the files under `root`, e.g. requests for
+    /// `<prefix>/v21.2/protoc-21.2-linux-x86_64.zip` with
+    /// `<root>/v21.2/protoc-21.2-linux-x86_64.zip`.
+    pub fn serve_dir(self, prefix: &str, root: impl Into<PathBuf>) -> Self {
+        self.route(Route::Dir {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            root: root.into(),
+        })
+    }
+
+    /// Serves the files in the fixture directory `root`, laid out like the release downloads as
+    /// for a [`FixtureServer`](super::FixtureServer), as the official releases.
+    pub fn releases(self, root: impl Into<PathBuf>) -> Self {
+        self.serve_dir(RELEASE_DOWNLOADS_URL, root)
+    }
+
+    /// Serves the archive of `release` as the official release for the current platform.
+    pub fn fake_release(self, release: &FakeRelease) -> io::Result<Self> {
+        let url = format!("{RELEASE_DOWNLOADS_URL}/{}", release.url_path()?);
+        Ok(self.respond(&url, release.to_bytes()?))
+    }
+
+    /// Makes the next request that doesn't have a fault yet fail with `fault`, as
+    /// [`FixtureServer::inject`](super::FixtureServer::inject) does.
+    pub fn inject(&self, fault: Fault) {
+        lock(&self.state).faults.push_back(fault);
+    }
+
+    /// Returns the URLs requested so far, in order, e.g. to check that a fetch reused an install
+    /// rather than downloading it again.
+    pub fn requests(&self) -> Vec<String> {
+        lock(&self.state).requests.clone()
+    }
+
+    fn route(self, route: Route) -> Self {
+        lock(&self.state).routes.push(route);
+        self
+    }
+}
+
+impl State {
+    /// Returns the status and body to answer a request for `url` with; later routes take
+    /// precedence over earlier ones.
+    fn respond(&self, url: &str) -> (u16, Vec<u8>) {
+        for route in self.routes.iter().rev() {
+            match route {
+                Route::Exact {
+                    url: exact,
+                    status,
+                    body,
+                } if exact == url => {
+                    return (*status, body.clone());
+                }
+                Route::Dir { prefix, root } => {
+                    let Some(target) = url.strip_prefix(prefix.as_str()) else {
+                        continue;
+                    };
+                    if !target.starts_with('/') {
+                        continue;
+                    }
+                    if let Some(body) = fixture_path(root, target).and_then(|p| fs::read(p).ok()) {
+                        return (200, body);
+                    }
+                }
+                Route::Exact { .. } => {}
+            }
+        }
+        (
+            404,
+            format!("MockHttp has no response for {url}").into_bytes(),
+        )
+    }
+}
+
+impl HttpClient for MockHttp {
+    fn get(
+        &self,
+        request: &HttpRequest,
+    ) -> Result<HttpResponse, Box<dyn std::error::Error + Send + Sync>> {
+        let ((status, mut body), fault) = {
+            let mut state = lock(&self.state);
+            state.requests.push(request.url.clone());
+            (state.respond(&request.url), state.faults.pop_front())
+        };
+        match fault {
+            Some(Fault::Status(status)) => return Ok(response(status, Vec::new(), None)),
+            Some(Fault::Stall(duration)) if duration >= request.timeout => {
+                thread::sleep(request.timeout);
+                return Err(
+                    io::Error::new(io::ErrorKind::TimedOut, "the mock request timed out").into(),
+                );
+            }
+            Some(Fault::Stall(duration)) => thread::sleep(duration),
+            _ => {}
+        }
+
+        // Resume downloads as a server would.
+        let mut status = status;
+        if status == 200 {
+            let offset = request
+                .headers
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case("Range"))
+                .and_then(|(_, range)| {
+                    range
+                        .strip_prefix("bytes=")?
+                        .strip_suffix('-')?
+                        .parse()
+                        .ok()
+                })
+                .filter(|&offset: &usize| offset < body.len());
+            if let Some(offset) = offset {
+                status = 206;
+                body.drain(..offset);
+            }
+        }
+        let len = body.len() as u64;
+        match fault {
+            Some(Fault::Truncate(sent)) => body.truncate(sent.try_into().unwrap_or(usize::MAX)),
+            Some(Fault::Corrupt) => corrupt(&mut body),
+            _ => {}
+        }
+        Ok(response(status, body, Some(len)))
+    }
+}
+
+fn response(status: u16, body: Vec<u8>, content_length: Option<u64>) -> HttpResponse {
+    HttpResponse {
+        status,
+        content_length: content_length.or(Some(body.len() as u64)),
+        etag: None,
+        body: Box::new(Cursor::new(body)),
+    }
+}
+
+/// Locks `state`, even if a thread panicked while holding the lock; it stays consistent.
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    state.lock().unwrap_or_else(PoisonError::into_inner)
+}
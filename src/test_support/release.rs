@@ -0,0 +1,106 @@
+//! Generating minimal release archives.
+
+use crate::fetcher::ProtocFetcher;
+use crate::install;
+use crate::wkt::WELL_KNOWN_TYPES;
+use std::fs;
+use std::io::{self, Cursor, Write};
+use std::path::{Path, PathBuf};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// A generator for tiny but valid protoc release archives, for testing extraction, verification
+/// and reuse of installs without multi-megabyte fixtures.
+///
+/// The archive holds a stub `bin/protoc` that prints `libprotoc <version>` for `--version` and
+/// does nothing else, plus an `include/` tree with a stub of each of the well-known types. The
+/// stub protoc is a shell script, so installs of the archive only verify on Unix-like systems.
+///
+/// ```no_run
+/// use protoc_fetcher::test_support::{FakeRelease, FixtureServer, Sandbox};
+///
+/// let fixtures = Sandbox::new().unwrap();
+/// FakeRelease::new("21.2").write_to(fixtures.path()).unwrap();
+/// let server = FixtureServer::start(fixtures.path()).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct FakeRelease {
+    version: String,
+    files: Vec<(String, Vec<u8>)>,
+}
+
+impl FakeRelease {
+    /// Creates a generator for a fake release of `version`.
+    pub fn new(version: &str) -> Self {
+        FakeRelease {
+            version: version.to_string(),
+            files: Vec::new(),
+        }
+    }
+
+    /// Adds a file to the archive, e.g. `readme.txt`, or replaces one of the generated files.
+    pub fn file(mut self, name: &str, contents: impl Into<Vec<u8>>) -> Self {
+        self.files.retain(|(existing, _)| existing != name);
+        self.files.push((name.to_string(), contents.into()));
+        self
+    }
+
+    /// Writes the archive into the fixture directory `root`, where a
+    /// [`FixtureServer`](super::FixtureServer) serving `root` serves it as the release for the
+    /// current platform. Returns the path of the archive.
+    pub fn write_to(&self, root: impl AsRef<Path>) -> io::Result<PathBuf> {
+        let path = root.as_ref().join(self.url_path()?);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        self.write_zip(&path)?;
+        Ok(path)
+    }
+
+    /// Returns where the archive goes below the release download URL, e.g.
+    /// `v21.2/protoc-21.2-linux-x86_64.zip`.
+    pub(super) fn url_path(&self) -> io::Result<String> {
+        let fetcher = ProtocFetcher::new(&self.version);
+        let release_name = install::get_protoc_release_name(&fetcher).map_err(io::Error::other)?;
+        Ok(format!("v{}/{release_name}.zip", self.version))
+    }
+
+    /// Writes the archive to `path`.
+    pub fn write_zip(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_bytes()?)
+    }
+
+    /// Returns the archive. It is the same for the same inputs, so its digest can be relied on.
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let protoc = format!(
+            "#!/bin/sh\necho \"libprotoc {}\"\n",
+            self.version.trim_start_matches('v')
+        );
+        let mut entries: Vec<(String, Vec<u8>)> = vec![("bin/protoc".to_string(), protoc.into())];
+        for proto in WELL_KNOWN_TYPES {
+            let contents = "syntax = \"proto3\";\n\npackage google.protobuf;\n";
+            entries.push((format!("include/{proto}"), contents.into()));
+        }
+        for (name, contents) in &self.files {
+            entries.retain(|(existing, _)| existing != name);
+            entries.push((name.clone(), contents.clone()));
+        }
+
+        // Without zip's `time` feature, entries get a fixed timestamp, which keeps the archive
+        // reproducible.
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, contents) in entries {
+            let mode = if name.starts_with("bin/") {
+                0o755
+            } else {
+                0o644
+            };
+            let options = SimpleFileOptions::default()
+                .compression_method(CompressionMethod::Stored)
+                .unix_permissions(mode);
+            zip.start_file(name, options)?;
+            zip.write_all(&contents)?;
+        }
+        Ok(zip.finish()?.into_inner())
+    }
+}
@@ -0,0 +1,61 @@
+//! Isolated install directories for tests.
+
+use crate::ProtocFetcher;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{env, fs, io, process};
+
+/// A fresh, empty directory for installs to go into, which is deleted (with everything in it)
+/// when the sandbox is dropped.
+///
+/// Each sandbox is separate from every other one and from any real install, so tests using them
+/// are hermetic and can run in parallel.
+///
+/// ```no_run
+/// use protoc_fetcher::test_support::{FixtureServer, Sandbox};
+///
+/// let server = FixtureServer::start("tests/fixtures/releases").unwrap();
+/// let sandbox = Sandbox::new().unwrap();
+/// let protoc = sandbox.fetcher("21.2").fixture_server(&server).fetch().unwrap();
+/// assert!(protoc.path().starts_with(sandbox.path()));
+/// ```
+#[derive(Debug)]
+pub struct Sandbox {
+    root: PathBuf,
+}
+
+impl Sandbox {
+    /// Creates a sandbox in the system's temporary directory.
+    pub fn new() -> io::Result<Self> {
+        static SANDBOXES: AtomicUsize = AtomicUsize::new(0);
+        loop {
+            let sandbox = SANDBOXES.fetch_add(1, Ordering::Relaxed);
+            let root = env::temp_dir().join(format!(
+                "protoc-fetcher-sandbox-{}-{sandbox}",
+                process::id()
+            ));
+            // Left over from an earlier process with the same id? Then try the next name.
+            match fs::create_dir(&root) {
+                Ok(()) => return Ok(Sandbox { root }),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Returns the sandbox directory.
+    pub fn path(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns a fetcher for `version` that installs into the sandbox.
+    pub fn fetcher(&self, version: &str) -> ProtocFetcher {
+        ProtocFetcher::new(version).install_dir(&self.root)
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
@@ -0,0 +1,208 @@
+//! A minimal HTTP server for serving release archives from a local directory.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// An HTTP server on localhost that serves the files in a fixture directory, laid out like the
+/// release downloads, e.g. `<root>/v21.2/protoc-21.2-linux-x86_64.zip`.
+///
+/// Point a fetcher at it with [`ProtocFetcher::fixture_server`]; every fetch then goes through the
+/// same download code as a real one, without any network. The server stops when dropped.
+///
+/// ```no_run
+/// use protoc_fetcher::test_support::FixtureServer;
+/// use protoc_fetcher::ProtocFetcher;
+///
+/// let server = FixtureServer::start("tests/fixtures/releases").unwrap();
+/// let protoc = ProtocFetcher::new("21.2")
+///     .install_dir("target/test-installs")
+///     .fixture_server(&server)
+///     .fetch()
+///     .unwrap();
+/// ```
+///
+/// [`ProtocFetcher::fixture_server`]: crate::ProtocFetcher::fixture_server
+#[derive(Debug)]
+pub struct FixtureServer {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    faults: Arc<Mutex<VecDeque<Fault>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// A failure for a [`FixtureServer`] or [`MockHttp`](super::MockHttp) to simulate, for testing
+/// how downloads cope with flaky servers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// Respond with the given status code (e.g. 429 or 503) and an empty body.
+    Status(u16),
+    /// Wait for the given time before responding, e.g. to trip a timeout.
+    Stall(Duration),
+    /// Announce the full body, but close the connection after sending the given number of bytes.
+    Truncate(u64),
+    /// Send the full body, with some of its bytes flipped, as if the archive were corrupt.
+    Corrupt,
+}
+
+impl FixtureServer {
+    /// Starts serving the files under `root` on a free localhost port.
+    pub fn start(root: impl Into<PathBuf>) -> io::Result<Self> {
+        let root = root.into();
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let faults = Arc::new(Mutex::new(VecDeque::new()));
+        let thread = {
+            let stop = Arc::clone(&stop);
+            let faults = Arc::clone(&faults);
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let Ok(stream) = stream else {
+                        continue;
+                    };
+                    let root = root.clone();
+                    let fault = lock(&faults).pop_front();
+                    thread::spawn(move || {
+                        // A client that goes away mid-response is none of the server's business.
+                        let _ = serve(&root, stream, fault);
+                    });
+                }
+            })
+        };
+        Ok(FixtureServer {
+            addr,
+            stop,
+            faults,
+            thread: Some(thread),
+        })
+    }
+
+    /// Makes the next request that doesn't have a fault yet fail with `fault`. Each injected fault
+    /// applies to exactly one request, in the order injected; requests after that are served
+    /// normally again.
+    ///
+    /// ```no_run
+    /// use protoc_fetcher::test_support::{Fault, FixtureServer};
+    ///
+    /// let server = FixtureServer::start("tests/fixtures/releases").unwrap();
+    /// // The first download is rate-limited, the second one is cut off.
+    /// server.inject(Fault::Status(429));
+    /// server.inject(Fault::Truncate(1024));
+    /// ```
+    pub fn inject(&self, fault: Fault) {
+        lock(&self.faults).push_back(fault);
+    }
+
+    /// Returns the base URL of the server, e.g. `http://127.0.0.1:12345`, which stands in for the
+    /// release download URL.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+}
+
+impl Drop for FixtureServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // Wake up the accept loop so that it sees the flag.
+        let _ = TcpStream::connect(self.addr);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Locks `faults`, even if a thread panicked while holding the lock; the queue stays consistent.
+fn lock(faults: &Mutex<VecDeque<Fault>>) -> MutexGuard<'_, VecDeque<Fault>> {
+    faults.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Answers a single request on `stream`, simulating `fault` if there is one.
+fn serve(root: &Path, stream: TcpStream, fault: Option<Fault>) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Skip the headers; nothing in them matters here.
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+
+    let mut stream = stream;
+    match &fault {
+        Some(Fault::Status(status)) => {
+            return respond(&mut stream, &format!("{status} Injected Fault"), None, None);
+        }
+        Some(Fault::Stall(duration)) => thread::sleep(*duration),
+        _ => {}
+    }
+    let mut parts = request_line.split_whitespace();
+    let (Some("GET"), Some(target)) = (parts.next(), parts.next()) else {
+        return respond(&mut stream, "405 Method Not Allowed", None, None);
+    };
+    match fixture_path(root, target).and_then(|path| File::open(path).ok()) {
+        Some(file) => respond(&mut stream, "200 OK", Some(file), fault),
+        None => respond(&mut stream, "404 Not Found", None, None),
+    }
+}
+
+/// Maps a request target to a file under `root`, refusing anything that would escape it.
+pub(super) fn fixture_path(root: &Path, target: &str) -> Option<PathBuf> {
+    let path = target.split(['?', '#']).next()?.trim_start_matches('/');
+    let path = Path::new(path);
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_)))
+        .then(|| root.join(path))
+}
+
+fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    body: Option<File>,
+    fault: Option<Fault>,
+) -> io::Result<()> {
+    let len = match &body {
+        Some(file) => file.metadata()?.len(),
+        None => 0,
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Length: {len}\r\nConnection: close\r\n\r\n"
+    )?;
+    if let Some(file) = body {
+        match fault {
+            Some(Fault::Truncate(sent)) => {
+                io::copy(&mut file.take(sent), stream)?;
+            }
+            Some(Fault::Corrupt) => {
+                let mut bytes = Vec::new();
+                BufReader::new(file).read_to_end(&mut bytes)?;
+                corrupt(&mut bytes);
+                stream.write_all(&bytes)?;
+            }
+            _ => {
+                let mut file = file;
+                io::copy(&mut file, stream)?;
+            }
+        }
+    }
+    stream.flush()
+}
+
+/// Flips a byte every 64 bytes, which no archive format survives unnoticed.
+pub(super) fn corrupt(bytes: &mut [u8]) {
+    for byte in bytes.iter_mut().step_by(64) {
+        *byte ^= 0xff;
+    }
+}
@@ -0,0 +1,201 @@
+//! Running protoc together with code generator plugins.
+
+use crate::compile::{Outputs, ProtocOutput};
+use crate::error::Error;
+use crate::plugin;
+use crate::protoc::Protoc;
+use anyhow::anyhow;
+use std::env;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The code generators built into protoc, which need no plugin.
+const BUILTIN_GENERATORS: &[&str] = &[
+    "cpp",
+    "csharp",
+    "java",
+    "kotlin",
+    "objc",
+    "php",
+    "pyi",
+    "python",
+    "ruby",
+    "rust",
+    "upb",
+    "upbdefs",
+    "upb_minitable",
+];
+
+/// A protoc install together with the code generator plugins to run it with.
+///
+/// Every plugin is passed to protoc explicitly (`--plugin=protoc-gen-<name>=<path>`), so protoc
+/// doesn't pick up whatever happens to be on `PATH`, and [`compile`](Self::compile) checks that
+/// each requested output has a generator before running protoc.
+///
+/// ```no_run
+/// # fn main() -> anyhow::Result<()> {
+/// use protoc_fetcher::{Outputs, ProtocFetcher, Toolchain};
+///
+/// let toolchain = Toolchain::new(ProtocFetcher::new("21.2").fetch()?)
+///     .plugin("grpc-java", "tools/protoc-gen-grpc-java");
+/// toolchain.compile(
+///     &["protos/my_service.proto"],
+///     &["protos"],
+///     &Outputs::new()
+///         .java_out("gen")
+///         .plugin_out("grpc-java", "gen", ""),
+/// )?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Toolchain {
+    protoc: Protoc,
+    plugins: Vec<Plugin>,
+}
+
+#[derive(Debug, Clone)]
+struct Plugin {
+    name: String,
+    path: PathBuf,
+}
+
+impl Toolchain {
+    /// Creates a toolchain that runs `protoc`, with no plugins yet.
+    pub fn new(protoc: Protoc) -> Self {
+        Toolchain {
+            protoc,
+            plugins: Vec::new(),
+        }
+    }
+
+    /// Adds the plugin binary at `path` as the generator for `--<name>_out`, i.e. as
+    /// `protoc-gen-<name>`. Replaces any plugin previously added under the same name.
+    pub fn plugin(mut self, name: &str, path: impl Into<PathBuf>) -> Self {
+        self.plugins.retain(|plugin| plugin.name != name);
+        self.plugins.push(Plugin {
+            name: name.to_string(),
+            path: path.into(),
+        });
+        self
+    }
+
+    /// Fetches the plugin `name` at `version` (checking its download against `sha256`, if given),
+    /// as [`fetch_plugin`](crate::fetch_plugin) does, into the install directory of the
+    /// toolchain's protoc, and adds it as the generator it provides, e.g. `grpc-web` for
+    /// `protoc-gen-grpc-web`. This way the plugins are pinned in one place together with protoc:
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use protoc_fetcher::{Outputs, ProtocFetcher, Toolchain};
+    ///
+    /// let toolchain = Toolchain::new(ProtocFetcher::new("31.1").fetch()?)
+    ///     .fetch_plugin("protoc-gen-js", "3.21.4", None)?
+    ///     .fetch_plugin("protoc-gen-grpc-web", "1.5.0", None)?
+    ///     .fetch_plugin("protoc-gen-ts", "0.8.7", None)?;
+    /// toolchain.compile(
+    ///     &["protos/my_service.proto"],
+    ///     &["protos"],
+    ///     &Outputs::new()
+    ///         .plugin_out("js", "web/gen", "import_style=commonjs")
+    ///         .plugin_out("grpc-web", "web/gen", "import_style=typescript,mode=grpcwebtext")
+    ///         .plugin_out("ts", "web/gen", ""),
+    /// )?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn fetch_plugin(
+        self,
+        name: &str,
+        version: &str,
+        sha256: Option<&str>,
+    ) -> Result<Self, Error> {
+        let path = plugin::fetch(&self.protoc.fetcher, name, version, sha256)?;
+        let generator = name.strip_prefix("protoc-gen-").unwrap_or(name).to_string();
+        Ok(self.plugin(&generator, path))
+    }
+
+    /// Returns the protoc install.
+    pub fn protoc(&self) -> &Protoc {
+        &self.protoc
+    }
+
+    /// Returns the path to the plugin added under `name`, if any.
+    pub fn plugin_path(&self, name: &str) -> Option<&Path> {
+        self.plugins
+            .iter()
+            .find(|plugin| plugin.name == name)
+            .map(|plugin| plugin.path.as_path())
+    }
+
+    /// Returns the plugins, as the generator names they were added under (e.g. `grpc-web`) and
+    /// the paths to their binaries.
+    pub fn plugins(&self) -> impl Iterator<Item = (&str, &Path)> {
+        self.plugins
+            .iter()
+            .map(|plugin| (plugin.name.as_str(), plugin.path.as_path()))
+    }
+
+    /// Returns a `--plugin=protoc-gen-<name>=<path>` argument for each of the plugins, for
+    /// running protoc without [`command`](Self::command), e.g. from another build tool.
+    pub fn plugin_args(&self) -> Vec<OsString> {
+        self.plugins
+            .iter()
+            .map(|plugin| {
+                let mut arg = OsString::from(format!("--plugin=protoc-gen-{}=", plugin.name));
+                arg.push(&plugin.path);
+                arg
+            })
+            .collect()
+    }
+
+    /// Like [`Protoc::command`], but with a `--plugin` argument for each of the plugins.
+    pub fn command(&self) -> Result<Command, Error> {
+        let mut command = self.protoc.command()?;
+        command.args(self.plugin_args());
+        Ok(command)
+    }
+
+    /// Like [`Protoc::compile`], but with the plugins available. Fails without running protoc if
+    /// one of the `outputs` has no generator: neither built into protoc, nor added as a plugin,
+    /// nor found on `PATH` as `protoc-gen-<language>`.
+    pub fn compile(
+        &self,
+        protos: &[impl AsRef<Path>],
+        includes: &[impl AsRef<Path>],
+        outputs: &Outputs,
+    ) -> Result<ProtocOutput, Error> {
+        let missing: Vec<&str> = outputs
+            .languages()
+            .filter(|language| !self.has_generator(language))
+            .collect();
+        if !missing.is_empty() {
+            return Err(anyhow!(
+                "No protoc plugin for the requested outputs: {} (add them with Toolchain::plugin)",
+                missing
+                    .iter()
+                    .map(|language| format!("--{language}_out"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+            .into());
+        }
+        Ok(self
+            .protoc
+            .compile_with(self.command()?, protos, includes, outputs)?)
+    }
+
+    fn has_generator(&self, language: &str) -> bool {
+        BUILTIN_GENERATORS.contains(&language)
+            || self.plugin_path(language).is_some()
+            || plugin_on_path(language)
+    }
+}
+
+/// Checks whether protoc would find a `protoc-gen-<name>` plugin on `PATH`.
+fn plugin_on_path(name: &str) -> bool {
+    let file_name = format!("protoc-gen-{name}{}", env::consts::EXE_SUFFIX);
+    env::var_os("PATH")
+        .is_some_and(|path| env::split_paths(&path).any(|dir| dir.join(&file_name).is_file()))
+}
@@ -0,0 +1,138 @@
+//! Plugging in an HTTP client other than the built-in one.
+
+use std::fmt;
+use std::io::Read;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Sends the HTTP requests of a fetch, for environments whose networking policies the built-in
+/// client can't honor, e.g. custom TLS roots, mTLS, or proxies that need extra headers. Set it
+/// with [`ProtocFetcher::http_client`](crate::ProtocFetcher::http_client).
+///
+/// It is implemented for `reqwest::blocking::Client` (with the `native-tls` or `rustls-tls`
+/// feature) and `ureq::Agent` (with the `ureq` feature), so a client configured with either's
+/// builder can be passed as is:
+///
+/// ```no_run
+/// # #[cfg(feature = "reqwest")]
+/// # fn main() -> anyhow::Result<()> {
+/// use protoc_fetcher::ProtocFetcher;
+///
+/// let certificate = reqwest::Certificate::from_pem(&std::fs::read("corp-root-ca.pem")?)?;
+/// let client = reqwest::blocking::Client::builder()
+///     .add_root_certificate(certificate)
+///     .build()?;
+/// let protoc = ProtocFetcher::new("21.2").http_client(client).fetch()?;
+/// # Ok(())
+/// # }
+/// # #[cfg(not(feature = "reqwest"))]
+/// # fn main() {}
+/// ```
+///
+/// Implementations only send the request and hand back the response, whatever its status;
+/// retries, status handling and verification stay with the fetcher. Requests are only ever GETs.
+pub trait HttpClient: Send + Sync {
+    /// Sends a GET request. Fails only if no response was received, e.g. because the connection
+    /// failed or timed out; such failures are retried like those of the built-in client.
+    fn get(
+        &self,
+        request: &HttpRequest,
+    ) -> Result<HttpResponse, Box<dyn std::error::Error + Send + Sync>>;
+}
+
+/// A request to be sent by an [`HttpClient`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct HttpRequest {
+    pub url: String,
+    /// The headers to send, e.g. `Authorization` for GitHub and `Range` to resume a download.
+    pub headers: Vec<(String, String)>,
+    /// How long the whole request may take, including reading the body; see
+    /// [`ProtocFetcher::download_timeout`](crate::ProtocFetcher::download_timeout).
+    pub timeout: Duration,
+}
+
+/// A response received by an [`HttpClient`].
+pub struct HttpResponse {
+    /// The status code, e.g. 200.
+    pub status: u16,
+    /// The `Content-Length`, if the server sent one.
+    pub content_length: Option<u64>,
+    /// The `ETag`, if the server sent one. The fetcher uses it to revalidate the GitHub API
+    /// responses it caches, so a client that leaves it `None` just makes them expire sooner.
+    pub etag: Option<String>,
+    /// The body, to be streamed.
+    pub body: Box<dyn Read + Send>,
+}
+
+impl fmt::Debug for HttpResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HttpResponse")
+            .field("status", &self.status)
+            .field("content_length", &self.content_length)
+            .field("etag", &self.etag)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "reqwest")]
+impl HttpClient for reqwest::blocking::Client {
+    fn get(
+        &self,
+        request: &HttpRequest,
+    ) -> Result<HttpResponse, Box<dyn std::error::Error + Send + Sync>> {
+        let mut builder =
+            reqwest::blocking::Client::get(self, &request.url).timeout(request.timeout);
+        for (name, value) in &request.headers {
+            builder = builder.header(name, value);
+        }
+        let response = builder.send()?;
+        Ok(HttpResponse {
+            status: response.status().as_u16(),
+            content_length: response.content_length(),
+            etag: response
+                .headers()
+                .get(reqwest::header::ETAG)
+                .and_then(|etag| etag.to_str().ok())
+                .map(str::to_string),
+            body: Box::new(response),
+        })
+    }
+}
+
+#[cfg(feature = "ureq")]
+impl HttpClient for ureq::Agent {
+    fn get(
+        &self,
+        request: &HttpRequest,
+    ) -> Result<HttpResponse, Box<dyn std::error::Error + Send + Sync>> {
+        let mut builder = ureq::Agent::get(self, &request.url).timeout(request.timeout);
+        for (name, value) in &request.headers {
+            builder = builder.set(name, value);
+        }
+        let response = match builder.call() {
+            Ok(response) => response,
+            // Statuses are for the fetcher to handle.
+            Err(ureq::Error::Status(_, response)) => response,
+            Err(err) => return Err(err.into()),
+        };
+        Ok(HttpResponse {
+            status: response.status(),
+            content_length: response
+                .header("Content-Length")
+                .and_then(|len| len.parse().ok()),
+            etag: response.header("ETag").map(str::to_string),
+            body: Box::new(response.into_reader()),
+        })
+    }
+}
+
+/// The client set with [`ProtocFetcher::http_client`](crate::ProtocFetcher::http_client).
+#[derive(Clone)]
+pub(crate) struct SharedHttpClient(pub Arc<dyn HttpClient>);
+
+impl fmt::Debug for SharedHttpClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SharedHttpClient")
+    }
+}
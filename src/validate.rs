@@ -0,0 +1,247 @@
+//! Offline validation of install directories, e.g. caches restored in CI.
+
+use crate::digest;
+use crate::error::Error;
+use crate::extract::ArchiveFormat;
+use crate::install::{self, COMPLETE_MARKER, CURRENT_LINK, INSTALLS_DIR, STAGING_SUFFIX};
+use crate::installed;
+use crate::manifest::Manifest;
+use crate::plugin::PLUGINS_DIR;
+use crate::wkt::{self, WKT_DIR};
+use anyhow::anyhow;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Buffer size for hashing retained archives.
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// The result of [`validate_cache`]: the state of every install found in an install directory.
+#[derive(Debug, Clone, Default)]
+pub struct CacheReport {
+    /// The installs, ordered by directory name.
+    pub installs: Vec<InstallReport>,
+}
+
+/// The state of a single install within a [`CacheReport`].
+#[derive(Debug, Clone)]
+pub struct InstallReport {
+    /// The install's directory, containing `bin/protoc`.
+    pub path: PathBuf,
+    /// The install's protoc binary, i.e. `bin/protoc`, or `bin/protoc.exe` for a Windows release.
+    pub protoc_path: PathBuf,
+    /// What is wrong with the install; empty if nothing is.
+    pub problems: Vec<CacheProblem>,
+}
+
+/// Something wrong with an install, as found by [`validate_cache`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheProblem {
+    /// The install was never completed (it has no completion marker), e.g. because it was
+    /// interrupted.
+    Incomplete,
+    /// `bin/protoc` is missing.
+    MissingBinary,
+    /// `bin/protoc` isn't executable.
+    NotExecutable,
+    /// The retained release archive doesn't match the digest recorded for it.
+    ArchiveDigestMismatch { expected: String, actual: String },
+    /// `bin/protoc` doesn't match the digest recorded when it was extracted.
+    BinaryDigestMismatch { expected: String, actual: String },
+    /// The completion marker and the manifest disagree on which archive the install came from.
+    MarkerDigestMismatch { marker: String, manifest: String },
+    /// The `include/` tree is recorded as extracted, but some of the well-known types are missing.
+    IncompleteInclude { missing: Vec<String> },
+}
+
+impl fmt::Display for CacheProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CacheProblem::Incomplete => write!(f, "the install was never completed"),
+            CacheProblem::MissingBinary => write!(f, "bin/protoc is missing"),
+            CacheProblem::NotExecutable => write!(f, "bin/protoc is not executable"),
+            CacheProblem::ArchiveDigestMismatch { expected, actual } => write!(
+                f,
+                "the retained archive has digest {actual}, but {expected} was recorded"
+            ),
+            CacheProblem::BinaryDigestMismatch { expected, actual } => write!(
+                f,
+                "bin/protoc has digest {actual}, but {expected} was recorded"
+            ),
+            CacheProblem::MarkerDigestMismatch { marker, manifest } => write!(
+                f,
+                "the completion marker records archive digest {marker}, but the manifest {manifest}"
+            ),
+            CacheProblem::IncompleteInclude { missing } => {
+                write!(f, "the include tree is missing {}", missing.join(", "))
+            }
+        }
+    }
+}
+
+impl CacheReport {
+    /// Returns whether every install is intact.
+    pub fn is_ok(&self) -> bool {
+        self.installs
+            .iter()
+            .all(|install| install.problems.is_empty())
+    }
+
+    /// Fails, with a message listing every problem found, unless every install is intact.
+    pub fn ensure_ok(&self) -> Result<(), Error> {
+        if self.is_ok() {
+            return Ok(());
+        }
+        let problems: Vec<String> = self
+            .installs
+            .iter()
+            .flat_map(|install| {
+                install
+                    .problems
+                    .iter()
+                    .map(|problem| format!("{:?}: {problem}", install.path))
+            })
+            .collect();
+        Err(anyhow!("The protoc cache is broken:\n{}", problems.join("\n")).into())
+    }
+}
+
+/// Checks every install in `install_dir` (the directory given to
+/// [`ProtocFetcher::install_dir`](crate::ProtocFetcher::install_dir)) without any network access
+/// and without running protoc: that it was completed, that `bin/protoc` is present and
+/// executable, and that the retained archive and `include/` tree match what was recorded.
+///
+/// Meant to run at the start of hermetic CI jobs, to fail fast on a bad restored cache rather than
+/// on a confusing error halfway through the build.
+///
+/// ```no_run
+/// # fn main() -> anyhow::Result<()> {
+/// protoc_fetcher::validate_cache("/ci-cache/protoc")?.ensure_ok()?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn validate_cache(install_dir: impl AsRef<Path>) -> Result<CacheReport, Error> {
+    Ok(validate_installs(&install_dir.as_ref().join(INSTALLS_DIR))?)
+}
+
+fn validate_installs(installs_dir: &Path) -> anyhow::Result<CacheReport> {
+    let mut report = CacheReport::default();
+    if !installs_dir.is_dir() {
+        return Ok(report);
+    }
+    let mut entries = fs::read_dir(installs_dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        // Lock files, plugins, the well-known types fetched on their own, the `current` link and
+        // the staging directories of unfinished installs live next to the installs.
+        let name = entry.file_name();
+        let staging = name.to_string_lossy().ends_with(STAGING_SUFFIX);
+        if !entry.file_type()?.is_dir()
+            || name == PLUGINS_DIR
+            || name == WKT_DIR
+            || name == CURRENT_LINK
+            || staging
+        {
+            continue;
+        }
+        let path = entry.path();
+        let platform = installed::parse_install_name(&name.to_string_lossy())
+            .map_or(String::new(), |(_, platform)| platform.to_string());
+        let protoc_path = install::protoc_path(&path, &platform);
+        let problems = validate_install(&path, &protoc_path)?;
+        report.installs.push(InstallReport {
+            path,
+            protoc_path,
+            problems,
+        });
+    }
+    Ok(report)
+}
+
+fn validate_install(protoc_dir: &Path, protoc_path: &Path) -> anyhow::Result<Vec<CacheProblem>> {
+    let mut problems = Vec::new();
+
+    let marker = fs::read_to_string(protoc_dir.join(COMPLETE_MARKER)).ok();
+    if marker.is_none() {
+        problems.push(CacheProblem::Incomplete);
+    }
+
+    match fs::metadata(protoc_path) {
+        Ok(metadata) if !metadata.is_file() => problems.push(CacheProblem::MissingBinary),
+        Ok(metadata) => {
+            if !is_executable(&metadata) {
+                problems.push(CacheProblem::NotExecutable);
+            }
+        }
+        Err(_) => problems.push(CacheProblem::MissingBinary),
+    }
+
+    let manifest = Manifest::read(protoc_dir)?;
+    if let Some(expected) = &manifest.protoc_sha256 {
+        if protoc_path.is_file() {
+            let actual = digest::sha256_file(protoc_path, BUFFER_SIZE)?;
+            if &actual != expected {
+                problems.push(CacheProblem::BinaryDigestMismatch {
+                    expected: expected.clone(),
+                    actual,
+                });
+            }
+        }
+    }
+    if let Some(expected) = &manifest.archive_sha256 {
+        if let Some(marker) = marker.as_deref().map(str::trim) {
+            if marker != expected {
+                problems.push(CacheProblem::MarkerDigestMismatch {
+                    marker: marker.to_string(),
+                    manifest: expected.clone(),
+                });
+            }
+        }
+        if let Some(name) = protoc_dir.file_name() {
+            // The archive is retained under its asset name, in whichever format it was fetched.
+            let formats = [
+                ArchiveFormat::Zip,
+                ArchiveFormat::TarGz,
+                ArchiveFormat::TarZst,
+                ArchiveFormat::TarXz,
+            ];
+            let retained = formats.into_iter().find_map(|format| {
+                let mut archive_name = name.to_owned();
+                archive_name.push(format.extension());
+                Some(protoc_dir.join(archive_name)).filter(|path| path.exists())
+            });
+            if let Some(archive_path) = retained {
+                let actual = digest::sha256_file(&archive_path, BUFFER_SIZE)?;
+                if &actual != expected {
+                    problems.push(CacheProblem::ArchiveDigestMismatch {
+                        expected: expected.clone(),
+                        actual,
+                    });
+                }
+            }
+        }
+    }
+
+    if manifest.include_extracted {
+        let availability =
+            wkt::WktAvailability::check(&protoc_dir.join("include"), wkt::WELL_KNOWN_TYPES);
+        if !availability.is_complete() {
+            problems.push(CacheProblem::IncompleteInclude {
+                missing: availability.missing,
+            });
+        }
+    }
+
+    Ok(problems)
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_metadata: &fs::Metadata) -> bool {
+    true
+}
@@ -0,0 +1,268 @@
+//! Parsing the protoc version given to the fetcher, so that malformed versions fail up front
+//! rather than with a confusing 404 from GitHub.
+
+use crate::error::Error;
+use crate::install;
+use crate::logging;
+use crate::resolve;
+use crate::system;
+use crate::version_req::VersionReq;
+use anyhow::{anyhow, Context};
+use std::cmp::Ordering;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// A protoc release version, e.g. "31.1", "3.20.3" or "32.0-rc1", possibly partial (e.g. "31").
+///
+/// Parsing accepts an accidental leading "v" as in the release tags ("v31.1"), and both spellings
+/// of release candidates ("32.0-rc1" as tagged, and "32.0-rc-1" as in the asset names). It
+/// displays as spelled in the release tag, minus the "v".
+///
+/// Versions are ordered as the releases are, with missing components taken as 0 and release
+/// candidates before their release, so "3.20.3" < "21.0" < "32.0-rc1" < "32.0". A partial
+/// version comes just before the full versions it covers, so "31" < "31.0".
+///
+/// ```
+/// use protoc_fetcher::ProtocVersion;
+///
+/// let version: ProtocVersion = "v32.0-rc-1".parse().unwrap();
+/// assert_eq!((version.major, version.minor, version.rc), (32, Some(0), Some(1)));
+/// assert_eq!(version.to_string(), "32.0-rc1");
+/// assert!("31.x".parse::<ProtocVersion>().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ProtocVersion {
+    /// The major version, e.g. 31 for "31.1", or 3 for the releases before 21.0.
+    pub major: u32,
+    /// The minor version, or `None` for a partial version such as "31".
+    pub minor: Option<u32>,
+    /// The patch version, which only the releases before 21.0 have, e.g. 3 for "3.20.3".
+    pub patch: Option<u32>,
+    /// The number of the release candidate, e.g. 1 for "32.0-rc1", or `None` for a release.
+    pub rc: Option<u32>,
+}
+
+impl FromStr for ProtocVersion {
+    type Err = Error;
+
+    fn from_str(version: &str) -> Result<Self, Error> {
+        let trimmed = version.trim();
+        let unprefixed = trimmed.strip_prefix(['v', 'V']).unwrap_or(trimmed);
+        let (release, rc) = match resolve::release_candidate(unprefixed) {
+            Some((release, rc)) => (release, Some(rc)),
+            None => (unprefixed, None),
+        };
+        let components = resolve::parse_components(release).filter(|c| c.len() <= 3);
+        let Some(components) = components else {
+            return Err(anyhow!(
+                "Invalid protoc version {version:?}: expected a release version such as \"31.1\" \
+                 (\"3.20.3\" for releases before 21.0) or a release candidate such as \
+                 \"32.0-rc1\", as on https://github.com/protocolbuffers/protobuf/releases without \
+                 the \"v\"; or \"latest\", a partial version such as \"31\" for the newest 31.x, \
+                 or a requirement such as \">=27, <32\""
+            )
+            .into());
+        };
+        Ok(ProtocVersion {
+            major: components[0],
+            minor: components.get(1).copied(),
+            patch: components.get(2).copied(),
+            rc,
+        })
+    }
+}
+
+impl ProtocVersion {
+    /// Parses the output of `protoc --version`, e.g. `libprotoc 27.1`, into the version of the
+    /// release. Releases 21.x report themselves as 3.21.x, which is mapped back to 21.x.
+    pub fn from_protoc_output(output: &str) -> Result<Self, Error> {
+        Ok(system::reported_version(output)
+            .parse::<ProtocVersion>()
+            .with_context(|| format!("Unexpected `protoc --version` output {output:?}"))?)
+    }
+
+    fn sort_key(&self) -> (u32, u32, u32, u32) {
+        (
+            self.major,
+            self.minor.unwrap_or(0),
+            self.patch.unwrap_or(0),
+            self.rc.unwrap_or(u32::MAX),
+        )
+    }
+}
+
+impl Ord for ProtocVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Break ties between e.g. "31" and "31.0", which are ordered alike but not equal.
+        self.sort_key()
+            .cmp(&other.sort_key())
+            .then_with(|| (self.minor, self.patch).cmp(&(other.minor, other.patch)))
+    }
+}
+
+impl PartialOrd for ProtocVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for ProtocVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.major)?;
+        for component in [self.minor, self.patch].into_iter().flatten() {
+            write!(f, ".{component}")?;
+        }
+        if let Some(rc) = self.rc {
+            write!(f, "-rc{rc}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs the protoc at `protoc_path` to get its version, and fails with [`Error::TooOld`] unless it
+/// is `min_version` (e.g. "27.0", or a partial version such as "27") or newer. Returns the
+/// version. Useful for builds that accept a system protoc but need a feature of newer releases,
+/// such as edition 2023 (see [`min_protoc_version`](crate::min_protoc_version)).
+///
+/// ```no_run
+/// # fn main() -> anyhow::Result<()> {
+/// let version = protoc_fetcher::ensure_min_version("/usr/bin/protoc", "27.0")?;
+/// println!("Using protoc {version}");
+/// # Ok(())
+/// # }
+/// ```
+pub fn ensure_min_version(
+    protoc_path: impl AsRef<Path>,
+    min_version: &str,
+) -> Result<ProtocVersion, Error> {
+    let path = protoc_path.as_ref();
+    let required: ProtocVersion = min_version.parse()?;
+    let output = install::get_protoc_version(path)
+        .with_context(|| format!("Failed to get the version of {path:?}"))?;
+    let version = ProtocVersion::from_protoc_output(&output)?;
+    if version < required {
+        return Err(Error::TooOld {
+            path: path.to_path_buf(),
+            version: version.to_string(),
+            required: required.to_string(),
+        });
+    }
+    Ok(version)
+}
+
+/// Checks the version given to the fetcher, returning it as it should be fetched: "latest" and
+/// [requirements](VersionReq) as they are, and versions with any leading "v" stripped. Fails with
+/// an error explaining the expected format if the version is malformed, e.g. "31.x".
+pub(crate) fn normalize(version: &str) -> anyhow::Result<String> {
+    if version == "latest" || VersionReq::is_requirement(version) {
+        return Ok(version.to_string());
+    }
+    let normalized = version.parse::<ProtocVersion>()?.to_string();
+    if normalized != version {
+        logging::info!("Fetching protoc {version:?} as {normalized:?}.");
+    }
+    Ok(normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(version: &str) -> ProtocVersion {
+        version.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_releases_and_release_candidates() {
+        assert_eq!(
+            version("31.1"),
+            ProtocVersion {
+                major: 31,
+                minor: Some(1),
+                patch: None,
+                rc: None,
+            }
+        );
+        assert_eq!(
+            version("3.20.3"),
+            ProtocVersion {
+                major: 3,
+                minor: Some(20),
+                patch: Some(3),
+                rc: None,
+            }
+        );
+        assert_eq!(version("32.0-rc1"), version("32.0-rc-1"));
+        assert_eq!(version("32.0-rc-1").rc, Some(1));
+        assert_eq!(
+            version("31"),
+            ProtocVersion {
+                major: 31,
+                minor: None,
+                patch: None,
+                rc: None,
+            }
+        );
+    }
+
+    #[test]
+    fn strips_a_leading_v_and_whitespace() {
+        assert_eq!(version("v31.1"), version("31.1"));
+        assert_eq!(version("V31.1"), version("31.1"));
+        assert_eq!(version(" 31.1\n"), version("31.1"));
+    }
+
+    #[test]
+    fn rejects_malformed_versions() {
+        for malformed in ["", "v", "31.x", "31.1.2.3", "latest", "31..1", "-1", ">=27"] {
+            assert!(
+                malformed.parse::<ProtocVersion>().is_err(),
+                "{malformed:?} parsed"
+            );
+        }
+    }
+
+    #[test]
+    fn displays_as_in_the_release_tag() {
+        for (input, displayed) in [
+            ("v31.1", "31.1"),
+            ("3.20.3", "3.20.3"),
+            ("32.0-rc-2", "32.0-rc2"),
+            ("31", "31"),
+        ] {
+            assert_eq!(version(input).to_string(), displayed);
+        }
+    }
+
+    #[test]
+    fn orders_as_the_releases() {
+        let ordered = [
+            "3.20.3", "21.0", "31", "31.0", "31.1", "32.0-rc1", "32.0-rc2", "32.0",
+        ];
+        for pair in ordered.windows(2) {
+            assert!(version(pair[0]) < version(pair[1]), "{pair:?}");
+        }
+    }
+
+    #[test]
+    fn parses_protoc_version_output() {
+        assert_eq!(
+            ProtocVersion::from_protoc_output("libprotoc 27.1\n").unwrap(),
+            version("27.1")
+        );
+        assert_eq!(
+            ProtocVersion::from_protoc_output("libprotoc 3.21.12").unwrap(),
+            version("21.12")
+        );
+        assert!(ProtocVersion::from_protoc_output("protoc: command not found").is_err());
+    }
+
+    #[test]
+    fn normalizes_the_fetcher_version() {
+        assert_eq!(normalize("v31.1").unwrap(), "31.1");
+        assert_eq!(normalize("latest").unwrap(), "latest");
+        assert_eq!(normalize(">=27, <32").unwrap(), ">=27, <32");
+        assert!(normalize("31.x").is_err());
+    }
+}
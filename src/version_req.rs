@@ -0,0 +1,199 @@
+//! Semver-style requirements on the protoc version, e.g. ">=27, <32" or "~31".
+
+use crate::error::Error;
+use crate::resolve;
+use anyhow::{bail, Context};
+use std::cmp::Ordering;
+
+/// A requirement on the version to fetch: comma-separated comparators, all of which a version
+/// must satisfy. Comparators are as in Cargo, applied to the components of protoc versions:
+///
+/// - `>=27`, `>27`, `<=31.1`, `<32`: compared on the components given, so `>27` means 28 or
+///   newer, and `<=31` any 31.x or older.
+/// - `=31`: any version starting with the components given, e.g. any 31.x.
+/// - `~31.1`: the given version or a newer one with the same first two components (just the
+///   first for `~31`, i.e. any 31.x).
+/// - `^31.1`: the given version or a newer one with the same major version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct VersionReq {
+    comparators: Vec<Comparator>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Comparator {
+    op: Op,
+    components: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Exact,
+    Greater,
+    GreaterEq,
+    Less,
+    LessEq,
+    Tilde,
+    Caret,
+}
+
+/// The operators a comparator can start with, longest first so that `>=` isn't taken for `>`.
+const OPS: [(&str, Op); 7] = [
+    (">=", Op::GreaterEq),
+    ("<=", Op::LessEq),
+    (">", Op::Greater),
+    ("<", Op::Less),
+    ("=", Op::Exact),
+    ("~", Op::Tilde),
+    ("^", Op::Caret),
+];
+
+impl VersionReq {
+    /// Returns whether `spec` is a requirement rather than a version: it starts with an operator,
+    /// or has several comparators.
+    pub fn is_requirement(spec: &str) -> bool {
+        spec.trim_start().starts_with(['>', '<', '=', '~', '^']) || spec.contains(',')
+    }
+
+    /// Parses a requirement such as ">=27, <32".
+    pub fn parse(spec: &str) -> Result<Self, Error> {
+        let comparators = spec
+            .split(',')
+            .map(|comparator| {
+                Comparator::parse(comparator.trim())
+                    .with_context(|| format!("Invalid protoc version requirement {spec:?}"))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(VersionReq { comparators })
+    }
+
+    /// Returns whether `version` satisfies the requirement. Release candidates and anything else
+    /// that isn't made up of numbers only never do.
+    pub fn matches(&self, version: &str) -> bool {
+        let Some(version) = resolve::parse_components(version) else {
+            return false;
+        };
+        self.comparators
+            .iter()
+            .all(|comparator| comparator.matches(&version))
+    }
+}
+
+impl Comparator {
+    fn parse(comparator: &str) -> anyhow::Result<Self> {
+        let (op, version) = OPS
+            .iter()
+            .find_map(|(prefix, op)| Some((*op, comparator.strip_prefix(prefix)?)))
+            .unwrap_or((Op::Exact, comparator));
+        let version = version.trim();
+        let Some(components) = resolve::parse_components(version) else {
+            bail!("{version:?} in {comparator:?} is not a version");
+        };
+        Ok(Comparator { op, components })
+    }
+
+    fn matches(&self, version: &[u32]) -> bool {
+        let given = &self.components;
+        let ordering = compare_prefix(version, given);
+        match self.op {
+            Op::Exact => ordering == Ordering::Equal,
+            Op::Greater => ordering == Ordering::Greater,
+            Op::GreaterEq => ordering != Ordering::Less,
+            Op::Less => ordering == Ordering::Less,
+            Op::LessEq => ordering != Ordering::Greater,
+            Op::Tilde => {
+                let fixed = given.len().min(2);
+                ordering != Ordering::Less && compare_prefix(version, &given[..fixed]).is_eq()
+            }
+            Op::Caret => ordering != Ordering::Less && compare_prefix(version, &given[..1]).is_eq(),
+        }
+    }
+}
+
+/// Compares `version` to `prefix` on the components of `prefix` only, with missing components of
+/// `version` taken as 0: "31.1.2" is equal to "31" and "31.1", and greater than "31.0".
+fn compare_prefix(version: &[u32], prefix: &[u32]) -> Ordering {
+    let padded = version.iter().copied().chain(std::iter::repeat(0));
+    padded
+        .zip(prefix)
+        .map(|(a, b)| a.cmp(b))
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matching(spec: &str, versions: &[&str]) -> Vec<String> {
+        let req = VersionReq::parse(spec).unwrap();
+        versions
+            .iter()
+            .filter(|version| req.matches(version))
+            .map(|version| version.to_string())
+            .collect()
+    }
+
+    const VERSIONS: &[&str] = &[
+        "3.20.3", "21.12", "27.0", "27.3", "31.0", "31.1", "31.1.2", "32.0",
+    ];
+
+    #[test]
+    fn tells_requirements_from_versions() {
+        for spec in [">=27", "<32", "=31", "~31.1", "^31", " >27", "27, 31"] {
+            assert!(VersionReq::is_requirement(spec), "{spec:?}");
+        }
+        for version in ["31.1", "v31.1", "latest", "32.0-rc1"] {
+            assert!(!VersionReq::is_requirement(version), "{version:?}");
+        }
+    }
+
+    #[test]
+    fn compares_on_the_components_given() {
+        assert_eq!(
+            matching(">27", VERSIONS),
+            ["31.0", "31.1", "31.1.2", "32.0"]
+        );
+        assert_eq!(
+            matching(">=27", VERSIONS),
+            ["27.0", "27.3", "31.0", "31.1", "31.1.2", "32.0"]
+        );
+        assert_eq!(matching("<27", VERSIONS), ["3.20.3", "21.12"]);
+        assert_eq!(
+            matching("<=31", VERSIONS),
+            ["3.20.3", "21.12", "27.0", "27.3", "31.0", "31.1", "31.1.2"]
+        );
+        assert_eq!(matching("=31", VERSIONS), ["31.0", "31.1", "31.1.2"]);
+        assert_eq!(matching("31.1", VERSIONS), ["31.1", "31.1.2"]);
+    }
+
+    #[test]
+    fn applies_tilde_and_caret() {
+        assert_eq!(matching("~31.1", VERSIONS), ["31.1", "31.1.2"]);
+        assert_eq!(matching("~31", VERSIONS), ["31.0", "31.1", "31.1.2"]);
+        assert_eq!(matching("^27.1", VERSIONS), ["27.3"]);
+        assert_eq!(matching("^3.20", VERSIONS), ["3.20.3"]);
+    }
+
+    #[test]
+    fn requires_every_comparator() {
+        assert_eq!(
+            matching(">=27, <32", VERSIONS),
+            ["27.0", "27.3", "31.0", "31.1", "31.1.2"]
+        );
+        assert_eq!(matching(" > 27 ,<= 31.0 ", VERSIONS), ["31.0"]);
+    }
+
+    #[test]
+    fn never_matches_release_candidates() {
+        let req = VersionReq::parse(">=31").unwrap();
+        assert!(!req.matches("32.0-rc1"));
+        assert!(!req.matches("latest"));
+    }
+
+    #[test]
+    fn rejects_malformed_requirements() {
+        for spec in ["", ">=", ">=27,", ">=27.x", "=>27", ">=27 <32"] {
+            assert!(VersionReq::parse(spec).is_err(), "{spec:?} parsed");
+        }
+    }
+}
@@ -0,0 +1,233 @@
+//! The well-known types bundled in the `include/` tree of protoc releases.
+
+use crate::error::Error;
+use crate::extract;
+use crate::fetcher::{LockWait, ProtocFetcher};
+use crate::glob::Glob;
+use crate::install::{self, COMPLETE_MARKER, INSTALLS_DIR};
+use crate::lock::{Acquired, InstallLock};
+use crate::logging;
+use crate::manifest::Manifest;
+use crate::progress::Progress;
+use anyhow::{anyhow, bail, Context};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The subdirectory of the installs directory that the `include/` trees fetched on their own go
+/// into, one per version.
+pub(crate) const WKT_DIR: &str = "well-known-types";
+
+/// The well-known type protos every protoc release since 3.0 ships in its `include/` tree,
+/// relative to it.
+///
+/// Newer releases bundle more files than these (e.g. the `*_features.proto` files of editions);
+/// pass those to [`Protoc::check_well_known_types`](crate::Protoc::check_well_known_types) to check
+/// for them.
+pub const WELL_KNOWN_TYPES: &[&str] = &[
+    "google/protobuf/any.proto",
+    "google/protobuf/api.proto",
+    "google/protobuf/compiler/plugin.proto",
+    "google/protobuf/descriptor.proto",
+    "google/protobuf/duration.proto",
+    "google/protobuf/empty.proto",
+    "google/protobuf/field_mask.proto",
+    "google/protobuf/source_context.proto",
+    "google/protobuf/struct.proto",
+    "google/protobuf/timestamp.proto",
+    "google/protobuf/type.proto",
+    "google/protobuf/wrappers.proto",
+];
+
+/// Which of a set of expected protos an install's `include/` tree provides, as returned by
+/// [`Protoc::check_well_known_types`](crate::Protoc::check_well_known_types).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WktAvailability {
+    /// The expected protos that are present (and non-empty), relative to the `include/` tree.
+    pub present: Vec<String>,
+    /// The expected protos that are missing or empty.
+    pub missing: Vec<String>,
+}
+
+impl WktAvailability {
+    /// Checks which of `protos` (relative to `include_dir`) exist and are non-empty.
+    pub(crate) fn check(include_dir: &Path, protos: &[&str]) -> Self {
+        let (present, missing) = protos
+            .iter()
+            .map(|proto| proto.to_string())
+            .partition(|proto| {
+                fs::metadata(include_dir.join(proto)).is_ok_and(|metadata| metadata.len() > 0)
+            });
+        WktAvailability { present, missing }
+    }
+
+    /// Returns whether all of the expected protos are present.
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty()
+    }
+
+    /// Fails, with a message listing the missing protos, unless all of the expected protos are
+    /// present. Meant for codegen to fail early, rather than with import errors from protoc.
+    pub fn require_all(&self) -> Result<(), Error> {
+        if !self.is_complete() {
+            return Err(anyhow!(
+                "The protoc release is missing expected well-known types: {}",
+                self.missing.join(", ")
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// Checks that every one of the [`WELL_KNOWN_TYPES`] exists in `include_dir` and is non-empty.
+///
+/// A partially extracted include tree otherwise only surfaces later, as confusing import errors
+/// from protoc.
+pub(crate) fn verify_include_tree(include_dir: &Path) -> anyhow::Result<()> {
+    let availability = WktAvailability::check(include_dir, WELL_KNOWN_TYPES);
+    if !availability.is_complete() {
+        bail!(
+            "The include directory {include_dir:?} is incomplete; missing or empty: {}",
+            availability.missing.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Installs the `include/` tree of the release fetched by `fetcher` on its own, without protoc,
+/// unless it is already installed, and returns its path. A complete install of the release with
+/// its `include/` tree already extracted is used instead, if there is one.
+pub(crate) fn fetch(fetcher: &ProtocFetcher) -> anyhow::Result<PathBuf> {
+    let install_dir = fetcher.resolve_install_dir()?;
+    let protoc_dir = install::protoc_install_dir(fetcher, &install_dir)?;
+    if fetcher.reuse_cache
+        && protoc_dir.join(COMPLETE_MARKER).exists()
+        && Manifest::read(&protoc_dir).is_ok_and(|manifest| manifest.include_extracted)
+    {
+        logging::info!("Using the include directory of the existing install {protoc_dir:?}.");
+        return Ok(protoc_dir.join("include"));
+    }
+    let wkt_dir = install_dir
+        .join(INSTALLS_DIR)
+        .join(WKT_DIR)
+        .join(&fetcher.version);
+
+    let _lock = match InstallLock::acquire(&wkt_dir, &fetcher.lock_wait, &fetcher.cancellation)? {
+        Acquired::Locked(lock) => lock,
+        Acquired::Contended => match &fetcher.lock_wait {
+            LockWait::Fallback(fallback_dir) => {
+                logging::warn!(
+                    "Well-known types install is locked by another process, using \
+                     {fallback_dir:?}"
+                );
+                let fallback = ProtocFetcher {
+                    install_dir: Some(fallback_dir.clone()),
+                    lock_wait: LockWait::default(),
+                    ..fetcher.clone()
+                };
+                return fetch(&fallback);
+            }
+            LockWait::Wait(timeout) => bail!(Error::Locked {
+                path: wkt_dir,
+                waited: *timeout,
+            }),
+            LockWait::Fail => bail!(Error::Locked {
+                path: wkt_dir,
+                waited: Duration::ZERO,
+            }),
+        },
+    };
+
+    let include_dir = wkt_dir.join("include");
+    if fetcher.reuse_cache && wkt_dir.join(COMPLETE_MARKER).exists() {
+        logging::info!(
+            "Well-known types of v{} are already installed.",
+            fetcher.version
+        );
+        return Ok(include_dir);
+    }
+    if wkt_dir.exists() {
+        fs::remove_dir_all(&wkt_dir)?;
+    }
+
+    logging::info!(
+        "Well-known types of v{} not found, installing...",
+        fetcher.version
+    );
+    let release_name = install::get_protoc_release_name(fetcher)?;
+    let staging_dir = install::staging_dir(&wkt_dir);
+    install::reset_dir(&staging_dir)?;
+    let archive_path = staging_dir.join(install::release_asset_name(fetcher, &release_name));
+    install::obtain_release_archive(fetcher, &archive_path, &release_name)?;
+    let include = Glob::new("include/**");
+    fetcher.report(Progress::Extracting {
+        archive: &archive_path,
+    });
+    extract::extract_archive(
+        &archive_path,
+        &staging_dir,
+        &fetcher.extract_options(),
+        |name| include.matches(name),
+    )?;
+    fs::remove_file(&archive_path)?;
+    verify_include_tree(&staging_dir.join("include"))?;
+    extract::normalize_tree(&staging_dir)?;
+    fs::write(staging_dir.join(COMPLETE_MARKER), "")?;
+    fs::rename(&staging_dir, &wkt_dir)
+        .with_context(|| format!("Failed to move {staging_dir:?} to {wkt_dir:?}"))?;
+
+    logging::info!("Well-known types installed successfully: {include_dir:?}");
+    fetcher.report(Progress::Installed { path: &include_dir });
+    Ok(include_dir)
+}
+
+/// What [`Protoc::export_well_known_types`](crate::Protoc::export_well_known_types) does with
+/// files that already exist in the destination directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExistingFiles {
+    /// Replace them with the release's copy.
+    Overwrite,
+    /// Leave them untouched, e.g. to keep local patches to vendored protos.
+    Skip,
+}
+
+/// Copies every file of the `include_dir` tree into `dest`, keeping the directory structure.
+/// Returns the paths of the files written, relative to `dest`.
+pub(crate) fn export_tree(
+    include_dir: &Path,
+    dest: &Path,
+    existing: ExistingFiles,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let mut written = Vec::new();
+    export_dir(include_dir, dest, Path::new(""), existing, &mut written)?;
+    Ok(written)
+}
+
+fn export_dir(
+    src_root: &Path,
+    dest_root: &Path,
+    relative: &Path,
+    existing: ExistingFiles,
+    written: &mut Vec<PathBuf>,
+) -> anyhow::Result<()> {
+    let dest_dir = dest_root.join(relative);
+    fs::create_dir_all(&dest_dir).with_context(|| format!("Failed to create {dest_dir:?}"))?;
+
+    let mut entries = fs::read_dir(src_root.join(relative))?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let relative = relative.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            export_dir(src_root, dest_root, &relative, existing, written)?;
+            continue;
+        }
+        let dest = dest_root.join(&relative);
+        if existing == ExistingFiles::Skip && dest.exists() {
+            continue;
+        }
+        fs::copy(entry.path(), &dest).with_context(|| format!("Failed to write {dest:?}"))?;
+        written.push(relative);
+    }
+    Ok(())
+}
@@ -0,0 +1,147 @@
+//! Fetches from a [`FixtureServer`], which go through the same download, retry and install code
+//! as fetches from GitHub, without any network.
+//!
+//! The fake releases' protoc is a shell script, which only runs on Unix-like systems.
+#![cfg(unix)]
+
+use protoc_fetcher::test_support::{fixtures, FakeRelease, Fault, FixtureServer, Sandbox};
+use protoc_fetcher::{Error, Protoc, ProtocFetcher};
+use std::path::Path;
+use std::time::Duration;
+
+const VERSION: &str = "21.2";
+
+/// Returns a fixture directory with a fake release of [`VERSION`] in it, and a server serving it.
+fn serve_release() -> (Sandbox, FixtureServer) {
+    let fixtures = Sandbox::new().unwrap();
+    FakeRelease::new(VERSION).write_to(fixtures.path()).unwrap();
+    let server = FixtureServer::start(fixtures.path()).unwrap();
+    (fixtures, server)
+}
+
+/// Returns a fetcher of [`VERSION`] into `sandbox` that downloads from `server` and retries
+/// without waiting.
+fn fetcher(sandbox: &Sandbox, server: &FixtureServer) -> ProtocFetcher {
+    sandbox
+        .fetcher(VERSION)
+        .fixture_server(server)
+        .retry_backoff(Duration::ZERO)
+}
+
+/// Returns the directory of the install `protoc` belongs to.
+fn install_dir(protoc: &Protoc) -> &Path {
+    protoc.path().parent().unwrap().parent().unwrap()
+}
+
+#[test]
+fn installs_the_served_release() {
+    let (_fixtures, server) = serve_release();
+    let sandbox = Sandbox::new().unwrap();
+    let protoc = fetcher(&sandbox, &server).fetch().unwrap();
+
+    assert!(protoc.path().starts_with(sandbox.path()));
+    assert!(protoc.path().is_file());
+    assert!(install_dir(&protoc).join(".complete").is_file());
+    assert_eq!(protoc.version(), VERSION);
+}
+
+#[test]
+fn reuses_a_completed_install() {
+    let (fixtures, server) = serve_release();
+    let sandbox = Sandbox::new().unwrap();
+    let first = fetcher(&sandbox, &server).fetch().unwrap();
+
+    // Without the server, a fetch that needed to download would fail.
+    let fetcher = fetcher(&sandbox, &server);
+    drop(server);
+    drop(fixtures);
+    let second = fetcher.fetch().unwrap();
+    assert_eq!(second.path(), first.path());
+}
+
+#[test]
+fn reinstalls_an_incomplete_install_from_the_retained_archive() {
+    let (fixtures, server) = serve_release();
+    let sandbox = Sandbox::new().unwrap();
+    let first = fetcher(&sandbox, &server).fetch().unwrap();
+    std::fs::remove_file(install_dir(&first).join(".complete")).unwrap();
+    std::fs::remove_file(first.path()).unwrap();
+
+    // An install without its completion marker isn't reused, but the release archive kept for
+    // extracting the `include/` tree later is, so the server isn't needed.
+    let fetcher = fetcher(&sandbox, &server);
+    drop(server);
+    drop(fixtures);
+    let second = fetcher.fetch().unwrap();
+    assert!(second.path().is_file());
+    assert!(install_dir(&second).join(".complete").is_file());
+}
+
+#[test]
+fn retries_server_errors() {
+    let (_fixtures, server) = serve_release();
+    server.inject(Fault::Status(503));
+    server.inject(Fault::Status(429));
+    let sandbox = Sandbox::new().unwrap();
+    let protoc = fetcher(&sandbox, &server).retries(2).fetch().unwrap();
+    assert!(protoc.path().is_file());
+}
+
+#[test]
+fn gives_up_after_the_configured_retries() {
+    let (_fixtures, server) = serve_release();
+    server.inject(Fault::Status(503));
+    server.inject(Fault::Status(503));
+    let sandbox = Sandbox::new().unwrap();
+    let err = fetcher(&sandbox, &server).retries(1).fetch().unwrap_err();
+    assert!(
+        matches!(err, Error::HttpStatus { status: 503, .. }),
+        "{err:?}"
+    );
+}
+
+#[test]
+fn retries_truncated_downloads() {
+    let (_fixtures, server) = serve_release();
+    server.inject(Fault::Truncate(100));
+    let sandbox = Sandbox::new().unwrap();
+    fetcher(&sandbox, &server).retries(0).fetch().unwrap_err();
+
+    server.inject(Fault::Truncate(100));
+    let protoc = fetcher(&sandbox, &server)
+        .retries(1)
+        .sha256(&fixtures::release_archive_sha256(VERSION).unwrap())
+        .fetch()
+        .unwrap();
+    assert!(protoc.path().is_file());
+}
+
+#[test]
+fn downloads_a_corrupt_release_again() {
+    let (_fixtures, server) = serve_release();
+    let sandbox = Sandbox::new().unwrap();
+    // Only once, though.
+    server.inject(Fault::Corrupt);
+    server.inject(Fault::Corrupt);
+    let err = fetcher(&sandbox, &server).fetch().unwrap_err();
+    assert!(matches!(err, Error::Extraction { .. }), "{err:?}");
+
+    server.inject(Fault::Corrupt);
+    let protoc = fetcher(&sandbox, &server).fetch().unwrap();
+    assert!(protoc.path().is_file());
+}
+
+#[test]
+fn rejects_a_corrupt_release_with_a_digest() {
+    let (_fixtures, server) = serve_release();
+    server.inject(Fault::Corrupt);
+    let sandbox = Sandbox::new().unwrap();
+    let fetcher =
+        fetcher(&sandbox, &server).sha256(&fixtures::release_archive_sha256(VERSION).unwrap());
+    let err = fetcher.fetch().unwrap_err();
+    assert!(matches!(err, Error::Verification { .. }), "{err:?}");
+
+    // Nothing of the corrupt download is left to get in the way of the next fetch.
+    let protoc = fetcher.fetch().unwrap();
+    assert!(protoc.path().is_file());
+}